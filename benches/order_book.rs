@@ -0,0 +1,265 @@
+//! Replays a level2 session against [OrderBook] and against the sorted `Vec` storage it used
+//! before. By default the synthetic session in `benches/data` is replayed, set
+//! `LEVEL2_RECORDING` to a recording of the live feed written by the `record_level2` example to
+//! replay that instead.
+use std::env;
+use std::fs;
+use std::mem;
+use std::ops::DerefMut;
+use std::sync::Arc;
+
+use criterion::{
+    criterion_group,
+    criterion_main,
+    BatchSize,
+    Criterion,
+};
+use log::error;
+use tokio::runtime::Runtime;
+use tokio::sync::{
+    Mutex,
+    MutexGuard,
+};
+use tokio::time::Instant;
+
+use coinbase_pro::datastructs::orders::Side;
+use coinbase_pro::datastructs::websocket::{
+    Level2Change,
+    Level2Snapshot,
+    Level2Update,
+    WebsocketMessage,
+};
+use coinbase_pro::order_book::{
+    OrderBook,
+    OrderBookEntry,
+};
+
+/// Synthetic, not recorded: a snapshot of 2000 levels a side one cent apart with random sizes and
+/// 1000 random updates, in the JSON lines `record_level2` writes.
+const SYNTHETIC_SESSION: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/benches/data/level2_synthetic.jsonl"
+);
+
+struct Session {
+    snapshot: Level2Snapshot,
+    updates: Vec<Level2Update>,
+}
+
+fn load_recording(path: &str) -> Session {
+    let recording = fs::read_to_string(path).expect("failed to read the level2 recording");
+    let mut snapshot = None;
+    let mut updates = Vec::new();
+
+    for line in recording.lines().filter(|line| !line.is_empty()) {
+        match serde_json::from_str::<WebsocketMessage>(line) {
+            Ok(WebsocketMessage::Snapshot(recorded)) if snapshot.is_none() => {
+                snapshot = Some(recorded)
+            }
+            Ok(WebsocketMessage::L2Update(update)) if snapshot.is_some() => updates.push(update),
+            _ => {}
+        }
+    }
+
+    Session {
+        snapshot: snapshot.expect("the recording has no level2 snapshot"),
+        updates,
+    }
+}
+
+/// The sorted `Vec` storage with linear index searches that [OrderBook] replaced, copied from
+/// the book before the change. Only field accesses the crate keeps private go through the public
+/// [OrderBookEntry] accessors and its price ordering instead, the rest is left as it was.
+#[derive(Debug, Clone)]
+struct VecOrderBook {
+    // Bids ordered least to greatest
+    bids: Arc<Mutex<Vec<OrderBookEntry>>>,
+    // Asks ordered greatest to least
+    asks: Arc<Mutex<Vec<OrderBookEntry>>>,
+
+    updated: Arc<Mutex<Instant>>,
+}
+
+#[allow(clippy::map_flatten)]
+impl From<Level2Snapshot> for VecOrderBook {
+    fn from(snap: Level2Snapshot) -> Self {
+        let mut bids = snap
+            .bids
+            .into_iter()
+            .map(|q| q.try_into())
+            .flatten()
+            .collect::<Vec<OrderBookEntry>>();
+        let mut asks = snap
+            .asks
+            .into_iter()
+            .map(|q| q.try_into())
+            .flatten()
+            .collect::<Vec<OrderBookEntry>>();
+
+        bids.sort();
+        asks.sort();
+
+        asks.reverse();
+
+        Self {
+            bids: Arc::new(Mutex::new(bids)),
+            asks: Arc::new(Mutex::new(asks)),
+            updated: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+}
+
+#[allow(
+    clippy::needless_borrow,
+    clippy::needless_return,
+    clippy::swap_with_temporary
+)]
+impl VecOrderBook {
+    async fn apply_change_l2_changes(&mut self, changes: Vec<Level2Change>) {
+        let ask_lock = self.asks.clone();
+        let bid_lock = self.bids.clone();
+
+        let b = ask_lock.lock().await;
+        let a = bid_lock.lock().await;
+
+        let mut locks = (a, b);
+
+        for change in changes {
+            let entry = match OrderBookEntry::try_from((change.price, change.size)) {
+                Ok(entry) => entry,
+                Err(_) => {
+                    error!("Skipping level2 change with invalid price or size: {:?}", change);
+                    continue;
+                }
+            };
+
+            match change.side.as_str() {
+                "buy" => {
+                    self.apply_change_with_lock(Side::BUY, entry, &mut locks.0)
+                        .await;
+                }
+                "sell" => {
+                    self.apply_change_with_lock(Side::SELL, entry, &mut locks.1)
+                        .await;
+                }
+                _ => {
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn apply_change_with_lock(
+        &mut self,
+        side: Side,
+        entry: OrderBookEntry,
+        lock: &mut MutexGuard<'_, Vec<OrderBookEntry>>,
+    ) {
+        let idx = match side {
+            Side::BUY => Self::find_bid_index(&lock, &entry),
+            Side::SELL => Self::find_ask_index(&lock, &entry),
+        };
+
+        if idx == lock.len() {
+            lock.insert(idx, entry);
+            mem::swap(self.updated.lock().await.deref_mut(), &mut Instant::now());
+            return;
+        }
+
+        if entry.size() == 0.0 {
+            lock.remove(idx);
+            return;
+        }
+
+        if lock[idx] == entry {
+            if entry.size() == 0f64 {
+                lock.remove(idx);
+            } else {
+                lock[idx] = entry;
+            }
+
+            mem::swap(self.updated.lock().await.deref_mut(), &mut Instant::now());
+            return;
+        }
+
+        if lock[idx] != entry && entry.size() != 0f64 {
+            lock.insert(idx, entry);
+            mem::swap(self.updated.lock().await.deref_mut(), &mut Instant::now());
+            return;
+        }
+    }
+
+    fn find_bid_index(vec: &MutexGuard<Vec<OrderBookEntry>>, entry: &OrderBookEntry) -> usize {
+        for (index, bid) in vec.iter().enumerate().rev() {
+            if bid == entry {
+                return index;
+            } else if bid < entry {
+                return index + 1;
+            }
+        }
+
+        return 0;
+    }
+
+    fn find_ask_index(vec: &MutexGuard<Vec<OrderBookEntry>>, entry: &OrderBookEntry) -> usize {
+        for (index, ask) in vec.iter().enumerate().rev() {
+            if ask == entry {
+                return index;
+            } else if ask > entry {
+                return index + 1;
+            }
+        }
+
+        return 0;
+    }
+}
+
+fn replay_level2(c: &mut Criterion) {
+    let path = env::var("LEVEL2_RECORDING").unwrap_or(SYNTHETIC_SESSION.to_string());
+    let session = load_recording(&path);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group("level2_replay");
+    group.sample_size(10);
+
+    group.bench_function("vec", |b| {
+        b.iter_batched(
+            || VecOrderBook::from(session.snapshot.clone()),
+            |book| replay(&runtime, &session, |changes| {
+                let mut book = book.clone();
+                async move { book.apply_change_l2_changes(changes).await }
+            }),
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.bench_function("btree_map", |b| {
+        b.iter_batched(
+            || OrderBook::from(session.snapshot.clone()),
+            |book| replay(&runtime, &session, |changes| {
+                let mut book = book.clone();
+                async move { book.apply_change_l2_changes(changes).await }
+            }),
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.finish();
+}
+
+fn replay<F, Fut>(runtime: &Runtime, session: &Session, mut apply: F)
+where
+    F: FnMut(Vec<Level2Change>) -> Fut,
+    Fut: std::future::Future<Output = ()>,
+{
+    runtime.block_on(async {
+        for update in &session.updates {
+            apply(update.changes.clone()).await;
+        }
+    })
+}
+
+criterion_group!(benches, replay_level2);
+criterion_main!(benches);
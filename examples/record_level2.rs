@@ -0,0 +1,61 @@
+//! Records the level2 feed of a product as JSON lines, the snapshot first and then every update.
+//! The recording can be replayed by the order book benchmark:
+//!
+//! ```text
+//! cargo run --example record_level2 -- ETH-USD 100000 > level2.jsonl
+//! LEVEL2_RECORDING=level2.jsonl cargo bench --bench order_book
+//! ```
+//!
+//! Without `LEVEL2_RECORDING` the benchmark replays the synthetic session in
+//! `benches/data/level2_synthetic.jsonl`.
+use std::env;
+
+use futures::StreamExt;
+
+use coinbase_pro::api::{
+    CBProAPI,
+    SubscriptionBuilder,
+};
+use coinbase_pro::datastructs::websocket::WebsocketMessage;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let mut args = env::args().skip(1);
+    let product_id = args.next().unwrap_or_else(|| "ETH-USD".to_string());
+    let updates = args
+        .next()
+        .map(|count| count.parse::<usize>().expect("update count must be a number"))
+        .unwrap_or(10_000);
+
+    let api = CBProAPI::default();
+    api.subscribe_to_websocket(
+        SubscriptionBuilder::new()
+            .subscribe_to_snapshot(product_id)
+            .build(),
+    )
+    .await
+    .expect("failed to subscribe to level2");
+
+    let mut recorded = 0;
+    let mut messages = Box::pin(api.websocket_stream());
+    while let Some(message) = messages.next().await {
+        let message = match message {
+            Ok(message) => message,
+            Err(err) => {
+                eprintln!("{}", err);
+                continue;
+            }
+        };
+
+        match message {
+            WebsocketMessage::Snapshot(_) => {}
+            WebsocketMessage::L2Update(_) => recorded += 1,
+            _ => continue,
+        }
+
+        println!("{}", serde_json::to_string(&message).unwrap());
+        if recorded == updates {
+            break;
+        }
+    }
+}
@@ -1,6 +1,7 @@
 #[cfg(backtrace)]
 use std::backtrace::Backtrace;
 use std::borrow::BorrowMut;
+use std::cmp::min;
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::future::Future;
@@ -10,12 +11,18 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use futures::{
+    stream,
+    Stream,
+    StreamExt,
+};
 use log::{
     debug,
     error,
-    trace,
 };
 use reqwest::Url;
+use serde::de::DeserializeOwned;
 use serde::{
     Deserialize,
     Serialize,
@@ -23,18 +30,19 @@ use serde::{
 use tokio::io::{
     AsyncReadExt,
     AsyncWriteExt,
+    ReadHalf,
+    WriteHalf,
 };
 use tokio::net::TcpStream;
+use tokio::spawn;
 use tokio::sync::{
+    oneshot,
     Mutex,
     MutexGuard,
+    Notify,
 };
 use tokio::task::JoinHandle;
 use tokio::time::Instant;
-use tokio::{
-    join,
-    spawn,
-};
 use tokio_native_tls::native_tls::TlsConnector as NativeTlsConnector;
 use tokio_native_tls::{
     TlsConnector,
@@ -61,11 +69,22 @@ use crate::datastructs::orders::{
     Order,
 };
 use crate::datastructs::products::{
+    Candle,
     Currency,
+    Level3ProductBook,
     Product,
     ProductBook,
+    ProductStats,
+    ProductTicker,
+    Trade,
+};
+use crate::datastructs::time::ServerTime;
+use crate::datastructs::websocket::{
+    Channel,
+    SubscribeRequest,
+    UnsubscribeRequest,
+    WebsocketMessage,
 };
-use crate::datastructs::websocket::WebsocketMessage;
 use crate::errors::WebsocketError::{
     NoSocketAddressError,
     NoWebsocketConnectionError,
@@ -79,13 +98,27 @@ use crate::errors::{
     SerdeJSONParseError,
     WebsocketError,
 };
-use crate::order_book::OrderBook;
+use crate::dispatcher::{
+    DispatchReceiver,
+    MessageDispatcher,
+};
+use crate::l3_order_book::{
+    L3OrderBook,
+    LiveL3OrderBook,
+};
+use crate::order_book::{
+    LiveOrderBook,
+    OrderBook,
+};
 use crate::requests::{
     CBRequestBuilder,
+    Pagination,
     RequestMethod,
+    ServerClock,
 };
 use crate::websocket_lite::{
     AsyncIO,
+    ControlFrame,
     WebsocketStreamConnector,
 };
 
@@ -141,7 +174,15 @@ pub struct SubscriptionBuilder {
     ticker_products: Vec<String>,
     l2_products: Vec<String>,
     full_channel_products: Vec<String>,
+    user_products: Vec<String>,
+    matches_products: Vec<String>,
+    l2_batch_products: Vec<String>,
+    ticker_batch_products: Vec<String>,
+    auction_products: Vec<String>,
+    rfq_products: Vec<String>,
+    rfq_channel: bool,
     status_channel: bool,
+    credentials: Option<APIKeyData>,
 }
 
 impl SubscriptionBuilder {
@@ -151,7 +192,15 @@ impl SubscriptionBuilder {
             ticker_products: vec![],
             l2_products: vec![],
             full_channel_products: vec![],
+            user_products: vec![],
+            matches_products: vec![],
+            l2_batch_products: vec![],
+            ticker_batch_products: vec![],
+            auction_products: vec![],
+            rfq_products: vec![],
+            rfq_channel: false,
             status_channel: false,
+            credentials: None,
         }
     }
 
@@ -219,9 +268,98 @@ impl SubscriptionBuilder {
         self
     }
 
+    /// Include the authenticated user's own order messages for specified product.<br>
+    /// Requires [Self::sign()].
+    /// ## Example
+    ///```
+    /// use coinbase_pro::api::{APIKeyData, SubscriptionBuilder};
+    /// let credentials = APIKeyData {
+    ///     key: "key".to_string(),
+    ///     secret: "c2VjcmV0".to_string(),
+    ///     passphrase: "passphrase".to_string(),
+    /// };
+    /// let subscription = SubscriptionBuilder::new()
+    ///    .subscribe_to_user("ETH-USD".to_string())
+    ///    .sign(credentials)
+    ///    .build();
+    ///```
+    pub fn subscribe_to_user(mut self, product: String) -> Self {
+        self.user_products.push(product);
+        self
+    }
+
+    pub fn subscribe_to_user_vec(mut self, product: &mut Vec<String>) -> Self {
+        self.user_products.append(product);
+        self
+    }
+
+    /// Include only the match messages of the full channel for specified product.
+    pub fn subscribe_to_matches(mut self, product: String) -> Self {
+        self.matches_products.push(product);
+        self
+    }
+
+    pub fn subscribe_to_matches_vec(mut self, product: &mut Vec<String>) -> Self {
+        self.matches_products.append(product);
+        self
+    }
+
+    /// Include level2 snapshots and updates batched every 50 milliseconds for specified product.
+    pub fn subscribe_to_level2_batch(mut self, product: String) -> Self {
+        self.l2_batch_products.push(product);
+        self
+    }
+
+    pub fn subscribe_to_level2_batch_vec(mut self, product: &mut Vec<String>) -> Self {
+        self.l2_batch_products.append(product);
+        self
+    }
+
+    /// Include ticker messages batched every 5 seconds for specified product.
+    pub fn subscribe_to_ticker_batch(mut self, product: String) -> Self {
+        self.ticker_batch_products.push(product);
+        self
+    }
+
+    pub fn subscribe_to_ticker_batch_vec(mut self, product: &mut Vec<String>) -> Self {
+        self.ticker_batch_products.append(product);
+        self
+    }
+
+    /// Include auction messages for specified product while it is in auction mode.
+    pub fn subscribe_to_auctionfeed(mut self, product: String) -> Self {
+        self.auction_products.push(product);
+        self
+    }
+
+    pub fn subscribe_to_auctionfeed_vec(mut self, product: &mut Vec<String>) -> Self {
+        self.auction_products.append(product);
+        self
+    }
+
+    /// Include block trade matches for specified product.
+    pub fn subscribe_to_rfq_matches(mut self, product: String) -> Self {
+        self.rfq_channel = true;
+        self.rfq_products.push(product);
+        self
+    }
+
+    /// Include block trade matches for every product.
+    pub fn subscribe_to_all_rfq_matches(mut self) -> Self {
+        self.rfq_channel = true;
+        self
+    }
+
+    /// Sign the subscription with the given API key.
+    /// The signature is renewed every time the subscription is sent.
+    pub fn sign(mut self, credentials: APIKeyData) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
     /// Finalize request and return the struct used to serialize into a websocket payload consuming the builder in the process.<br>
     pub fn build(self) -> crate::datastructs::websocket::SubscribeRequest {
-        let mut request = crate::datastructs::websocket::SubscribeRequest { channels: vec![] };
+        let mut request = crate::datastructs::websocket::SubscribeRequest::new(vec![]);
 
         if self.heartbeat_products.len() > 0 {
             request
@@ -263,6 +401,66 @@ impl SubscriptionBuilder {
                 ));
         }
 
+        if !self.user_products.is_empty() {
+            request
+                .channels
+                .push(crate::datastructs::websocket::Channel::User(
+                    crate::datastructs::websocket::UserChannel {
+                        product_ids: self.user_products,
+                    },
+                ));
+        }
+
+        if !self.matches_products.is_empty() {
+            request
+                .channels
+                .push(crate::datastructs::websocket::Channel::Matches(
+                    crate::datastructs::websocket::MatchesChannel {
+                        product_ids: self.matches_products,
+                    },
+                ));
+        }
+
+        if !self.l2_batch_products.is_empty() {
+            request
+                .channels
+                .push(crate::datastructs::websocket::Channel::Level2Batch(
+                    crate::datastructs::websocket::Level2BatchChannel {
+                        product_ids: self.l2_batch_products,
+                    },
+                ));
+        }
+
+        if !self.ticker_batch_products.is_empty() {
+            request
+                .channels
+                .push(crate::datastructs::websocket::Channel::TickerBatch(
+                    crate::datastructs::websocket::TickerBatchChannel {
+                        product_ids: self.ticker_batch_products,
+                    },
+                ));
+        }
+
+        if !self.auction_products.is_empty() {
+            request
+                .channels
+                .push(crate::datastructs::websocket::Channel::Auctionfeed(
+                    crate::datastructs::websocket::AuctionfeedChannel {
+                        product_ids: self.auction_products,
+                    },
+                ));
+        }
+
+        if self.rfq_channel {
+            request
+                .channels
+                .push(crate::datastructs::websocket::Channel::RfqMatches(
+                    crate::datastructs::websocket::RfqMatchesChannel {
+                        product_ids: self.rfq_products,
+                    },
+                ));
+        }
+
         if self.status_channel {
             request
                 .channels
@@ -271,8 +469,140 @@ impl SubscriptionBuilder {
                 ));
         }
 
-        request
+        match self.credentials {
+            None => request,
+            Some(credentials) => request.sign(credentials),
+        }
+    }
+}
+
+/// Use an unsubscribe builder to stop receiving channels from a coinbase websocket.<br>
+/// Takes the same channels as [SubscriptionBuilder], [Self::build()] returns the final unsubscribe request.<br>
+///
+/// ## Example
+///```
+/// use coinbase_pro::api::UnsubscribeBuilder;
+/// let unsubscribe = UnsubscribeBuilder::new()
+///    .unsubscribe_from_ticker("ETH-USD".to_string())
+///    .unsubscribe_from_status()
+///    .build();
+///```
+pub struct UnsubscribeBuilder {
+    channels: SubscriptionBuilder,
+}
+
+impl UnsubscribeBuilder {
+    pub fn new() -> Self {
+        UnsubscribeBuilder {
+            channels: SubscriptionBuilder::new(),
+        }
+    }
+
+    pub fn unsubscribe_from_heartbeat(self, product: String) -> Self {
+        UnsubscribeBuilder {
+            channels: self.channels.subscribe_to_heartbeat(product),
+        }
+    }
+
+    pub fn unsubscribe_from_status(self) -> Self {
+        UnsubscribeBuilder {
+            channels: self.channels.subscribe_to_status(),
+        }
+    }
+
+    pub fn unsubscribe_from_ticker(self, product: String) -> Self {
+        UnsubscribeBuilder {
+            channels: self.channels.subscribe_to_ticker(product),
+        }
+    }
+
+    pub fn unsubscribe_from_snapshot(self, product: String) -> Self {
+        UnsubscribeBuilder {
+            channels: self.channels.subscribe_to_snapshot(product),
+        }
+    }
+
+    pub fn unsubscribe_from_full(self, product: String) -> Self {
+        UnsubscribeBuilder {
+            channels: self.channels.subscribe_to_full(product),
+        }
+    }
+
+    pub fn unsubscribe_from_user(self, product: String) -> Self {
+        UnsubscribeBuilder {
+            channels: self.channels.subscribe_to_user(product),
+        }
+    }
+
+    pub fn unsubscribe_from_matches(self, product: String) -> Self {
+        UnsubscribeBuilder {
+            channels: self.channels.subscribe_to_matches(product),
+        }
+    }
+
+    pub fn unsubscribe_from_level2_batch(self, product: String) -> Self {
+        UnsubscribeBuilder {
+            channels: self.channels.subscribe_to_level2_batch(product),
+        }
+    }
+
+    pub fn unsubscribe_from_ticker_batch(self, product: String) -> Self {
+        UnsubscribeBuilder {
+            channels: self.channels.subscribe_to_ticker_batch(product),
+        }
+    }
+
+    pub fn unsubscribe_from_auctionfeed(self, product: String) -> Self {
+        UnsubscribeBuilder {
+            channels: self.channels.subscribe_to_auctionfeed(product),
+        }
+    }
+
+    pub fn unsubscribe_from_rfq_matches(self, product: String) -> Self {
+        UnsubscribeBuilder {
+            channels: self.channels.subscribe_to_rfq_matches(product),
+        }
+    }
+
+    /// Finalize request and return the struct used to serialize into a websocket payload consuming the builder in the process.<br>
+    pub fn build(self) -> UnsubscribeRequest {
+        UnsubscribeRequest {
+            channels: self.channels.build().channels,
+        }
+    }
+}
+
+impl Default for UnsubscribeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Requested channels missing from the confirmed set, as `channel:product` or the bare channel
+/// name for channels that are not per product.
+fn missing_channels(requested: &[Channel], confirmed: &[Channel]) -> Vec<String> {
+    let mut missing = Vec::new();
+
+    for channel in requested {
+        let confirmed_channel = confirmed
+            .iter()
+            .find(|confirmed| confirmed.name() == channel.name());
+
+        match confirmed_channel {
+            None if channel.product_ids().is_empty() => missing.push(channel.name().to_string()),
+            None => channel
+                .product_ids()
+                .iter()
+                .for_each(|product| missing.push(format!("{}:{}", channel.name(), product))),
+            Some(confirmed_channel) => channel
+                .product_ids()
+                .iter()
+                .filter(|product| !confirmed_channel.product_ids().contains(product))
+                .for_each(|product| missing.push(format!("{}:{}", channel.name(), product))),
+        }
     }
+
+    missing
 }
 
 #[derive(Clone)]
@@ -288,57 +618,335 @@ impl Level {
     }
 }
 
+/// Candle widths accepted by the [Get Product Candles](https://docs.cloud.coinbase.com/exchange/reference/exchangerestapi_getproductcandles) endpoint.
+/// Coinbase rejects any other granularity so raw second counts must be converted with [Granularity::try_from].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Granularity {
+    OneMinute = 60,
+    FiveMinutes = 300,
+    FifteenMinutes = 900,
+    OneHour = 3600,
+    SixHours = 21600,
+    OneDay = 86400,
+}
+
+impl Granularity {
+    pub fn as_seconds(&self) -> i64 {
+        *self as i64
+    }
+
+    pub fn as_string(&self) -> String {
+        self.as_seconds().to_string()
+    }
+}
+
+impl TryFrom<u64> for Granularity {
+    type Error = Error;
+
+    fn try_from(seconds: u64) -> Result<Self, Self::Error> {
+        match seconds {
+            60 => Ok(Granularity::OneMinute),
+            300 => Ok(Granularity::FiveMinutes),
+            900 => Ok(Granularity::FifteenMinutes),
+            3600 => Ok(Granularity::OneHour),
+            21600 => Ok(Granularity::SixHours),
+            86400 => Ok(Granularity::OneDay),
+            _ => Err(Error::InvalidGranularity(seconds)),
+        }
+    }
+}
+
+/// Coinbase returns at most this many candles from a single candles request.
+const MAX_CANDLES_PER_REQUEST: i32 = 300;
+const CANDLE_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+/// Wait before fetching a level 3 book again after it could not seed the order book
+const L3_RESEED_DELAY: Duration = Duration::from_secs(1);
+/// Wait for the level2 snapshot seeding a new order book before giving up
+const L2_SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Wait for a level 3 book to seed a new order book before giving up, covers every reseed
+const L3_SEED_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Flatten a stream of pages into a stream of individual items.
+/// An error ends the page stream so it is forwarded as the final item.
+fn flatten_pages<O>(
+    pages: impl Stream<Item = Result<Vec<O>, Error>>,
+) -> impl Stream<Item = Result<O, Error>> {
+    pages.flat_map(|page| {
+        let items: Vec<Result<O, Error>> = match page {
+            Ok(items) => items.into_iter().map(Ok).collect(),
+            Err(err) => vec![Err(err)],
+        };
+
+        stream::iter(items)
+    })
+}
+
+/// Sustained rate and burst size of a single rate limit bucket.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub rate_per_second: f32,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub fn new(rate_per_second: f32, burst: u32) -> Self {
+        RateLimit {
+            rate_per_second,
+            burst,
+        }
+    }
+}
+
+/// Rate limits for each class of REST traffic.
+///
+/// Unsigned requests are charged against `public`, signed requests against `private` and
+/// requests placing or cancelling orders against `orders`.
+/// The defaults follow coinbase's published limits.
+/// CBPro API reference: [Rate Limits](https://docs.cloud.coinbase.com/exchange/docs/rest-rate-limits).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimits {
+    pub public: RateLimit,
+    pub private: RateLimit,
+    pub orders: RateLimit,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            public: RateLimit::new(10.0, 15),
+            private: RateLimit::new(15.0, 30),
+            orders: RateLimit::new(15.0, 30),
+        }
+    }
+}
+
+/// One token bucket per class of traffic in [RateLimits].
+#[derive(Clone)]
+pub(crate) struct RateLimiter {
+    public: RateLimitedPool,
+    private: RateLimitedPool,
+    orders: RateLimitedPool,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        RateLimiter {
+            public: RateLimitedPool::new(limits.public),
+            private: RateLimitedPool::new(limits.private),
+            orders: RateLimitedPool::new(limits.orders),
+        }
+    }
+
+    /// The bucket a request is charged against.
+    pub fn pool_for(&self, request: &CBRequestBuilder) -> &RateLimitedPool {
+        if request.is_order_request() {
+            &self.orders
+        } else if request.is_signed() {
+            &self.private
+        } else {
+            &self.public
+        }
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// A token bucket holding up to `burst` tokens which refills at `rate_per_second`.
+///
+/// Every scheduled task takes a token before it runs, waiting for the bucket to refill when it
+/// is empty. Waiting tasks are served in the order they were scheduled.
 #[derive(Clone)]
 pub(crate) struct RateLimitedPool {
-    last_req: Arc<Mutex<Instant>>,
-    rate_limit: Arc<f32>,
+    bucket: Arc<Mutex<TokenBucket>>,
+    limit: RateLimit,
 }
 
 impl RateLimitedPool {
-    pub fn new(rate_per_second: f32) -> Self {
+    pub fn new(limit: RateLimit) -> Self {
         RateLimitedPool {
-            last_req: Arc::new(Mutex::new(Instant::now())),
-            rate_limit: Arc::new(rate_per_second),
+            bucket: Arc::new(Mutex::new(TokenBucket {
+                tokens: limit.burst.max(1) as f64,
+                last_refill: Instant::now(),
+            })),
+            limit,
         }
     }
 
-    fn rate_limit_time_delta_micros(self: &Self) -> u64 {
-        (1000000.0 / self.rate_limit.deref()) as u64
+    /// The limit the pool was created with, only needed to check bucket selection in tests.
+    #[cfg(all(test, feature = "mock"))]
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    pub async fn schedule_rate_limited_task<O>(&self, fut: impl Future<Output = O>) -> O {
+        self.take_token().await;
+        fut.await
     }
 
-    pub async fn schedule_rate_limited_task<O>(self: &Self, fut: impl Future<Output = O>) -> O {
-        self.wait_in_queue(fut).await
+    async fn take_token(&self) {
+        let rate = self.limit.rate_per_second as f64;
+        let burst = self.limit.burst.max(1) as f64;
+
+        // The lock is held while waiting for a refill. tokio's Mutex is fair so other tasks queue
+        // up behind this one instead of racing for the next token.
+        let mut bucket = self.bucket.lock().await;
+
+        loop {
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+            bucket.last_refill = now;
+
+            if bucket.tokens >= 1.0 {
+                bucket.tokens -= 1.0;
+                return;
+            }
+
+            tokio::time::sleep(Duration::from_secs_f64((1.0 - bucket.tokens) / rate)).await;
+        }
     }
 
-    async fn wait_in_queue<O>(self: &Self, fut: impl Future<Output = O>) -> O {
-        let mut fut_instant = tokio::time::sleep_until(Instant::now());
-        let mut fut_last_req = self.last_req.deref().lock();
+    /// Schedule a task through the pool, rebuilding and rescheduling it whenever it fails with an
+    /// error `policy` considers retryable.
+    /// Every attempt waits its turn in the pool so retries still respect the rate limit.
+    /// Tasks that are not `idempotent` are only ever attempted once.
+    pub async fn schedule_retryable_task<O, F, Fut>(
+        &self,
+        policy: &RetryPolicy,
+        idempotent: bool,
+        mut task: F,
+    ) -> Result<O, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<O, Error>>,
+    {
+        let mut attempt = 1;
 
         loop {
-            // wait turn and wait for lock. If we get a lock we still wait for the time and just take this req as the next one to execute.
-            let (_, mut last_req) = join!(fut_instant, fut_last_req);
-
-            let next_available_instant: Instant = *last_req.deref()
-                + tokio::time::Duration::from_micros(self.rate_limit_time_delta_micros());
-
-            // if we aren't ready to execute
-            if Instant::now() < next_available_instant {
-                // wait and keep looping
-                fut_instant = tokio::time::sleep_until(next_available_instant);
-                fut_last_req = self.last_req.deref().lock();
-            } else {
-                // set last req time and return.
-                *last_req = Instant::now();
-                break;
+            match self.schedule_rate_limited_task(task()).await {
+                Err(err)
+                    if idempotent && attempt < policy.max_attempts && policy.is_retryable(&err) =>
+                {
+                    let delay = policy.delay(attempt, &err);
+                    debug!("Retrying request in {:?} after error: {}", delay, err);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
             }
         }
+    }
+}
 
-        fut.await
+/// Kinds of request failures a [RetryPolicy] may retry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetryableError {
+    /// [Error::ReqwestConnectionError]
+    Connection,
+    /// [Error::ReqwestTimeoutError]
+    Timeout,
+    /// [Error::RateLimited]
+    RateLimited,
+    /// [Error::ServerUnavailable]
+    ServerUnavailable,
+}
+
+impl RetryableError {
+    fn matches(&self, error: &Error) -> bool {
+        matches!(
+            (self, error),
+            (RetryableError::Connection, Error::ReqwestConnectionError)
+                | (RetryableError::Timeout, Error::ReqwestTimeoutError)
+                | (RetryableError::RateLimited, Error::RateLimited(_))
+                | (
+                    RetryableError::ServerUnavailable,
+                    Error::ServerUnavailable(_)
+                )
+        )
+    }
+}
+
+/// Controls how failed requests are retried.
+///
+/// Retries wait `base_delay * 2^(retry - 1)` capped at `max_delay`. The wait is then shortened by
+/// a random fraction of up to `jitter` (0.0 - 1.0) so that clients do not retry in lockstep.
+/// A rate limited response with a `retry-after` header never waits less than the server asked.
+///
+/// Only idempotent requests are retried. POST requests are only retried when creating an order
+/// that carries a `client_oid`.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Total number of attempts including the first one.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: f64,
+    pub retryable: Vec<RetryableError>,
+}
+
+/// Three attempts retrying connection errors, timeouts, rate limits and 5xx responses.
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(5),
+            jitter: 0.5,
+            retryable: vec![
+                RetryableError::Connection,
+                RetryableError::Timeout,
+                RetryableError::RateLimited,
+                RetryableError::ServerUnavailable,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_retryable(&self, error: &Error) -> bool {
+        self.retryable.iter().any(|kind| kind.matches(error))
+    }
+
+    /// Exponential backoff with jitter before the given retry, starting at 1 for the first retry.
+    fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry - 1))
+            .min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0) * rand::random::<f64>();
+        backoff.mul_f64(1.0 - jitter)
+    }
+
+    /// Time to wait before the given retry, honouring any retry-after header on the error.
+    fn delay(&self, retry: u32, error: &Error) -> Duration {
+        let delay = self.backoff(retry);
+
+        let retry_after = match error {
+            Error::RateLimited(data) => data
+                .headers
+                .get("retry-after")
+                .and_then(|secs| secs.parse::<u64>().ok())
+                .map(Duration::from_secs),
+            _ => None,
+        };
+
+        retry_after.map_or(delay, |retry_after| delay.max(retry_after))
     }
 }
 
 #[async_trait]
-pub trait AsyncIOBuilder: Send {
+pub trait AsyncIOBuilder: Send + Sync {
     async fn new_stream(&self, url: &str) -> Result<Box<dyn AsyncIO>, WebsocketError>;
 }
 
@@ -411,6 +1019,9 @@ pub(crate) async fn default_tls_stream(url: &str) -> TlsStream<TcpStream> {
     connector.connect(url.domain().unwrap(), tcp).await.unwrap()
 }
 
+type WebsocketReader = ReadHalf<Box<dyn AsyncIO>>;
+type WebsocketWriter = WriteHalf<Box<dyn AsyncIO>>;
+
 /// # Main crate interface
 /// CBProAPI is the main entry point to CBPro data.
 ///
@@ -420,14 +1031,34 @@ pub(crate) async fn default_tls_stream(url: &str) -> TlsStream<TcpStream> {
 pub struct CBProAPI {
     pub client: Client,
 
-    /// Rate limit in requests per second
-    pool: RateLimitedPool,
+    /// REST base URL
+    url: Arc<String>,
+
+    /// Token buckets for public, private and order traffic
+    limiter: RateLimiter,
+    retry_policy: RetryPolicy,
     user_agent: Arc<String>,
 
+    /// Server clock offset applied to signed requests when enabled
+    clock: Option<ServerClock>,
+
     websocket_connector: Arc<Mutex<Box<dyn AsyncIOBuilder>>>,
-    websocket: Arc<Mutex<Option<Box<dyn AsyncIO>>>>,
+    /// Halves of the websocket so writing a subscription never waits on a read in progress
+    websocket_reader: Arc<Mutex<Option<WebsocketReader>>>,
+    websocket_writer: Arc<Mutex<Option<WebsocketWriter>>>,
+    /// Wakes a read waiting on a websocket that another task is replacing
+    websocket_reset: Arc<Notify>,
     wss_url: Arc<Mutex<String>>,
     threads: Arc<Mutex<Vec<JoinHandle<()>>>>,
+
+    /// Backoff used to reopen a dropped websocket, reconnecting is disabled when None
+    reconnect_policy: Option<RetryPolicy>,
+    /// Subscriptions sent on the current websocket, replayed after reconnecting
+    subscriptions: Arc<Mutex<Vec<SubscribeRequest>>>,
+    /// Channels coinbase last confirmed with a subscriptions message
+    active_channels: Arc<Mutex<Vec<Channel>>>,
+    /// Live level2 order books per product, the last one to stop unsubscribes from the channel
+    order_books: Arc<Mutex<HashMap<String, usize>>>,
 }
 
 #[derive(Clone)]
@@ -437,101 +1068,382 @@ pub struct APIKeyData {
     pub passphrase: String,
 }
 
-/// Creates a CBProAPI instance with useful default values.
-impl Default for CBProAPI {
-    fn default() -> Self {
-        CBProAPI {
-            client: Client::new(),
-            user_agent: Arc::new("Rust".to_string()),
-            pool: RateLimitedPool::new(10.0),
-            websocket: Arc::new(Mutex::new(None)),
-            wss_url: Arc::new(Mutex::new(
-                "https://ws-feed.exchange.coinbase.com/".to_string(),
-            )),
-            websocket_connector: Arc::new(Mutex::new(Box::new(TokioTlsStreamBuilder {}))),
-            threads: Arc::new(Mutex::new(Vec::new())),
-        }
+/// Only the key is printed so credentials never end up in logs or error context.
+impl Debug for APIKeyData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("APIKeyData")
+            .field("key", &self.key)
+            .finish_non_exhaustive()
     }
 }
 
-impl CBProAPI {
-    pub fn from_client(client: Client) -> Self {
-        CBProAPI {
-            client,
-            user_agent: Arc::new("Rust".to_string()),
-            pool: RateLimitedPool::new(10.0),
-            websocket: Arc::new(Mutex::new(None)),
-            wss_url: Arc::new(Mutex::new(
-                "https://ws-feed.exchange.coinbase.com/".to_string(),
-            )),
-            websocket_connector: Arc::new(Mutex::new(Box::new(TokioTlsStreamBuilder {}))),
-            threads: Arc::new(Mutex::new(Vec::new())),
+const REST_URL: &str = "https://api.exchange.coinbase.com";
+const WEBSOCKET_URL: &str = "https://ws-feed.exchange.coinbase.com/";
+const SANDBOX_REST_URL: &str = "https://api-public.sandbox.exchange.coinbase.com";
+const SANDBOX_WEBSOCKET_URL: &str = "https://ws-feed-public.sandbox.exchange.coinbase.com/";
+
+/// Builds a [CBProAPI] with custom endpoints, rate limits and retry behaviour.
+/// ## Example
+///```
+/// use coinbase_pro::api::{CBProAPIBuilder, RateLimit};
+/// let api = CBProAPIBuilder::new()
+///    .sandbox()
+///    .set_user_agent("my-trading-bot".to_string())
+///    .set_public_rate_limit(RateLimit::new(3.0, 6))
+///    .set_private_rate_limit(RateLimit::new(5.0, 10))
+///    .build();
+///```
+pub struct CBProAPIBuilder {
+    client: Option<Client>,
+    url: String,
+    websocket_url: String,
+    user_agent: String,
+    rate_limits: RateLimits,
+    retry_policy: RetryPolicy,
+    io_builder: Box<dyn AsyncIOBuilder>,
+    server_time_refresh: Option<Duration>,
+    reconnect_policy: Option<RetryPolicy>,
+}
+
+impl CBProAPIBuilder {
+    pub fn new() -> Self {
+        CBProAPIBuilder {
+            client: None,
+            url: REST_URL.to_string(),
+            websocket_url: WEBSOCKET_URL.to_string(),
+            user_agent: "Rust".to_string(),
+            rate_limits: RateLimits::default(),
+            retry_policy: RetryPolicy::default(),
+            io_builder: Box::new(TokioTlsStreamBuilder {}),
+            server_time_refresh: None,
+            reconnect_policy: None,
         }
     }
 
-    pub fn from_client_and_io_builder(
-        client: Client,
-        builder: impl AsyncIOBuilder + 'static,
-    ) -> Self {
-        CBProAPI {
-            client,
-            user_agent: Arc::new("Rust".to_string()),
-            pool: RateLimitedPool::new(10.0),
-            websocket: Arc::new(Mutex::new(None)),
-            wss_url: Arc::new(Mutex::new(
-                "https://ws-feed.exchange.coinbase.com/".to_string(),
-            )),
-            websocket_connector: Arc::new(Mutex::new(Box::new(builder))),
-            threads: Arc::new(Mutex::new(Vec::new())),
-        }
+    /// Point both the REST and websocket endpoints at coinbase's public sandbox.
+    pub fn sandbox(self) -> Self {
+        self.set_url(SANDBOX_REST_URL.to_string())
+            .set_websocket_url(SANDBOX_WEBSOCKET_URL.to_string())
     }
 
-    /// Send a [Get Product Book Request](https://docs.cloud.coinbase.com/exchange/reference/exchangerestapi_getproductbook) and return a result containing the requested product book or an error.
-    pub async fn get_product_book(
+    /// Use an existing client instead of creating a new one.
+    pub fn set_client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Base URL every REST endpoint is appended to, without a trailing slash.
+    pub fn set_url(mut self, url: String) -> Self {
+        self.url = url;
+        self
+    }
+
+    pub fn set_websocket_url(mut self, websocket_url: String) -> Self {
+        self.websocket_url = websocket_url;
+        self
+    }
+
+    /// User-Agent header sent with every REST request.
+    pub fn set_user_agent(mut self, user_agent: String) -> Self {
+        self.user_agent = user_agent;
+        self
+    }
+
+    pub fn set_rate_limits(mut self, rate_limits: RateLimits) -> Self {
+        self.rate_limits = rate_limits;
+        self
+    }
+
+    /// Limit for unsigned market data requests.
+    pub fn set_public_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limits.public = limit;
+        self
+    }
+
+    /// Limit for signed requests other than placing or cancelling orders.
+    pub fn set_private_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limits.private = limit;
+        self
+    }
+
+    /// Limit for placing and cancelling orders.
+    pub fn set_order_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limits.orders = limit;
+        self
+    }
+
+    pub fn set_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Builder used to open the underlying stream of the websocket connection.
+    pub fn set_io_builder(mut self, io_builder: impl AsyncIOBuilder + 'static) -> Self {
+        self.io_builder = Box::new(io_builder);
+        self
+    }
+
+    /// Sign requests using coinbase's clock instead of the local clock.
+    ///
+    /// The offset between the two clocks is measured with a `/time` request before the first
+    /// signed request and measured again whenever it is older than `refresh_interval`.
+    /// Use this when the local clock cannot be trusted to stay within 30 seconds of coinbase's.
+    pub fn sync_server_time(mut self, refresh_interval: Duration) -> Self {
+        self.server_time_refresh = Some(refresh_interval);
+        self
+    }
+
+    /// Reopen the websocket when it drops and re-send every active subscription.
+    ///
+    /// `read_websocket` returns `WebsocketMessage::Reconnected` once the new connection is up,
+    /// messages sent while disconnected are lost so anything built from the feed should resync.
+    /// Each attempt waits according to the policy's backoff, `max_attempts` bounds the attempts
+    /// made for a single disconnect.
+    pub fn auto_reconnect_websocket(mut self, reconnect_policy: RetryPolicy) -> Self {
+        self.reconnect_policy = Some(reconnect_policy);
+        self
+    }
+
+    pub fn build(self) -> CBProAPI {
+        CBProAPI {
+            client: self.client.unwrap_or_else(Client::new),
+            url: Arc::new(self.url),
+            user_agent: Arc::new(self.user_agent),
+            limiter: RateLimiter::new(self.rate_limits),
+            retry_policy: self.retry_policy,
+            clock: self.server_time_refresh.map(ServerClock::new),
+            websocket_reader: Arc::new(Mutex::new(None)),
+            websocket_writer: Arc::new(Mutex::new(None)),
+            websocket_reset: Arc::new(Notify::new()),
+            wss_url: Arc::new(Mutex::new(self.websocket_url)),
+            websocket_connector: Arc::new(Mutex::new(self.io_builder)),
+            threads: Arc::new(Mutex::new(Vec::new())),
+            reconnect_policy: self.reconnect_policy,
+            subscriptions: Arc::new(Mutex::new(Vec::new())),
+            active_channels: Arc::new(Mutex::new(Vec::new())),
+            order_books: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl Default for CBProAPIBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Creates a CBProAPI instance with useful default values.
+impl Default for CBProAPI {
+    fn default() -> Self {
+        CBProAPIBuilder::new().build()
+    }
+}
+
+impl CBProAPI {
+    pub fn from_client(client: Client) -> Self {
+        CBProAPIBuilder::new().set_client(client).build()
+    }
+
+    pub fn from_client_and_io_builder(
+        client: Client,
+        builder: impl AsyncIOBuilder + 'static,
+    ) -> Self {
+        CBProAPIBuilder::new()
+            .set_client(client)
+            .set_io_builder(builder)
+            .build()
+    }
+
+    /// A request builder pointed at this instance's REST URL.
+    fn request(&self) -> CBRequestBuilder {
+        CBRequestBuilder::new(&self.client, self.user_agent.deref().clone())
+            .set_url(self.url.deref().clone())
+            .set_server_clock(self.clock.clone())
+    }
+
+    /// Replace the [RetryPolicy] used for every subsequent request.
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    async fn exec_with_retry<O>(&self, request: CBRequestBuilder) -> Result<O, Error>
+    where
+        O: DeserializeOwned + Debug,
+    {
+        self.limiter
+            .pool_for(&request)
+            .schedule_retryable_task(&self.retry_policy, request.is_idempotent(), || {
+                request.clone().exec::<O>()
+            })
+            .await
+    }
+
+    async fn exec_pagenated_with_retry<O>(&self, request: CBRequestBuilder) -> Result<Vec<O>, Error>
+    where
+        O: DeserializeOwned + Debug,
+    {
+        let pool = self.limiter.pool_for(&request).clone();
+
+        request
+            .set_rate_limiter(pool, self.retry_policy.clone())
+            .exec_pagenated::<O>()
+            .await
+    }
+
+    /// Send a [Get Time Request](https://docs.cloud.coinbase.com/exchange/reference/exchangerestapi_gettime) and return coinbase's current time.
+    pub async fn get_time(&self) -> Result<ServerTime, Error> {
+        let request = self.request().set_endpoint("/time".to_string());
+
+        self.exec_with_retry::<ServerTime>(request).await
+    }
+
+    /// Send a [Get Product Book Request](https://docs.cloud.coinbase.com/exchange/reference/exchangerestapi_getproductbook) and return a result containing the requested product book or an error.
+    pub async fn get_product_book(
         self: &Self,
         product_id: String,
         level: Option<Level>,
     ) -> Result<ProductBook, Error> {
-        let future = CBRequestBuilder::new(&self.client, self.user_agent.deref().clone())
+        let request = self
+            .request()
             .try_add_query_param("level".to_string(), level.map(|x| x.as_string()))
-            .set_endpoint(format!("/products/{}/book", product_id))
-            .exec::<ProductBook>();
+            .set_endpoint(format!("/products/{}/book", product_id));
+
+        self.exec_with_retry::<ProductBook>(request).await
+    }
+
+    /// Send a [Get Product Book Request](https://docs.cloud.coinbase.com/exchange/reference/exchangerestapi_getproductbook) with [Level::Three] and return every open order of the product.
+    /// Level 3 entries carry an order id instead of an order count, so they are not parsed as a
+    /// [ProductBook].
+    pub async fn get_product_book_l3(
+        &self,
+        product_id: String,
+    ) -> Result<Level3ProductBook, Error> {
+        let request = self
+            .request()
+            .add_query_param("level".to_string(), Level::Three.as_string())
+            .set_endpoint(format!("/products/{}/book", product_id));
+
+        self.exec_with_retry::<Level3ProductBook>(request).await
+    }
+
+    /// Send a [Get Product Ticker Request](https://docs.cloud.coinbase.com/exchange/reference/exchangerestapi_getproductticker) and return a snapshot of the last trade, best bid/ask and 24h volume.
+    pub async fn get_product_ticker(&self, product_id: String) -> Result<ProductTicker, Error> {
+        let request = self
+            .request()
+            .set_endpoint(format!("/products/{}/ticker", product_id));
+
+        self.exec_with_retry::<ProductTicker>(request).await
+    }
+
+    /// Send a [Get Product Stats Request](https://docs.cloud.coinbase.com/exchange/reference/exchangerestapi_getproductstats) and return the 24h stats along with the 30 day volume.
+    pub async fn get_product_stats(&self, product_id: String) -> Result<ProductStats, Error> {
+        let request = self
+            .request()
+            .set_endpoint(format!("/products/{}/stats", product_id));
+
+        self.exec_with_retry::<ProductStats>(request).await
+    }
+
+    /// Send [Get Product Trades Requests](https://docs.cloud.coinbase.com/exchange/reference/exchangerestapi_getproducttrades) and return the trades newest first.
+    ///
+    /// Trades are walked back through history one page at a time starting from the `after` cursor
+    /// (a trade id, or the most recent trade when [None]) for at most `pages` pages.
+    /// To continue from where a call stopped pass the `trade_id` of the last returned trade as `after`.
+    ///
+    /// Returns [Error::InvalidPageCount] without sending a request when `pages` is 0.
+    pub async fn get_product_trades(
+        &self,
+        product_id: String,
+        after: Option<String>,
+        pages: usize,
+    ) -> Result<Vec<Trade>, Error> {
+        if pages == 0 {
+            return Err(Error::InvalidPageCount(pages));
+        }
+
+        let request = self
+            .request()
+            .set_endpoint(format!("/products/{}/trades", product_id))
+            .set_after(after)
+            .set_max_pages(Some(pages));
+
+        self.exec_pagenated_with_retry::<Trade>(request).await
+    }
+
+    /// Send [Get Product Candles Requests](https://docs.cloud.coinbase.com/exchange/reference/exchangerestapi_getproductcandles) covering `start` to `end`.
+    ///
+    /// Coinbase caps every response at 300 candles so longer ranges are split into several requests.
+    /// Each request is scheduled through the rate limiter and the results are merged
+    /// into a single [Vec] ordered from oldest to newest candle.
+    pub async fn get_product_candles(
+        &self,
+        product_id: String,
+        granularity: Granularity,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<Vec<Candle>, Error> {
+        let step = chrono::Duration::seconds(granularity.as_seconds());
+        let chunk_span = step * (MAX_CANDLES_PER_REQUEST - 1);
+
+        let mut candles = Vec::new();
+        let mut chunk_start = start;
+
+        while chunk_start <= end {
+            let chunk_end = min(chunk_start + chunk_span, end);
+
+            let request = self
+                .request()
+                .set_endpoint(format!("/products/{}/candles", product_id))
+                .add_query_param("granularity".to_string(), granularity.as_string())
+                .add_query_param(
+                    "start".to_string(),
+                    chunk_start.format(CANDLE_TIME_FORMAT).to_string(),
+                )
+                .add_query_param(
+                    "end".to_string(),
+                    chunk_end.format(CANDLE_TIME_FORMAT).to_string(),
+                );
+
+            let mut chunk = self.exec_with_retry::<Vec<Candle>>(request).await?;
+            candles.append(&mut chunk);
+
+            chunk_start = chunk_end + step;
+        }
 
-        self.pool.clone().schedule_rate_limited_task(future).await
+        // Chunks are returned newest first and may overlap on their boundaries.
+        candles.sort_by_key(|candle| candle.time);
+        candles.dedup_by_key(|candle| candle.time);
+
+        Ok(candles)
     }
 
     pub async fn get_all_products(self: &Self) -> Result<Vec<Product>, Error> {
-        let future = CBRequestBuilder::new(&self.client, self.user_agent.deref().clone())
-            .set_endpoint(format!("/products"))
-            .exec::<Vec<Product>>();
+        let request = self.request().set_endpoint(format!("/products"));
 
-        self.pool.clone().schedule_rate_limited_task(future).await
+        self.exec_with_retry::<Vec<Product>>(request).await
     }
 
     pub async fn get_product(self: &Self, product_id: String) -> Result<Product, Error> {
-        let future = CBRequestBuilder::new(&self.client, self.user_agent.deref().clone())
-            .set_endpoint(format!("/products/{}", product_id))
-            .exec::<Product>();
+        let request = self
+            .request()
+            .set_endpoint(format!("/products/{}", product_id));
 
-        self.pool.clone().schedule_rate_limited_task(future).await
+        self.exec_with_retry::<Product>(request).await
     }
 
     pub async fn get_fees(self: &Self, account: APIKeyData) -> Result<Fees, Error> {
-        let future = CBRequestBuilder::new(&self.client, self.user_agent.deref().clone())
-            .set_endpoint(format!("/fees"))
-            .sign(account)
-            .exec::<Fees>();
+        let request = self.request().set_endpoint(format!("/fees")).sign(account);
 
-        self.pool.clone().schedule_rate_limited_task(future).await
+        self.exec_with_retry::<Fees>(request).await
     }
 
     pub async fn get_accounts(self: &Self, account: APIKeyData) -> Result<Vec<Account>, Error> {
-        let future = CBRequestBuilder::new(&self.client, self.user_agent.deref().clone())
+        let request = self
+            .request()
             .set_endpoint(format!("/accounts"))
-            .sign(account)
-            .exec::<Vec<Account>>();
+            .sign(account);
 
-        self.pool.clone().schedule_rate_limited_task(future).await
+        self.exec_with_retry::<Vec<Account>>(request).await
     }
 
     pub async fn get_account(
@@ -539,12 +1451,12 @@ impl CBProAPI {
         account: APIKeyData,
         account_id: &str,
     ) -> Result<Account, Error> {
-        let future = CBRequestBuilder::new(&self.client, self.user_agent.deref().clone())
+        let request = self
+            .request()
             .set_endpoint(format!("/accounts/{}", account_id))
-            .sign(account)
-            .exec::<Account>();
+            .sign(account);
 
-        self.pool.clone().schedule_rate_limited_task(future).await
+        self.exec_with_retry::<Account>(request).await
     }
 
     pub async fn get_account_holds(
@@ -552,12 +1464,12 @@ impl CBProAPI {
         account: APIKeyData,
         account_id: &str,
     ) -> Result<Vec<Hold>, Error> {
-        let future = CBRequestBuilder::new(&self.client, self.user_agent.deref().clone())
+        let request = self
+            .request()
             .set_endpoint(format!("/accounts/{}/holds", account_id))
-            .sign(account)
-            .exec_pagenated::<Hold>();
+            .sign(account);
 
-        self.pool.clone().schedule_rate_limited_task(future).await
+        self.exec_pagenated_with_retry::<Hold>(request).await
     }
 
     pub async fn get_account_ledger(
@@ -565,21 +1477,41 @@ impl CBProAPI {
         account: APIKeyData,
         account_id: &str,
     ) -> Result<Vec<Ledger>, Error> {
-        let future = CBRequestBuilder::new(&self.client, self.user_agent.deref().clone())
+        let request = self
+            .request()
             .set_endpoint(format!("/accounts/{}/ledger", account_id))
+            .sign(account);
+
+        self.exec_pagenated_with_retry::<Ledger>(request).await
+    }
+
+    /// Streaming counterpart of [Self::get_account_ledger].
+    /// Pages are requested lazily as the stream is polled and each ledger entry is yielded
+    /// individually.
+    pub fn get_account_ledger_stream(
+        &self,
+        account: APIKeyData,
+        account_id: &str,
+        pagination: Pagination,
+    ) -> impl Stream<Item = Result<Ledger, Error>> {
+        let pages = self
+            .request()
+            .set_endpoint(format!("/accounts/{}/ledger", account_id))
+            .set_pagination(pagination)
+            .set_rate_limiter(self.limiter.private.clone(), self.retry_policy.clone())
             .sign(account)
-            .exec_pagenated::<Ledger>();
+            .exec_paginated_stream::<Ledger>();
 
-        self.pool.clone().schedule_rate_limited_task(future).await
+        flatten_pages(pages)
     }
 
     pub async fn get_all_wallets(self: &Self, account: APIKeyData) -> Result<Vec<Wallet>, Error> {
-        let future = CBRequestBuilder::new(&self.client, self.user_agent.deref().clone())
+        let request = self
+            .request()
             .set_endpoint(format!("/coinbase-accounts"))
-            .sign(account)
-            .exec::<Vec<Wallet>>();
+            .sign(account);
 
-        self.pool.clone().schedule_rate_limited_task(future).await
+        self.exec_with_retry::<Vec<Wallet>>(request).await
     }
 
     pub async fn get_account_transfers(
@@ -587,12 +1519,12 @@ impl CBProAPI {
         account: APIKeyData,
         account_id: &str,
     ) -> Result<Vec<Transfer>, Error> {
-        let future = CBRequestBuilder::new(&self.client, self.user_agent.deref().clone())
+        let request = self
+            .request()
             .set_endpoint(format!("/accounts/{}/transfers", account_id))
-            .sign(account)
-            .exec_pagenated::<Transfer>();
+            .sign(account);
 
-        self.pool.clone().schedule_rate_limited_task(future).await
+        self.exec_pagenated_with_retry::<Transfer>(request).await
     }
 
     pub async fn get_conversion(
@@ -601,29 +1533,27 @@ impl CBProAPI {
         conversion_id: &str,
         profile_id: &str,
     ) -> Result<Conversion, Error> {
-        let future = CBRequestBuilder::new(&self.client, self.user_agent.deref().clone())
+        let request = self
+            .request()
             .set_endpoint(format!("/conversions/{}", conversion_id))
             .add_query_param("profile_id".to_string(), profile_id.to_string())
-            .sign(account)
-            .exec::<Conversion>();
+            .sign(account);
 
-        self.pool.clone().schedule_rate_limited_task(future).await
+        self.exec_with_retry::<Conversion>(request).await
     }
 
     pub async fn get_currencies(self: &Self) -> Result<Vec<Currency>, Error> {
-        let future = CBRequestBuilder::new(&self.client, self.user_agent.deref().clone())
-            .set_endpoint(format!("/currencies"))
-            .exec::<Vec<Currency>>();
+        let request = self.request().set_endpoint(format!("/currencies"));
 
-        self.pool.clone().schedule_rate_limited_task(future).await
+        self.exec_with_retry::<Vec<Currency>>(request).await
     }
 
     pub async fn get_currency(self: &Self, currency_id: String) -> Result<Currency, Error> {
-        let future = CBRequestBuilder::new(&self.client, self.user_agent.deref().clone())
-            .set_endpoint(format!("/currencies/{}", currency_id))
-            .exec::<Currency>();
+        let request = self
+            .request()
+            .set_endpoint(format!("/currencies/{}", currency_id));
 
-        self.pool.clone().schedule_rate_limited_task(future).await
+        self.exec_with_retry::<Currency>(request).await
     }
 
     pub async fn get_fills(
@@ -633,15 +1563,39 @@ impl CBProAPI {
         product_id: Option<String>,
         profile_id: Option<String>,
     ) -> Result<Vec<Fill>, Error> {
-        let future = CBRequestBuilder::new(&self.client, self.user_agent.deref().clone())
+        let request = self
+            .request()
             .set_endpoint(format!("/fills"))
             .try_add_query_param("order_id".to_string(), order_id)
             .try_add_query_param("product_id".to_string(), product_id)
             .try_add_query_param("profile_id".to_string(), profile_id)
+            .sign(account);
+
+        self.exec_pagenated_with_retry::<Fill>(request).await
+    }
+
+    /// Streaming counterpart of [Self::get_fills].
+    /// Pages are requested lazily as the stream is polled and each fill is yielded individually.
+    pub fn get_fills_stream(
+        &self,
+        account: APIKeyData,
+        order_id: Option<String>,
+        product_id: Option<String>,
+        profile_id: Option<String>,
+        pagination: Pagination,
+    ) -> impl Stream<Item = Result<Fill, Error>> {
+        let pages = self
+            .request()
+            .set_endpoint("/fills".to_string())
+            .try_add_query_param("order_id".to_string(), order_id)
+            .try_add_query_param("product_id".to_string(), product_id)
+            .try_add_query_param("profile_id".to_string(), profile_id)
+            .set_pagination(pagination)
+            .set_rate_limiter(self.limiter.private.clone(), self.retry_policy.clone())
             .sign(account)
-            .exec_pagenated::<Fill>();
+            .exec_paginated_stream::<Fill>();
 
-        self.pool.clone().schedule_rate_limited_task(future).await
+        flatten_pages(pages)
     }
 
     pub async fn get_orders(
@@ -650,14 +1604,36 @@ impl CBProAPI {
         product_id: Option<String>,
         profile_id: Option<String>,
     ) -> Result<Vec<Order>, Error> {
-        let future = CBRequestBuilder::new(&self.client, self.user_agent.deref().clone())
+        let request = self
+            .request()
             .set_endpoint(format!("/orders"))
             .try_add_query_param("product_id".to_string(), product_id)
             .try_add_query_param("profile_id".to_string(), profile_id)
+            .sign(account);
+
+        self.exec_pagenated_with_retry::<Order>(request).await
+    }
+
+    /// Streaming counterpart of [Self::get_orders].
+    /// Pages are requested lazily as the stream is polled and each order is yielded individually.
+    pub fn get_orders_stream(
+        &self,
+        account: APIKeyData,
+        product_id: Option<String>,
+        profile_id: Option<String>,
+        pagination: Pagination,
+    ) -> impl Stream<Item = Result<Order, Error>> {
+        let pages = self
+            .request()
+            .set_endpoint("/orders".to_string())
+            .try_add_query_param("product_id".to_string(), product_id)
+            .try_add_query_param("profile_id".to_string(), profile_id)
+            .set_pagination(pagination)
+            .set_rate_limiter(self.limiter.private.clone(), self.retry_policy.clone())
             .sign(account)
-            .exec_pagenated::<Order>();
+            .exec_paginated_stream::<Order>();
 
-        self.pool.clone().schedule_rate_limited_task(future).await
+        flatten_pages(pages)
     }
 
     pub async fn create_order(
@@ -665,14 +1641,18 @@ impl CBProAPI {
         account: APIKeyData,
         order: impl Serialize + CoinbaseOrder,
     ) -> Result<NewOrderResponse, Error> {
-        let future = CBRequestBuilder::new(&self.client, self.user_agent.deref().clone())
+        // Coinbase rejects a duplicate client_oid so only then is it safe to resend an order.
+        let idempotent = order.client_oid().is_some();
+
+        let request = self
+            .request()
             .set_endpoint(format!("/orders"))
             .set_method(RequestMethod::POST)
+            .set_idempotent(Some(idempotent))
             .set_body(order)?
-            .sign(account)
-            .exec::<NewOrderResponse>();
+            .sign(account);
 
-        self.pool.clone().schedule_rate_limited_task(future).await
+        self.exec_with_retry::<NewOrderResponse>(request).await
     }
 
     pub async fn get_single_order(
@@ -680,27 +1660,99 @@ impl CBProAPI {
         account: APIKeyData,
         order_id: String,
     ) -> Result<NewOrderResponse, Error> {
-        let future = CBRequestBuilder::new(&self.client, self.user_agent.deref().clone())
+        let request = self
+            .request()
             .set_endpoint(format!("/orders/{}", order_id))
-            .sign(account)
-            .exec::<NewOrderResponse>();
+            .sign(account);
 
-        self.pool.clone().schedule_rate_limited_task(future).await
+        self.exec_with_retry::<NewOrderResponse>(request).await
+    }
+
+    /// Send a [Cancel Order Request](https://docs.cloud.coinbase.com/exchange/reference/exchangerestapi_deleteorder) and return the id of the cancelled order.
+    pub async fn cancel_order(
+        &self,
+        account: APIKeyData,
+        order_id: String,
+        profile_id: Option<String>,
+    ) -> Result<String, Error> {
+        let request = self
+            .request()
+            .set_endpoint(format!("/orders/{}", order_id))
+            .set_method(RequestMethod::DELETE)
+            .try_add_query_param("profile_id".to_string(), profile_id)
+            .sign(account);
+
+        self.exec_with_retry::<String>(request).await
+    }
+
+    /// Cancel an order using the `client_oid` it was placed with instead of the server assigned order id.
+    /// Returns the id of the cancelled order.
+    pub async fn cancel_order_by_client_oid(
+        &self,
+        account: APIKeyData,
+        client_oid: String,
+        profile_id: Option<String>,
+    ) -> Result<String, Error> {
+        let request = self
+            .request()
+            .set_endpoint(format!("/orders/client:{}", client_oid))
+            .set_method(RequestMethod::DELETE)
+            .try_add_query_param("profile_id".to_string(), profile_id)
+            .sign(account);
+
+        self.exec_with_retry::<String>(request).await
+    }
+
+    /// Send a [Cancel All Orders Request](https://docs.cloud.coinbase.com/exchange/reference/exchangerestapi_deleteorders) and return the ids of every cancelled order.
+    /// Orders can optionally be restricted to a single product and/or profile.
+    pub async fn cancel_all_orders(
+        &self,
+        account: APIKeyData,
+        product_id: Option<String>,
+        profile_id: Option<String>,
+    ) -> Result<Vec<String>, Error> {
+        let request = self
+            .request()
+            .set_endpoint("/orders".to_string())
+            .set_method(RequestMethod::DELETE)
+            .try_add_query_param("product_id".to_string(), product_id)
+            .try_add_query_param("profile_id".to_string(), profile_id)
+            .sign(account);
+
+        self.exec_with_retry::<Vec<String>>(request).await
     }
 
     /// performs a write and a read of the websocket.
     ///
-    /// todo! add check to the received message to make sure the desired channels are subscribed.
+    /// When coinbase answers with a subscriptions message, every requested channel and product
+    /// must be in it or `WebsocketError::UnconfirmedSubscription` is returned.
     pub async fn subscribe_to_websocket(
-        &mut self,
+        &self,
         subscription: crate::datastructs::websocket::SubscribeRequest,
     ) -> Result<crate::datastructs::websocket::WebsocketMessage, WebsocketError> {
-        let init;
-        {
-            let websock = self.websocket.lock().await;
-            init = websock.is_none();
+        let channels = subscription.channels.clone();
+        self.send_subscribe(subscription).await?;
+
+        let response = self.read_websocket_once().await;
+
+        if let Ok(WebsocketMessage::Subscriptions(confirmed)) = &response {
+            let missing = missing_channels(&channels, &confirmed.channels);
+            if !missing.is_empty() {
+                return Err(WebsocketError::UnconfirmedSubscription { missing });
+            }
         }
-        if init {
+
+        response
+    }
+
+    /// Writes a subscribe message without waiting for the response, opening the websocket if
+    /// needed. The subscriptions confirmation arrives on [CBProAPI::websocket_stream].
+    pub async fn send_subscribe(
+        &self,
+        mut subscription: crate::datastructs::websocket::SubscribeRequest,
+    ) -> Result<(), WebsocketError> {
+        let mut writer = self.websocket_writer.lock().await;
+        if writer.is_none() {
             let new_websocket = self
                 .websocket_connector
                 .lock()
@@ -708,22 +1760,100 @@ impl CBProAPI {
                 .new_stream(self.wss_url.lock().await.clone().as_str())
                 .await?;
 
-            self.websocket = Arc::new(Mutex::new(Some(new_websocket)));
+            let (read_half, write_half) = tokio::io::split(new_websocket);
+            *self.websocket_reader.lock().await = Some(read_half);
+            *writer = Some(write_half);
         }
 
-        let mut lock = self.websocket.lock().await;
-        lock.borrow_mut()
-            .as_mut()
-            .unwrap()
+        self.refresh_signature(&mut subscription).await?;
+        let message = WebsocketMessage::Subscribe(subscription.clone());
+        Self::write_message(writer.as_mut().unwrap(), &message).await?;
+        self.subscriptions.lock().await.push(subscription);
+
+        Ok(())
+    }
+
+    /// Signs a signed subscription again with the server's clock when
+    /// [CBProAPIBuilder::sync_server_time] is set, the local clock otherwise.
+    async fn refresh_signature(
+        &self,
+        subscription: &mut crate::datastructs::websocket::SubscribeRequest,
+    ) -> Result<(), WebsocketError> {
+        if !subscription.is_signed() {
+            return Ok(());
+        }
+
+        let timestamp = match &self.clock {
+            Some(clock) => clock
+                .timestamp(&self.request())
+                .await
+                .map_err(|source| WebsocketError::ServerClockError { source })?,
+            None => chrono::Utc::now().timestamp(),
+        };
+        subscription.refresh_signature(timestamp);
+
+        Ok(())
+    }
+
+    /// Writes an unsubscribe message and reads the response.
+    /// The channels are no longer replayed when the websocket reconnects.
+    pub async fn unsubscribe_from_websocket(
+        &self,
+        unsubscribe: UnsubscribeRequest,
+    ) -> Result<WebsocketMessage, WebsocketError> {
+        self.send_unsubscribe(unsubscribe).await?;
+        self.read_websocket_once().await
+    }
+
+    /// Writes an unsubscribe message without waiting for the response.
+    /// The channels are no longer replayed when the websocket reconnects.
+    pub async fn send_unsubscribe(
+        &self,
+        unsubscribe: UnsubscribeRequest,
+    ) -> Result<(), WebsocketError> {
+        let mut writer = self.websocket_writer.lock().await;
+        let websocket = writer.as_mut().ok_or(NoWebsocketConnectionError)?;
+
+        Self::write_message(
+            websocket,
+            &WebsocketMessage::Unsubscribe(unsubscribe.clone()),
+        )
+        .await?;
+
+        let mut subscriptions = self.subscriptions.lock().await;
+        subscriptions
+            .iter_mut()
+            .for_each(|subscription| subscription.remove(&unsubscribe));
+        subscriptions.retain(|subscription| !subscription.channels.is_empty());
+
+        Ok(())
+    }
+
+    /// Channels and products coinbase last confirmed with a subscriptions message.
+    pub async fn active_subscriptions(&self) -> Vec<Channel> {
+        self.active_channels.lock().await.clone()
+    }
+
+    /// Records the confirmed channel set whenever a subscriptions message is read.
+    async fn track_subscriptions(&self, response: &Result<WebsocketMessage, WebsocketError>) {
+        if let Ok(WebsocketMessage::Subscriptions(confirmed)) = response {
+            *self.active_channels.lock().await = confirmed.channels.clone();
+        }
+    }
+
+    /// Serializes the message and writes it to the websocket.
+    async fn write_message(
+        websocket: &mut WebsocketWriter,
+        message: &WebsocketMessage,
+    ) -> Result<(), WebsocketError> {
+        websocket
             .write_all(
-                serde_json::to_string(&crate::datastructs::websocket::WebsocketMessage::Subscribe(
-                    subscription.clone(),
-                ))
-                .map_err(|err| SerdeJSONParseError {
-                    message: format!("{:?}", subscription),
-                    source: err,
-                })?
-                .as_bytes(),
+                serde_json::to_string(message)
+                    .map_err(|err| SerdeJSONParseError {
+                        message: format!("{:?}", message),
+                        source: err,
+                    })?
+                    .as_bytes(),
             )
             .await
             .map_err(|err| WebsocketIOError {
@@ -731,26 +1861,160 @@ impl CBProAPI {
                 #[cfg(backtrace)]
                 backtrace: Backtrace::capture(),
                 context: Some(HashMap::from([(
-                    "Subscription".to_string(),
-                    format!("{:?}", subscription),
+                    "Message".to_string(),
+                    format!("{:?}", message),
                 )])),
             })?;
 
-        Self::read_websocket_with_lock(lock).await
+        Ok(())
     }
 
     /// reads the websocket and returns the next Websocket message received
+    ///
+    /// Server pings are answered automatically and returned as `WebsocketMessage::Ping`, a close
+    /// frame from the server is returned as `WebsocketError::ConnectionClosed`.
+    ///
+    /// With `auto_reconnect_websocket` enabled a dropped connection is reopened and
+    /// `WebsocketMessage::Reconnected` is returned in place of the error.
     pub async fn read_websocket(
-        &mut self,
+        &self,
     ) -> Result<crate::datastructs::websocket::WebsocketMessage, WebsocketError> {
-        let lock = self.websocket.lock().await;
+        match self.read_websocket_once().await {
+            Err(err) if self.reconnect_policy.is_some() && Self::is_disconnect(&err) => {
+                debug!("Websocket disconnected, reconnecting: {}", err);
+                self.reconnect_websocket().await?;
+                Ok(WebsocketMessage::Reconnected)
+            }
+            result => result,
+        }
+    }
+
+    /// Reads the next message without reconnecting, recording any subscriptions confirmation.
+    async fn read_websocket_once(&self) -> Result<WebsocketMessage, WebsocketError> {
+        // Created before waiting on the reader so a reconnect from another task is never missed.
+        let reset = self.websocket_reset.notified();
+        let result = {
+            let lock = self.websocket_reader.lock().await;
+            tokio::select! {
+                result = Self::read_websocket_with_lock(lock) => result,
+                _ = reset => Ok(WebsocketMessage::Reconnected),
+            }
+        };
+        self.track_subscriptions(&result).await;
+        result
+    }
+
+    /// Every message read from the websocket as a [Stream], see [CBProAPI::read_websocket].
+    ///
+    /// The stream reads through a clone of the API so subscriptions can still be sent while it
+    /// is waiting on the next message. A message that fails to parse is yielded as an error and
+    /// the stream carries on, any other error is the last item of the stream.
+    pub fn websocket_stream(
+        &self,
+    ) -> impl Stream<Item = Result<crate::datastructs::websocket::WebsocketMessage, WebsocketError>>
+    {
+        futures::stream::unfold((self.clone(), false), |(api, done)| async move {
+            if done {
+                return None;
+            }
+
+            let message = api.read_websocket().await;
+            let done =
+                matches!(&message, Err(err) if !matches!(err, WebsocketError::ParseError(_)));
+            Some((message, (api, done)))
+        })
+    }
+
+    /// Errors meaning the connection is gone rather than a single bad message.
+    fn is_disconnect(err: &WebsocketError) -> bool {
+        matches!(
+            err,
+            WebsocketIOError { .. } | WebsocketError::ConnectionClosed { .. }
+        )
+    }
+
+    /// Replaces the websocket with a new connection from the configured `AsyncIOBuilder` and
+    /// re-sends every active subscription.
+    ///
+    /// Attempts are spaced by the reconnect policy's backoff, or the default [RetryPolicy] when
+    /// automatic reconnection is not enabled. The last connection error is returned once the
+    /// attempts run out.
+    ///
+    /// A read waiting on the old connection in another task, such as a [CBProAPI::websocket_stream],
+    /// returns `WebsocketMessage::Reconnected` instead.
+    pub async fn reconnect_websocket(&self) -> Result<(), WebsocketError> {
+        let policy = self.reconnect_policy.clone().unwrap_or_default();
+        let mut attempt = 1;
+
+        loop {
+            match self.replay_subscriptions().await {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt >= policy.max_attempts => return Err(err),
+                Err(err) => {
+                    debug!("Websocket reconnect attempt {} failed: {}", attempt, err);
+                    tokio::time::sleep(policy.backoff(attempt)).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Opens a new connection and sends the active subscriptions on it.
+    async fn replay_subscriptions(&self) -> Result<(), WebsocketError> {
+        let mut writer = self.websocket_writer.lock().await;
+        // A read on the old stream may never finish, it has to let go of the reader first.
+        self.websocket_reset.notify_waiters();
+        // Held until the new stream is in place so no read runs against a missing stream.
+        let mut reader = self.websocket_reader.lock().await;
+        // Drop the dead stream before connecting so a failed attempt never leaves it in place.
+        writer.take();
+        reader.take();
+
+        let new_websocket = self
+            .websocket_connector
+            .lock()
+            .await
+            .new_stream(self.wss_url.lock().await.clone().as_str())
+            .await?;
+        let (read_half, mut write_half) = tokio::io::split(new_websocket);
+
+        // Coinbase confirms the replayed subscriptions on the new connection.
+        self.active_channels.lock().await.clear();
+        for subscription in self.subscriptions.lock().await.iter_mut() {
+            self.refresh_signature(subscription).await?;
+            let message = WebsocketMessage::Subscribe(subscription.clone());
+            Self::write_message(&mut write_half, &message).await?;
+        }
+
+        *reader = Some(read_half);
+        *writer = Some(write_half);
+        Ok(())
+    }
+
+    /// Closes the websocket with a normal closure, waiting for the server to acknowledge it.
+    /// The next subscription opens a fresh connection.
+    ///
+    /// A read in progress on a [CBProAPI::websocket_stream] must finish or be dropped first, for
+    /// example by closing from the other branch of a `select!`.
+    pub async fn close_websocket(&self) -> Result<(), WebsocketError> {
+        let mut writer = self.websocket_writer.lock().await;
 
-        Self::read_websocket_with_lock(lock).await
+        self.subscriptions.lock().await.clear();
+        self.active_channels.lock().await.clear();
+
+        let mut websocket = match writer.take() {
+            None => return Ok(()),
+            Some(websocket) => websocket,
+        };
+
+        websocket.shutdown().await?;
+        self.websocket_reader.lock().await.take();
+        Ok(())
     }
 
     /// reads the websocket into a websocket message given a MutexGuard to the WebsocketConnection.
     async fn read_websocket_with_lock(
-        mut lock: MutexGuard<'_, Option<Box<dyn AsyncIO>>>,
+        mut lock: MutexGuard<'_, Option<WebsocketReader>>,
     ) -> Result<crate::datastructs::websocket::WebsocketMessage, WebsocketError> {
         let websocket = lock
             .borrow_mut()
@@ -758,15 +2022,37 @@ impl CBProAPI {
             .ok_or(NoWebsocketConnectionError)?;
 
         let mut buf = Vec::new();
-        websocket
-            .read_to_end(&mut buf)
-            .await
-            .map_err(|err| WebsocketError::WebsocketIOError {
-                source: Box::new(err),
+        if let Err(err) = websocket.read_to_end(&mut buf).await {
+            let control = err
+                .get_ref()
+                .and_then(|inner| inner.downcast_ref::<ControlFrame>());
+
+            return match control {
+                Some(ControlFrame::Ping(payload)) => Ok(WebsocketMessage::Ping(payload.clone())),
+                Some(ControlFrame::Close { code, reason }) => {
+                    Err(WebsocketError::ConnectionClosed {
+                        code: *code,
+                        reason: reason.clone(),
+                    })
+                }
+                None => Err(WebsocketError::WebsocketIOError {
+                    source: Box::new(err),
+                    #[cfg(backtrace)]
+                    backtrace: Backtrace::capture(),
+                    context: None,
+                }),
+            };
+        }
+
+        // Coinbase never sends empty messages, an empty read means the stream hit EOF.
+        if buf.is_empty() {
+            return Err(WebsocketError::WebsocketIOError {
+                source: Box::new(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)),
                 #[cfg(backtrace)]
                 backtrace: Backtrace::capture(),
                 context: None,
-            })?;
+            });
+        }
 
         // trace!(
         //     "Websocket Incoming Message: {}",
@@ -783,51 +2069,349 @@ impl CBProAPI {
         Ok(parsed_resp)
     }
 
-    /*
-    pub async fn new_order_book_l2(&mut self, product: String) -> OrderBook {
-        let subscription = SubscriptionBuilder::new()
-            .subscribe_to_snapshot(product)
-            .build();
+    /// Subscribes to the level2 channel for `product_id` and returns an [OrderBook] seeded from
+    /// coinbase's snapshot, kept up to date by a task applying every `l2update`.
+    ///
+    /// Messages are received through `dispatcher`, which must already be reading the websocket.
+    /// When the websocket reconnects, or the dispatcher dropped updates for the book, updates are
+    /// ignored until a fresh snapshot arrives. Dropping the returned handle stops the task, the last
+    /// order book of the product to stop unsubscribes from its level2 channel.
+    ///
+    /// Fails when coinbase rejects the subscription of the product or no snapshot arrives within 10
+    /// seconds.
+    pub async fn new_order_book_l2(
+        &self,
+        dispatcher: &MessageDispatcher,
+        product_id: String,
+    ) -> Result<LiveOrderBook, WebsocketError> {
+        self.new_order_book_l2_within(dispatcher, product_id, L2_SNAPSHOT_TIMEOUT)
+            .await
+    }
 
-        self.subscribe_to_websocket(subscription).await.unwrap();
-        let order_book: OrderBook = match self.read_websocket().await.unwrap() {
-            WebsocketMessage::Snapshot(snap) => snap.into(),
-            _ => {
-                panic!("")
+    pub(crate) async fn new_order_book_l2_within(
+        &self,
+        dispatcher: &MessageDispatcher,
+        product_id: String,
+        timeout: Duration,
+    ) -> Result<LiveOrderBook, WebsocketError> {
+        let mut messages = dispatcher.product_receiver::<WebsocketMessage>(product_id.clone());
+        self.retain_order_book(&product_id).await?;
+
+        let snapshot = tokio::time::timeout(timeout, async {
+            loop {
+                match messages.recv().await {
+                    Some(WebsocketMessage::Snapshot(snapshot)) => return Ok(snapshot),
+                    // Every receiver gets the errors, only fail on those about this product.
+                    Some(WebsocketMessage::Error(error)) if error.mentions_product(&product_id) => {
+                        return Err(WebsocketError::SubscriptionRejected {
+                            message: error.message,
+                            reason: error.reason,
+                        })
+                    }
+                    Some(_) => {}
+                    None => {
+                        return Err(WebsocketError::OrderBookSnapshotMissing {
+                            product_id: product_id.clone(),
+                        })
+                    }
+                }
+            }
+        })
+        .await
+        .unwrap_or_else(|_| {
+            Err(WebsocketError::OrderBookSnapshotTimeout {
+                product_id: product_id.clone(),
+                timeout,
+            })
+        });
+
+        let order_book: OrderBook = match snapshot {
+            Ok(snapshot) => snapshot.into(),
+            Err(err) => {
+                // Keep a failed subscription from being replayed when the websocket reconnects.
+                self.release_order_book(&product_id).await;
+                return Err(err);
             }
         };
 
-        let self_clone = self.clone();
-        let order_book_clone = order_book.clone();
-        let handle =
-            spawn(Self::manage_order_book(self_clone, order_book_clone));
-        self.threads.lock().await.push(handle);
-        order_book
+        let (shutdown, stopped) = oneshot::channel();
+        let handle = spawn(Self::manage_order_book(
+            self.clone(),
+            product_id,
+            order_book.clone(),
+            messages,
+            stopped,
+        ));
+
+        let mut threads = self.threads.lock().await;
+        threads.retain(|thread| !thread.is_finished());
+        threads.push(handle);
+
+        Ok(LiveOrderBook::new(order_book, shutdown))
     }
 
-    pub(crate) async fn manage_order_book(mut self, mut order_book: OrderBook) {
+    pub(crate) async fn manage_order_book(
+        self,
+        product_id: String,
+        mut order_book: OrderBook,
+        mut messages: DispatchReceiver<WebsocketMessage>,
+        mut stopped: oneshot::Receiver<()>,
+    ) {
+        let mut dropped = messages.dropped();
+        let mut resyncing = false;
+
         loop {
-            let res = match tokio::time::timeout(Duration::new(5, 0), self.read_websocket()).await {
-                Ok(msg) => match msg {
-                    Ok(msg) => msg,
-                    Err(err) => {
-                        error!("{}", err);
-                        continue;
+            let message = tokio::select! {
+                _ = &mut stopped => break,
+                message = messages.recv() => match message {
+                    Some(message) => message,
+                    None => {
+                        debug!("Websocket feed ended, stopped order book of {}", product_id);
+                        break;
                     }
                 },
-                Err(err) => {
-                    error!("{}", err);
-                    continue;
-                }
             };
 
-            match res {
-                WebsocketMessage::L2Update(updates) => {
-                    order_book.apply_change_l2_changes(updates.changes).await;
+            if messages.dropped() != dropped {
+                dropped = messages.dropped();
+                resyncing = true;
+                // A snapshot in hand resyncs the book by itself, otherwise ask for a new one.
+                if !matches!(message, WebsocketMessage::Snapshot(_)) {
+                    error!(
+                        "Dropped level2 updates of {}, waiting for a new snapshot",
+                        product_id
+                    );
+                    if let Err(err) = self.resubscribe_order_book(&product_id).await {
+                        error!("Failed to resubscribe to level2 of {}: {}", product_id, err);
+                    }
+                }
+            }
+
+            match message {
+                WebsocketMessage::Snapshot(snapshot) => {
+                    order_book.apply_snapshot(snapshot).await;
+                    resyncing = false;
                 }
+                WebsocketMessage::L2Update(update) if !resyncing => {
+                    order_book.apply_change_l2_changes(update.changes).await;
+                }
+                // The level2 subscription is replayed on the new connection, which sends a snapshot
+                WebsocketMessage::Reconnected => resyncing = true,
                 _ => {}
             }
         }
+
+        self.release_order_book(&product_id).await;
+    }
+
+    /// Subscribes to level2 of the product for one more order book. A product other books already
+    /// follow is subscribed again, the only way to have coinbase send the new book a snapshot.
+    async fn retain_order_book(&self, product_id: &str) -> Result<(), WebsocketError> {
+        let mut order_books = self.order_books.lock().await;
+        if order_books.contains_key(product_id) {
+            self.resubscribe_order_book(product_id).await?;
+        } else {
+            let subscription = SubscriptionBuilder::new()
+                .subscribe_to_snapshot(product_id.to_string())
+                .build();
+            self.send_subscribe(subscription).await?;
+        }
+
+        *order_books.entry(product_id.to_string()).or_insert(0) += 1;
+        Ok(())
+    }
+
+    /// Stops counting one order book of the product, the last one unsubscribes from its level2
+    /// channel.
+    async fn release_order_book(&self, product_id: &str) {
+        let mut order_books = self.order_books.lock().await;
+        match order_books.get_mut(product_id) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                return;
+            }
+            _ => {
+                order_books.remove(product_id);
+            }
+        }
+
+        let unsubscribe = UnsubscribeBuilder::new()
+            .unsubscribe_from_snapshot(product_id.to_string())
+            .build();
+        if let Err(err) = self.send_unsubscribe(unsubscribe).await {
+            debug!("Failed to unsubscribe from level2 of {}: {}", product_id, err);
+        }
+    }
+
+    /// Subscribes to the full channel for `product_id` and returns an [L3OrderBook] seeded from the
+    /// level 3 product book, kept up to date by a task applying every full channel message.
+    ///
+    /// Messages are received through `dispatcher`, which must already be reading the websocket.
+    /// Messages received while the product book is fetched are buffered and applied after it.
+    /// When a message is missed the product book is fetched again. Dropping the returned handle
+    /// stops the task and unsubscribes from the product's full channel.
+    ///
+    /// Fails when the product book cannot be fetched or no product book seeds the order book within
+    /// 30 seconds.
+    pub async fn new_order_book_l3(
+        &self,
+        dispatcher: &MessageDispatcher,
+        product_id: String,
+    ) -> Result<LiveL3OrderBook, WebsocketError> {
+        self.new_order_book_l3_within(dispatcher, product_id, L3_SEED_TIMEOUT)
+            .await
+    }
+
+    pub(crate) async fn new_order_book_l3_within(
+        &self,
+        dispatcher: &MessageDispatcher,
+        product_id: String,
+        timeout: Duration,
+    ) -> Result<LiveL3OrderBook, WebsocketError> {
+        let messages = dispatcher.product_receiver::<WebsocketMessage>(product_id.clone());
+
+        let subscription = SubscriptionBuilder::new()
+            .subscribe_to_full(product_id.clone())
+            .build();
+        self.send_subscribe(subscription).await?;
+
+        let order_book = Arc::new(Mutex::new(L3OrderBook::new(product_id.clone())));
+        let (seeded, ready) = oneshot::channel();
+        let (shutdown, stopped) = oneshot::channel();
+        let handle = spawn(Self::manage_order_book_l3(
+            self.clone(),
+            product_id.clone(),
+            order_book.clone(),
+            messages,
+            stopped,
+            seeded,
+        ));
+
+        {
+            let mut threads = self.threads.lock().await;
+            threads.retain(|thread| !thread.is_finished());
+            threads.push(handle);
+        }
+
+        match tokio::time::timeout(timeout, ready).await {
+            Ok(Ok(Ok(()))) => Ok(LiveL3OrderBook::new(order_book, shutdown)),
+            Ok(Ok(Err(err))) => Err(err),
+            Ok(Err(_)) => Err(WebsocketError::OrderBookSnapshotMissing { product_id }),
+            Err(_) => {
+                // Stops the reseeding, the task unsubscribes from the full channel.
+                let _ = shutdown.send(());
+                Err(WebsocketError::OrderBookSnapshotTimeout {
+                    product_id,
+                    timeout,
+                })
+            }
+        }
+    }
+
+    pub(crate) async fn manage_order_book_l3(
+        self,
+        product_id: String,
+        order_book: Arc<Mutex<L3OrderBook>>,
+        mut messages: DispatchReceiver<WebsocketMessage>,
+        mut stopped: oneshot::Receiver<()>,
+        seeded: oneshot::Sender<Result<(), WebsocketError>>,
+    ) {
+        let mut seeded = Some(seeded);
+        let mut fetch = Some(Box::pin(
+            self.fetch_book_l3(product_id.clone(), Duration::ZERO),
+        ));
+
+        loop {
+            tokio::select! {
+                _ = &mut stopped => break,
+                product_book = async { fetch.as_mut().unwrap().await }, if fetch.is_some() => {
+                    fetch = None;
+                    let retry = match product_book {
+                        Ok(product_book) => match order_book.lock().await.seed(product_book) {
+                            Ok(()) => {
+                                if let Some(seeded) = seeded.take() {
+                                    let _ = seeded.send(Ok(()));
+                                }
+                                false
+                            }
+                            Err(gap) => {
+                                debug!("Book of {} is older than messages {:?}", product_id, gap);
+                                true
+                            }
+                        },
+                        Err(source) => match seeded.take() {
+                            Some(seeded) => {
+                                let _ = seeded.send(Err(WebsocketError::OrderBookSeedError {
+                                    product_id: product_id.clone(),
+                                    source,
+                                }));
+                                break;
+                            }
+                            None => {
+                                error!("Failed to fetch the book of {}: {}", product_id, source);
+                                true
+                            }
+                        },
+                    };
+
+                    if retry {
+                        fetch = Some(Box::pin(
+                            self.fetch_book_l3(product_id.clone(), L3_RESEED_DELAY),
+                        ));
+                    }
+                }
+                message = messages.recv() => {
+                    let message = match message {
+                        Some(message) => message,
+                        None => {
+                            debug!("Websocket feed ended, stopped order book of {}", product_id);
+                            return;
+                        }
+                    };
+
+                    if let Err(gap) = order_book.lock().await.apply_message(message) {
+                        error!(
+                            "Missed full channel messages {:?} of {}",
+                            gap.missing(),
+                            product_id
+                        );
+                        if fetch.is_none() {
+                            fetch = Some(Box::pin(
+                                self.fetch_book_l3(product_id.clone(), Duration::ZERO),
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        let unsubscribe = UnsubscribeBuilder::new()
+            .unsubscribe_from_full(product_id.clone())
+            .build();
+        if let Err(err) = self.send_unsubscribe(unsubscribe).await {
+            debug!("Failed to unsubscribe from full of {}: {}", product_id, err);
+        }
+    }
+
+    async fn fetch_book_l3(
+        &self,
+        product_id: String,
+        delay: Duration,
+    ) -> Result<Level3ProductBook, Error> {
+        tokio::time::sleep(delay).await;
+        self.get_product_book_l3(product_id).await
+    }
+
+    /// Subscribing again is the only way to have coinbase send a new level2 snapshot.
+    async fn resubscribe_order_book(&self, product_id: &str) -> Result<(), WebsocketError> {
+        let unsubscribe = UnsubscribeBuilder::new()
+            .unsubscribe_from_snapshot(product_id.to_string())
+            .build();
+        self.send_unsubscribe(unsubscribe).await?;
+
+        let subscription = SubscriptionBuilder::new()
+            .subscribe_to_snapshot(product_id.to_string())
+            .build();
+        self.send_subscribe(subscription).await
     }
-     */
 }
@@ -1,4 +1,5 @@
 pub mod accounts;
 pub mod orders;
 pub mod products;
+pub mod time;
 pub mod websocket;
@@ -161,7 +161,14 @@ pub enum CancelAfter {
     Day,
 }
 
-pub trait CoinbaseOrder {}
+pub trait CoinbaseOrder {
+    /// Client supplied order id. Orders carrying one can safely be resent because coinbase will
+    /// reject a duplicate instead of placing a second order.
+    /// Orders without one are sent only once.
+    fn client_oid(&self) -> &Option<String> {
+        &None
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MarketOrder {
@@ -173,7 +180,11 @@ pub struct MarketOrder {
     client_oid: Option<String>,
 }
 
-impl CoinbaseOrder for MarketOrder {}
+impl CoinbaseOrder for MarketOrder {
+    fn client_oid(&self) -> &Option<String> {
+        &self.client_oid
+    }
+}
 
 impl MarketOrder {
     pub fn new(product_id: String, side: Side, value: MarketOrderValue) -> Self {
@@ -186,6 +197,11 @@ impl MarketOrder {
             client_oid: None,
         }
     }
+
+    pub fn set_client_oid(mut self, client_oid: Option<String>) -> Self {
+        self.client_oid = client_oid;
+        self
+    }
 }
 
 pub fn not(value: &bool) -> bool {
@@ -214,7 +230,11 @@ pub struct LimitOrder {
     post_only: bool,
 }
 
-impl CoinbaseOrder for LimitOrder {}
+impl CoinbaseOrder for LimitOrder {
+    fn client_oid(&self) -> &Option<String> {
+        &self.client_oid
+    }
+}
 
 impl LimitOrder {
     pub fn new(product_id: String, side: Side, price: f64, size: f64) -> Self {
@@ -241,6 +261,11 @@ impl LimitOrder {
         &self.profile_id
     }
 
+    pub fn set_client_oid(mut self, client_oid: Option<String>) -> Self {
+        self.client_oid = client_oid;
+        self
+    }
+
     pub fn set_self_trade_prevention(
         mut self,
         self_trade_prevention: Option<SelfTradePrevention>,
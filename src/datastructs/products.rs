@@ -1,10 +1,14 @@
-use chrono::NaiveDateTime;
+use chrono::{
+    DateTime,
+    NaiveDateTime,
+};
 use serde::{
     Deserialize,
     Serialize,
 };
 
 use crate::deserialization::{
+    iso_date_time,
     option_iso_date_time,
     option_string_as_float,
     string_as_float,
@@ -93,6 +97,39 @@ pub struct ProductBook {
     pub auction: Option<Auction>,
 }
 
+/// # Level 3 Product Book Data
+/// Every open order of a product, returned by [/products/{product_id}/book?level=3](https://api.exchange.coinbase.com/products/{product_id}/book?level=3).
+///
+/// CBPro API reference: [Product Book](https://docs.cloud.coinbase.com/exchange/reference/exchangerestapi_getproductbook).
+///
+///
+/// # JSON Input Example
+///
+/// ```ignore
+/// {
+///     "bids": [["1955.80", "0.19478896", "dbeb625b-42cb-4559-af17-225b96aa674c"]],
+///     "asks": [["1955.81", "0.00130538", "145110f7-362c-48d2-a7d0-a407918775dd"]],
+///     "sequence": 29892914008
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Level3ProductBook {
+    pub bids: Vec<Level3BookOrder>,
+    pub asks: Vec<Level3BookOrder>,
+    pub sequence: u64,
+}
+
+/// A single open order of a [Level3ProductBook].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Level3BookOrder {
+    #[serde(with = "string_as_float")]
+    pub price: f64,
+    #[serde(with = "string_as_float")]
+    pub size: f64,
+    pub order_id: String,
+}
+
 /// # Currency Data
 /// A strongly typed representation of currency data returned by [/currencies](https://api.exchange.coinbase.com/currencies) and [/currencies/{currency_id}](https://api.exchange.coinbase.com/currencies/{currency_id}).
 ///
@@ -237,3 +274,156 @@ pub struct Auction {
     #[serde(with = "option_iso_date_time")]
     pub time: Option<NaiveDateTime>,
 }
+
+/// # Candle Data
+/// A strongly typed representation of a single candle returned by [/products/{product_id}/candles](https://api.exchange.coinbase.com/products/{product_id}/candles).
+///
+/// CBPro API reference: [Product Candles](https://docs.cloud.coinbase.com/exchange/reference/exchangerestapi_getproductcandles).
+///
+/// Coinbase sends each candle as an array of `[time, low, high, open, close, volume]`
+/// where time is the start of the bucket in unix seconds.
+///
+/// # JSON Input Example
+///
+/// ```ignore
+/// [1652918400, 1915.51, 2020.0, 1930.0, 1958.18, 163658.03343361]
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "CandleArray", into = "CandleArray")]
+pub struct Candle {
+    pub time: NaiveDateTime,
+    pub low: f64,
+    pub high: f64,
+    pub open: f64,
+    pub close: f64,
+    pub volume: f64,
+}
+
+type CandleArray = (i64, f64, f64, f64, f64, f64);
+
+impl From<CandleArray> for Candle {
+    fn from((time, low, high, open, close, volume): CandleArray) -> Self {
+        Self {
+            time: DateTime::from_timestamp(time, 0)
+                .unwrap_or_default()
+                .naive_utc(),
+            low,
+            high,
+            open,
+            close,
+            volume,
+        }
+    }
+}
+
+impl From<Candle> for CandleArray {
+    fn from(candle: Candle) -> Self {
+        (
+            candle.time.and_utc().timestamp(),
+            candle.low,
+            candle.high,
+            candle.open,
+            candle.close,
+            candle.volume,
+        )
+    }
+}
+
+/// # Product Ticker Data
+/// A strongly typed representation of the ticker returned by [/products/{product_id}/ticker](https://api.exchange.coinbase.com/products/{product_id}/ticker).
+///
+/// CBPro API reference: [Product Ticker](https://docs.cloud.coinbase.com/exchange/reference/exchangerestapi_getproductticker).
+///
+///
+/// # JSON Input Example
+///
+/// ```ignore
+///{
+///     "ask": "1958.37",
+///     "bid": "1958.18",
+///     "volume": "163658.03343361",
+///     "trade_id": 280884307,
+///     "price": "1958.18",
+///     "size": "0.001",
+///     "time": "2022-05-25T13:06:56.076339Z"
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProductTicker {
+    #[serde(with = "string_as_float")]
+    pub ask: f64,
+    #[serde(with = "string_as_float")]
+    pub bid: f64,
+    #[serde(with = "string_as_float")]
+    pub volume: f64,
+    pub trade_id: u64,
+    #[serde(with = "string_as_float")]
+    pub price: f64,
+    #[serde(with = "string_as_float")]
+    pub size: f64,
+    #[serde(with = "iso_date_time")]
+    pub time: NaiveDateTime,
+}
+
+/// # Product Stats Data
+/// A strongly typed representation of the 24 hour stats returned by [/products/{product_id}/stats](https://api.exchange.coinbase.com/products/{product_id}/stats).
+///
+/// CBPro API reference: [Product Stats](https://docs.cloud.coinbase.com/exchange/reference/exchangerestapi_getproductstats).
+///
+///
+/// # JSON Input Example
+///
+/// ```ignore
+///{
+///     "open": "1930",
+///     "high": "2020",
+///     "low": "1909.51",
+///     "last": "1958.18",
+///     "volume": "163658.03343361",
+///     "volume_30day": "6409735.37447281"
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProductStats {
+    #[serde(with = "string_as_float")]
+    pub open: f64,
+    #[serde(with = "string_as_float")]
+    pub high: f64,
+    #[serde(with = "string_as_float")]
+    pub low: f64,
+    #[serde(with = "string_as_float")]
+    pub last: f64,
+    #[serde(with = "string_as_float")]
+    pub volume: f64,
+    #[serde(with = "string_as_float")]
+    pub volume_30day: f64,
+}
+
+/// # Trade Data
+/// A strongly typed representation of a single trade returned by [/products/{product_id}/trades](https://api.exchange.coinbase.com/products/{product_id}/trades).
+///
+/// CBPro API reference: [Product Trades](https://docs.cloud.coinbase.com/exchange/reference/exchangerestapi_getproducttrades).
+///
+///
+/// # JSON Input Example
+///
+/// ```ignore
+///{
+///     "time": "2022-05-25T13:06:56.076Z",
+///     "trade_id": 280884307,
+///     "price": "1958.18",
+///     "size": "0.001",
+///     "side": "sell"
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Trade {
+    #[serde(with = "iso_date_time")]
+    pub time: NaiveDateTime,
+    pub trade_id: u64,
+    #[serde(with = "string_as_float")]
+    pub price: f64,
+    #[serde(with = "string_as_float")]
+    pub size: f64,
+    pub side: String,
+}
@@ -0,0 +1,27 @@
+use chrono::NaiveDateTime;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::deserialization::iso_date_time;
+
+/// # Server Time Data
+/// A strongly typed representation of the server time returned by [/time](https://api.exchange.coinbase.com/time).
+///
+/// CBPro API reference: [Time](https://docs.cloud.coinbase.com/exchange/reference/exchangerestapi_gettime).
+///
+/// # JSON Input Example
+///
+/// ```ignore
+///{
+///     "iso": "2015-01-07T23:47:25.201Z",
+///     "epoch": 1420674445.201
+///}
+///```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerTime {
+    #[serde(with = "iso_date_time")]
+    pub iso: NaiveDateTime,
+    pub epoch: f64,
+}
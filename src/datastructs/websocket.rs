@@ -1,8 +1,14 @@
+use std::fmt::{
+    Debug,
+    Formatter,
+};
+
 use serde::{
     Deserialize,
     Serialize,
 };
 
+use crate::api::APIKeyData;
 use crate::deserialization::{
     iso_date_time,
     string_as_float,
@@ -33,6 +39,7 @@ use crate::deserialization::{
 #[non_exhaustive]
 pub enum WebsocketMessage {
     Subscribe(SubscribeRequest),
+    Unsubscribe(UnsubscribeRequest),
     Heartbeat(HeartbeatMessage),
     Subscriptions(SubscriptionsMessage),
     Status(StatusMessage),
@@ -45,6 +52,65 @@ pub enum WebsocketMessage {
     Done(DoneMessage),
     Change(ChangeMessage),
     Activate(ActivateMessage),
+    /// Most recent match sent by the matches channel when subscribing.
+    #[serde(rename = "last_match")]
+    LastMatch(MatchMessage),
+    Auction(AuctionMessage),
+    #[serde(rename = "rfq_match")]
+    RfqMatch(RfqMatchMessage),
+    /// Sent instead of a subscriptions message when a subscription is rejected.
+    Error(ErrorMessage),
+    /// A ping control frame from the server, already answered with a pong.
+    #[serde(skip)]
+    Ping(Vec<u8>),
+    /// The websocket dropped and was reopened with every active subscription re-sent.
+    /// Messages sent while disconnected are lost.
+    #[serde(skip)]
+    Reconnected,
+}
+
+impl WebsocketMessage {
+    /// Product the message is about, None for messages such as status or subscriptions.
+    pub fn product_id(&self) -> Option<&str> {
+        match self {
+            WebsocketMessage::Heartbeat(message) => Some(&message.product_id),
+            WebsocketMessage::Ticker(message) => Some(&message.product_id),
+            WebsocketMessage::Snapshot(message) => Some(&message.product_id),
+            WebsocketMessage::L2Update(message) => Some(&message.product_id),
+            WebsocketMessage::Received(message) => Some(&message.product_id),
+            WebsocketMessage::Open(message) => Some(&message.product_id),
+            WebsocketMessage::Match(message) => Some(&message.product_id),
+            WebsocketMessage::Done(message) => Some(&message.product_id),
+            WebsocketMessage::Change(message) => Some(&message.product_id),
+            WebsocketMessage::Activate(message) => Some(&message.product_id),
+            WebsocketMessage::LastMatch(message) => Some(&message.product_id),
+            WebsocketMessage::Auction(message) => Some(&message.product_id),
+            WebsocketMessage::RfqMatch(message) => Some(&message.product_id),
+            WebsocketMessage::Subscribe(_)
+            | WebsocketMessage::Unsubscribe(_)
+            | WebsocketMessage::Subscriptions(_)
+            | WebsocketMessage::Status(_)
+            | WebsocketMessage::Error(_)
+            | WebsocketMessage::Ping(_)
+            | WebsocketMessage::Reconnected => None,
+        }
+    }
+
+    /// Per product sequence number of the message, None for messages without one.
+    pub fn sequence(&self) -> Option<u64> {
+        match self {
+            WebsocketMessage::Heartbeat(message) => Some(message.sequence),
+            WebsocketMessage::Ticker(message) => Some(message.sequence),
+            WebsocketMessage::Received(message) => Some(message.sequence),
+            WebsocketMessage::Open(message) => Some(message.sequence),
+            WebsocketMessage::Match(message) => Some(message.sequence),
+            WebsocketMessage::Done(message) => Some(message.sequence),
+            WebsocketMessage::Change(message) => Some(message.sequence),
+            WebsocketMessage::LastMatch(message) => Some(message.sequence),
+            WebsocketMessage::Auction(message) => Some(message.sequence),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -57,11 +123,194 @@ pub enum Channel {
     Ticker(TickerChannel),
     Level2(Level2Channel),
     Full(FullChannel),
+    /// Full channel messages for the authenticated user's own orders, requires a signed subscription.
+    User(UserChannel),
+    /// Only the `match` messages of the full channel, plus a `last_match` when subscribing.
+    Matches(MatchesChannel),
+    /// Level2 updates batched every 50 milliseconds.
+    #[serde(rename = "level2_batch")]
+    Level2Batch(Level2BatchChannel),
+    /// Ticker messages batched every 5 seconds.
+    #[serde(rename = "ticker_batch")]
+    TickerBatch(TickerBatchChannel),
+    /// `auction` messages while a product is in auction mode.
+    Auctionfeed(AuctionfeedChannel),
+    /// `rfq_match` messages for block trades, every product when no products are given.
+    #[serde(rename = "rfq_matches")]
+    RfqMatches(RfqMatchesChannel),
 }
 
+impl Channel {
+    /// Name coinbase uses for the channel.
+    pub fn name(&self) -> &str {
+        match self {
+            Channel::Heartbeat(_) => "heartbeat",
+            Channel::Status(_) => "status",
+            Channel::Ticker(_) => "ticker",
+            Channel::Level2(_) => "level2",
+            Channel::Full(_) => "full",
+            Channel::User(_) => "user",
+            Channel::Matches(_) => "matches",
+            Channel::Level2Batch(_) => "level2_batch",
+            Channel::TickerBatch(_) => "ticker_batch",
+            Channel::Auctionfeed(_) => "auctionfeed",
+            Channel::RfqMatches(_) => "rfq_matches",
+        }
+    }
+
+    /// Products covered by the channel, empty for channels that are not per product.
+    pub fn product_ids(&self) -> &[String] {
+        match self {
+            Channel::Heartbeat(channel) => &channel.product_ids,
+            Channel::Status(_) => &[],
+            Channel::Ticker(channel) => &channel.product_ids,
+            Channel::Level2(channel) => &channel.product_ids,
+            Channel::Full(channel) => &channel.product_ids,
+            Channel::User(channel) => &channel.product_ids,
+            Channel::Matches(channel) => &channel.product_ids,
+            Channel::Level2Batch(channel) => &channel.product_ids,
+            Channel::TickerBatch(channel) => &channel.product_ids,
+            Channel::Auctionfeed(channel) => &channel.product_ids,
+            Channel::RfqMatches(channel) => &channel.product_ids,
+        }
+    }
+
+    fn product_ids_mut(&mut self) -> Option<&mut Vec<String>> {
+        match self {
+            Channel::Heartbeat(channel) => Some(&mut channel.product_ids),
+            Channel::Status(_) => None,
+            Channel::Ticker(channel) => Some(&mut channel.product_ids),
+            Channel::Level2(channel) => Some(&mut channel.product_ids),
+            Channel::Full(channel) => Some(&mut channel.product_ids),
+            Channel::User(channel) => Some(&mut channel.product_ids),
+            Channel::Matches(channel) => Some(&mut channel.product_ids),
+            Channel::Level2Batch(channel) => Some(&mut channel.product_ids),
+            Channel::TickerBatch(channel) => Some(&mut channel.product_ids),
+            Channel::Auctionfeed(channel) => Some(&mut channel.product_ids),
+            Channel::RfqMatches(channel) => Some(&mut channel.product_ids),
+        }
+    }
+}
+
+/// Signed request
+/// ```[ignore]
+/// {
+///     "type": "subscribe",
+///     "channels": [{ "name": "user", "product_ids": ["ETH-EUR"] }],
+///     "signature": "...",
+///     "key": "...",
+///     "passphrase": "...",
+///     "timestamp": "1652390402"
+/// }
+/// ```
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SubscribeRequest {
     pub channels: Vec<Channel>,
+    #[serde(flatten, default)]
+    pub auth: Option<WebsocketAuth>,
+    /// Kept so the signature can be renewed each time the subscription is sent.
+    #[serde(skip)]
+    credentials: Option<APIKeyData>,
+}
+
+/// Signature fields added to a subscribe message.
+/// The signature covers `timestamp + "GET" + "/users/self/verify"`, the same as a REST request.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WebsocketAuth {
+    pub signature: String,
+    pub key: String,
+    pub passphrase: String,
+    pub timestamp: String,
+}
+
+impl Debug for WebsocketAuth {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebsocketAuth")
+            .field("key", &self.key)
+            .field("timestamp", &self.timestamp)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SubscribeRequest {
+    pub fn new(channels: Vec<Channel>) -> Self {
+        SubscribeRequest {
+            channels,
+            auth: None,
+            credentials: None,
+        }
+    }
+
+    /// Sign the subscription so authenticated channels such as [Channel::User] are delivered.
+    pub fn sign(mut self, credentials: APIKeyData) -> Self {
+        self.credentials = Some(credentials);
+        self.refresh_signature(chrono::Utc::now().timestamp());
+        self
+    }
+
+    pub(crate) fn is_signed(&self) -> bool {
+        self.credentials.is_some()
+    }
+
+    /// Signs again with `timestamp`, coinbase rejects signatures more than 30 seconds away from
+    /// its own clock.
+    pub(crate) fn refresh_signature(&mut self, timestamp: i64) {
+        let credentials = match &self.credentials {
+            None => return,
+            Some(credentials) => credentials,
+        };
+
+        self.auth = crate::requests::signature(
+            &credentials.secret,
+            timestamp,
+            "GET",
+            "/users/self/verify",
+            "",
+        )
+        .map(|signature| WebsocketAuth {
+            signature,
+            key: credentials.key.clone(),
+            passphrase: credentials.passphrase.clone(),
+            timestamp: timestamp.to_string(),
+        });
+    }
+
+    /// Drops the channels and products named in the unsubscribe request.
+    /// Channels left without any products are removed entirely.
+    pub(crate) fn remove(&mut self, unsubscribe: &UnsubscribeRequest) {
+        for removed in &unsubscribe.channels {
+            self.channels.retain_mut(|channel| {
+                if channel.name() != removed.name() {
+                    return true;
+                }
+
+                // A channel named without products is dropped for every product.
+                if removed.product_ids().is_empty() {
+                    return false;
+                }
+
+                match channel.product_ids_mut() {
+                    None => false,
+                    Some(products) => {
+                        products.retain(|product| !removed.product_ids().contains(product));
+                        !products.is_empty()
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// Request
+/// ```[ignore]
+/// {
+///     "type": "unsubscribe",
+///     "channels": [{ "name": "heartbeat", "product_ids": ["ETH-EUR"] }]
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UnsubscribeRequest {
+    pub channels: Vec<Channel>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -89,6 +338,37 @@ pub struct FullChannel {
     pub product_ids: Vec<String>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UserChannel {
+    pub product_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MatchesChannel {
+    pub product_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Level2BatchChannel {
+    pub product_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TickerBatchChannel {
+    pub product_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuctionfeedChannel {
+    pub product_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RfqMatchesChannel {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub product_ids: Vec<String>,
+}
+
 /// // Request
 /// ```[ignore]
 /// {
@@ -99,6 +379,32 @@ pub struct FullChannel {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct StatusChannel {}
 
+/// Error message, sent when a request such as a subscription could not be handled.
+/// ```[ignore]
+/// {
+///     "type": "error",
+///     "message": "Failed to subscribe",
+///     "reason": "ETH-FOO is not a valid product"
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ErrorMessage {
+    pub message: String,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl ErrorMessage {
+    /// Whether the reason names `product_id`, errors carry no product id of their own.
+    pub fn mentions_product(&self, product_id: &str) -> bool {
+        self.reason.as_deref().is_some_and(|reason| {
+            reason
+                .split(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+                .any(|word| word.eq_ignore_ascii_case(product_id))
+        })
+    }
+}
+
 ///
 /// ```[ignore]
 /// {
@@ -432,6 +738,9 @@ pub struct ReceivedMessage {
     pub order_id: String,
     #[serde(flatten)]
     pub order: Order,
+    /// Only sent on the user channel.
+    pub user_id: Option<String>,
+    pub profile_id: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -484,6 +793,9 @@ pub struct OpenMessage {
     #[serde(with = "string_as_float")]
     pub remaining_size: f64,
     pub side: String,
+    /// Only sent on the user channel.
+    pub user_id: Option<String>,
+    pub profile_id: Option<String>,
 }
 
 ///
@@ -515,6 +827,9 @@ pub struct DoneMessage {
     #[serde(default)]
     #[serde(with = "string_as_float")]
     pub remaining_size: f64,
+    /// Only sent on the user channel.
+    pub user_id: Option<String>,
+    pub profile_id: Option<String>,
 }
 
 ///
@@ -546,6 +861,13 @@ pub struct MatchMessage {
     #[serde(with = "string_as_float")]
     pub price: f64,
     pub side: String,
+    /// The user and taker or maker fields are only sent on the user channel.
+    pub user_id: Option<String>,
+    pub profile_id: Option<String>,
+    pub taker_user_id: Option<String>,
+    pub taker_profile_id: Option<String>,
+    pub maker_user_id: Option<String>,
+    pub maker_profile_id: Option<String>,
 }
 
 ///
@@ -577,6 +899,9 @@ pub struct ChangeMessage {
     #[serde(with = "string_as_float")]
     pub price: f64,
     pub side: String,
+    /// Only sent on the user channel.
+    pub user_id: Option<String>,
+    pub profile_id: Option<String>,
 }
 
 /// # Websocket Activate Message
@@ -619,6 +944,74 @@ pub struct ActivateMessage {
     pub private: bool,
 }
 
+///
+/// ```[ignore]
+/// {
+///     "type": "auction",
+///     "product_id": "LTC-USD",
+///     "sequence": 3262786978,
+///     "auction_state": "collection",
+///     "best_bid_price": "333.98",
+///     "best_bid_size": "4.39088265",
+///     "best_ask_price": "333.99",
+///     "best_ask_size": "25.23542881",
+///     "open_price": "333.99",
+///     "open_size": "0.193",
+///     "can_open": "yes",
+///     "timestamp": "2015-11-14T20:46:03.511254Z"
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuctionMessage {
+    pub product_id: String,
+    pub sequence: u64,
+    pub auction_state: String,
+    #[serde(with = "string_as_float")]
+    pub best_bid_price: f64,
+    #[serde(with = "string_as_float")]
+    pub best_bid_size: f64,
+    #[serde(with = "string_as_float")]
+    pub best_ask_price: f64,
+    #[serde(with = "string_as_float")]
+    pub best_ask_size: f64,
+    #[serde(with = "string_as_float")]
+    pub open_price: f64,
+    #[serde(with = "string_as_float")]
+    pub open_size: f64,
+    pub can_open: String,
+    #[serde(with = "iso_date_time")]
+    pub timestamp: chrono::NaiveDateTime,
+}
+
+///
+/// ```[ignore]
+/// {
+///     "type": "rfq_match",
+///     "maker_order_id": "ac928c66-ca53-498f-9c13-a110027a60e8",
+///     "taker_order_id": "132fb6ae-456b-4654-b4e0-d681ac05cea1",
+///     "time": "2014-11-07T08:19:27.028459Z",
+///     "trade_id": 30,
+///     "product_id": "BTC-USD",
+///     "size": "5.23512",
+///     "price": "400.23",
+///     "side": "sell"
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RfqMatchMessage {
+    pub maker_order_id: String,
+    pub taker_order_id: String,
+    #[serde(with = "iso_date_time")]
+    pub time: chrono::NaiveDateTime,
+    pub trade_id: u64,
+    pub product_id: String,
+    #[serde(with = "string_as_float")]
+    pub size: f64,
+    #[serde(with = "string_as_float")]
+    pub price: f64,
+    pub side: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Quote {
     #[serde(with = "string_as_float")]
@@ -0,0 +1,213 @@
+use crate::datastructs::orders::Side;
+use crate::order_book::{
+    BookSide,
+    OrderBookEntry,
+};
+
+/// Basis points in one.
+const BPS: f64 = 10_000.0;
+
+/// Relative shortfall under which a level still covers the rest of an order, absorbs the rounding
+/// of `size * price`.
+const FILL_TOLERANCE: f64 = 1e-9;
+
+/// How much a market order fills, in the base currency or in quote currency funds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FillAmount {
+    Size(f64),
+    Funds(f64),
+}
+
+/// Result of walking a market order through one side of the book.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fill {
+    /// Base currency filled
+    pub size: f64,
+    /// Quote currency spent or received
+    pub funds: f64,
+    /// Volume weighted average price of the fill
+    pub average_price: f64,
+    /// Price of the level where the order was exhausted, or the last level when the side ran out
+    pub last_price: f64,
+    /// Distance of the average price from the best price, in basis points
+    pub slippage_bps: f64,
+    /// False when the side did not hold enough to fill the whole amount
+    pub complete: bool,
+}
+
+/// Size resting on each side of the book.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Depth {
+    pub bids: f64,
+    pub asks: f64,
+}
+
+impl Depth {
+    /// `(bids - asks) / (bids + asks)`, from -1 when only asks rest to 1 when only bids rest.
+    /// None when both sides are empty.
+    pub fn imbalance(&self) -> Option<f64> {
+        let total = self.bids + self.asks;
+        if total <= 0f64 {
+            return None;
+        }
+        Some((self.bids - self.asks) / total)
+    }
+}
+
+impl BookSide {
+    /// Size resting at `price` or better, bids at or above it and asks at or below it.
+    pub fn depth_to(&self, price: f64) -> f64 {
+        let side = self.side();
+        self.levels()
+            .take_while(|level| match side {
+                Side::BUY => level.price() >= price,
+                Side::SELL => level.price() <= price,
+            })
+            .map(|level| level.size())
+            .sum()
+    }
+
+    /// Walks a market order taking liquidity from this side from the best price outwards, a buy
+    /// walks the asks and a sell the bids.
+    ///
+    /// None when the side is empty, the amount is not a positive number or nothing could be
+    /// filled.
+    pub fn fill(&self, amount: FillAmount) -> Option<Fill> {
+        let best = self.best()?.price();
+        let mut remaining = match amount {
+            FillAmount::Size(remaining) | FillAmount::Funds(remaining) => remaining,
+        };
+        if !remaining.is_finite() || remaining <= 0f64 {
+            return None;
+        }
+
+        let mut size = 0f64;
+        let mut funds = 0f64;
+        let mut last_price = best;
+
+        for level in self.levels() {
+            last_price = level.price();
+            // What the level holds in the unit of `amount`
+            let available = match amount {
+                FillAmount::Size(_) => level.size(),
+                FillAmount::Funds(_) => level.size() * level.price(),
+            };
+
+            if remaining - available > remaining * FILL_TOLERANCE {
+                size += level.size();
+                funds += level.size() * level.price();
+                remaining -= available;
+                continue;
+            }
+
+            // The level covers the rest, take only what is left so no residue carries on.
+            match amount {
+                FillAmount::Size(_) => {
+                    size += remaining;
+                    funds += remaining * level.price();
+                }
+                FillAmount::Funds(_) => {
+                    size += remaining / level.price();
+                    funds += remaining;
+                }
+            }
+            remaining = 0f64;
+            break;
+        }
+
+        if size <= 0f64 {
+            return None;
+        }
+
+        let average_price = funds / size;
+        Some(Fill {
+            size,
+            funds,
+            average_price,
+            last_price,
+            slippage_bps: (average_price - best).abs() / best * BPS,
+            complete: remaining <= 0f64,
+        })
+    }
+}
+
+/// # Book Depth
+/// Queries on both sides of an order book, taken from one consistent view of the bids and asks.
+///
+/// Use [OrderBook::depth](crate::order_book::OrderBook::depth) to run queries while the book's
+/// locks are held, or build one from the guards of
+/// [OrderBook::bid_ask_locks](crate::order_book::OrderBook::bid_ask_locks).
+#[derive(Clone, Copy, Debug)]
+pub struct BookDepth<'a> {
+    bids: &'a BookSide,
+    asks: &'a BookSide,
+}
+
+impl<'a> BookDepth<'a> {
+    pub fn new(bids: &'a BookSide, asks: &'a BookSide) -> Self {
+        BookDepth { bids, asks }
+    }
+
+    pub fn bids(&self) -> &'a BookSide {
+        self.bids
+    }
+
+    pub fn asks(&self) -> &'a BookSide {
+        self.asks
+    }
+
+    pub fn best_bid(&self) -> Option<OrderBookEntry> {
+        self.bids.best()
+    }
+
+    pub fn best_ask(&self) -> Option<OrderBookEntry> {
+        self.asks.best()
+    }
+
+    /// Halfway between the best bid and ask, None when either side is empty.
+    pub fn mid_price(&self) -> Option<f64> {
+        Some((self.best_bid()?.price() + self.best_ask()?.price()) / 2.0)
+    }
+
+    /// Best ask minus best bid, None when either side is empty.
+    pub fn spread(&self) -> Option<f64> {
+        Some(self.best_ask()?.price() - self.best_bid()?.price())
+    }
+
+    /// Spread relative to the mid price, in basis points.
+    pub fn spread_bps(&self) -> Option<f64> {
+        Some(self.spread()? / self.mid_price()? * BPS)
+    }
+
+    /// Cumulative size of the levels within `bps` basis points of the mid price on each side.
+    /// None when either side is empty.
+    pub fn depth_within_bps(&self, bps: f64) -> Option<Depth> {
+        let mid = self.mid_price()?;
+        let distance = mid * bps / BPS;
+
+        Some(Depth {
+            bids: self.bids.depth_to(mid - distance),
+            asks: self.asks.depth_to(mid + distance),
+        })
+    }
+
+    /// [Depth::imbalance] of the levels within `bps` basis points of the mid price.
+    pub fn imbalance_within_bps(&self, bps: f64) -> Option<f64> {
+        self.depth_within_bps(bps)?.imbalance()
+    }
+
+    /// Walks a market order for `side` through the book, buys take from the asks and sells from
+    /// the bids. See [BookSide::fill].
+    pub fn fill(&self, side: Side, amount: FillAmount) -> Option<Fill> {
+        match side {
+            Side::BUY => self.asks.fill(amount),
+            Side::SELL => self.bids.fill(amount),
+        }
+    }
+
+    /// Price of the level where a market order for `side` would be exhausted, the last level of
+    /// the book when it cannot be filled completely.
+    pub fn exhaustion_price(&self, side: Side, amount: FillAmount) -> Option<f64> {
+        self.fill(side, amount).map(|fill| fill.last_price)
+    }
+}
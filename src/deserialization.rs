@@ -9,6 +9,8 @@ pub(crate) mod iso_date_time {
 
     // Returned format from coinbase 2022-01-20T18:38:25.055677Z
     const FORMAT: &'static str = "%Y-%m-%dT%H:%M:%S%.6fZ";
+    // Some endpoints (e.g. trades) return fewer fractional digits 2022-01-20T18:38:25.055Z
+    const PARSE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.fZ";
 
     // The signature of a serialize_with function must follow the pattern:
     //
@@ -37,7 +39,7 @@ pub(crate) mod iso_date_time {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        NaiveDateTime::parse_from_str(&s, FORMAT).map_err(serde::de::Error::custom)
+        NaiveDateTime::parse_from_str(&s, PARSE_FORMAT).map_err(serde::de::Error::custom)
     }
 }
 
@@ -0,0 +1,458 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{
+    AtomicBool,
+    AtomicU64,
+    Ordering,
+};
+use std::sync::{
+    Arc,
+    Mutex as StdMutex,
+};
+
+use async_trait::async_trait;
+use futures::{
+    Stream,
+    StreamExt,
+};
+use log::error;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+use crate::api::CBProAPI;
+use crate::datastructs::websocket::{
+    ActivateMessage,
+    AuctionMessage,
+    ChangeMessage,
+    DoneMessage,
+    ErrorMessage,
+    HeartbeatMessage,
+    Level2Snapshot,
+    Level2Update,
+    MatchMessage,
+    OpenMessage,
+    ReceivedMessage,
+    RfqMatchMessage,
+    StatusMessage,
+    SubscriptionsMessage,
+    TickerMessage,
+    WebsocketMessage,
+};
+use crate::errors::WebsocketError;
+
+/// What the dispatcher does with a message when a receiver's queue is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlowConsumerPolicy {
+    /// Discard the oldest queued message to make room, see [DispatchReceiver::dropped].
+    DropOldest,
+    /// Wait for the receiver to make room. Every other receiver waits with it.
+    Block,
+    /// Stop delivering to the receiver, it returns None once its queue is drained.
+    Disconnect,
+}
+
+/// Queue size and slow consumer handling shared by every receiver of a [MessageDispatcher].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DispatcherConfig {
+    pub capacity: usize,
+    pub policy: SlowConsumerPolicy,
+}
+
+impl Default for DispatcherConfig {
+    fn default() -> Self {
+        DispatcherConfig {
+            capacity: 1024,
+            policy: SlowConsumerPolicy::DropOldest,
+        }
+    }
+}
+
+/// A message type a [DispatchReceiver] can be registered for.
+pub trait DispatchMessage: Clone + Send + 'static {
+    /// The message carried by `message`, None when it is a different type.
+    fn from_websocket_message(message: &WebsocketMessage) -> Option<Self>;
+}
+
+/// Every message read from the websocket.
+impl DispatchMessage for WebsocketMessage {
+    fn from_websocket_message(message: &WebsocketMessage) -> Option<Self> {
+        Some(message.clone())
+    }
+}
+
+/// Marker received whenever the websocket was reopened, see `WebsocketMessage::Reconnected`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reconnected;
+
+impl DispatchMessage for Reconnected {
+    fn from_websocket_message(message: &WebsocketMessage) -> Option<Self> {
+        match message {
+            WebsocketMessage::Reconnected => Some(Reconnected),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! dispatch_message {
+    ($message_type:ty, $($variant:ident)|+) => {
+        impl DispatchMessage for $message_type {
+            fn from_websocket_message(message: &WebsocketMessage) -> Option<Self> {
+                match message {
+                    $(WebsocketMessage::$variant(inner) => Some(inner.clone()),)+
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+dispatch_message!(SubscriptionsMessage, Subscriptions);
+dispatch_message!(HeartbeatMessage, Heartbeat);
+dispatch_message!(StatusMessage, Status);
+dispatch_message!(TickerMessage, Ticker);
+dispatch_message!(Level2Snapshot, Snapshot);
+dispatch_message!(Level2Update, L2Update);
+dispatch_message!(ReceivedMessage, Received);
+dispatch_message!(OpenMessage, Open);
+dispatch_message!(MatchMessage, Match | LastMatch);
+dispatch_message!(DoneMessage, Done);
+dispatch_message!(ChangeMessage, Change);
+dispatch_message!(ActivateMessage, Activate);
+dispatch_message!(AuctionMessage, Auction);
+dispatch_message!(RfqMatchMessage, RfqMatch);
+dispatch_message!(ErrorMessage, Error);
+
+/// Bounded queue between the dispatch loop and a single receiver.
+struct Queue<T> {
+    items: StdMutex<VecDeque<T>>,
+    capacity: usize,
+    policy: SlowConsumerPolicy,
+    closed: AtomicBool,
+    dropped: AtomicU64,
+    readable: Notify,
+    writable: Notify,
+}
+
+impl<T> Queue<T> {
+    fn new(config: DispatcherConfig) -> Self {
+        Queue {
+            items: StdMutex::new(VecDeque::new()),
+            capacity: config.capacity.max(1),
+            policy: config.policy,
+            closed: AtomicBool::new(false),
+            dropped: AtomicU64::new(0),
+            readable: Notify::new(),
+            writable: Notify::new(),
+        }
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.readable.notify_one();
+        self.writable.notify_one();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Queues the item according to the policy, false once the queue no longer accepts items.
+    async fn push(&self, item: T) -> bool {
+        loop {
+            if self.is_closed() {
+                return false;
+            }
+
+            {
+                let mut items = self.items.lock().unwrap();
+                if items.len() < self.capacity {
+                    items.push_back(item);
+                    self.readable.notify_one();
+                    return true;
+                }
+
+                match self.policy {
+                    SlowConsumerPolicy::DropOldest => {
+                        items.pop_front();
+                        items.push_back(item);
+                        self.dropped.fetch_add(1, Ordering::SeqCst);
+                        self.readable.notify_one();
+                        return true;
+                    }
+                    SlowConsumerPolicy::Disconnect => {
+                        drop(items);
+                        self.close();
+                        return false;
+                    }
+                    SlowConsumerPolicy::Block => {}
+                }
+            }
+
+            // notify_one keeps a permit when nobody is waiting so a pop between the check above
+            // and this await is never missed.
+            self.writable.notified().await;
+        }
+    }
+
+    async fn pop(&self) -> Option<T> {
+        loop {
+            {
+                let mut items = self.items.lock().unwrap();
+                if let Some(item) = items.pop_front() {
+                    self.writable.notify_one();
+                    return Some(item);
+                }
+
+                if self.is_closed() {
+                    return None;
+                }
+            }
+
+            self.readable.notified().await;
+        }
+    }
+}
+
+/// A registered receiver as seen by the dispatch loop.
+#[async_trait]
+trait Route: Send + Sync {
+    /// Delivers the message if it passes the route's filters, false once the receiver is gone.
+    async fn deliver(&self, message: &WebsocketMessage) -> bool;
+
+    fn close(&self);
+}
+
+struct TypedRoute<T> {
+    product_ids: Vec<String>,
+    queue: Arc<Queue<T>>,
+}
+
+#[async_trait]
+impl<T: DispatchMessage> Route for TypedRoute<T> {
+    async fn deliver(&self, message: &WebsocketMessage) -> bool {
+        if self.queue.is_closed() {
+            return false;
+        }
+
+        let product_matches = match message.product_id() {
+            Some(product_id) => {
+                self.product_ids.is_empty() || self.product_ids.iter().any(|id| id == product_id)
+            }
+            None => true,
+        };
+        if !product_matches {
+            return true;
+        }
+
+        match T::from_websocket_message(message) {
+            None => true,
+            Some(item) => self.queue.push(item).await,
+        }
+    }
+
+    fn close(&self) {
+        self.queue.close();
+    }
+}
+
+/// Receives the messages of one type a [MessageDispatcher] routes to it.
+///
+/// Dropping the receiver unregisters it.
+pub struct DispatchReceiver<T> {
+    queue: Arc<Queue<T>>,
+}
+
+impl<T: DispatchMessage> DispatchReceiver<T> {
+    /// The next message, None once the dispatcher has stopped or disconnected this receiver and
+    /// every queued message has been received.
+    pub async fn recv(&mut self) -> Option<T> {
+        self.queue.pop().await
+    }
+
+    /// Messages discarded because the queue was full under [SlowConsumerPolicy::DropOldest].
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped.load(Ordering::SeqCst)
+    }
+
+    /// Every message routed to this receiver as a [Stream].
+    pub fn into_stream(self) -> impl Stream<Item = T> {
+        futures::stream::unfold(self, |mut receiver| async move {
+            receiver.recv().await.map(|item| (item, receiver))
+        })
+    }
+}
+
+impl<T> Drop for DispatchReceiver<T> {
+    fn drop(&mut self) {
+        self.queue.close();
+    }
+}
+
+/// # Websocket message dispatcher
+/// Owns the read loop of a [CBProAPI]'s websocket and fans every message out to the receivers
+/// registered for its type and product.
+///
+/// Messages that are not about a single product, such as `WebsocketMessage::Reconnected`, reach
+/// every receiver of their type whatever products it was registered for. Subscriptions are still
+/// sent through the [CBProAPI], with `send_subscribe` so the confirmation is dispatched as well.
+/// ## Example
+///```no_run
+/// use coinbase_pro::api::{CBProAPI, SubscriptionBuilder};
+/// use coinbase_pro::datastructs::websocket::TickerMessage;
+/// use coinbase_pro::dispatcher::{DispatcherConfig, MessageDispatcher};
+/// # async fn run() {
+/// let api = CBProAPI::default();
+/// api.send_subscribe(SubscriptionBuilder::new().subscribe_to_ticker("ETH-USD".to_string()).build())
+///     .await
+///     .unwrap();
+///
+/// let dispatcher = MessageDispatcher::new(&api, DispatcherConfig::default());
+/// let mut tickers = dispatcher.product_receiver::<TickerMessage>("ETH-USD".to_string());
+/// while let Some(ticker) = tickers.recv().await {
+///     println!("{}", ticker.price);
+/// }
+/// # }
+///```
+#[derive(Clone)]
+pub struct MessageDispatcher {
+    config: DispatcherConfig,
+    routes: Arc<StdMutex<Vec<Arc<dyn Route>>>>,
+    /// Set once the read loop has ended, receivers registered afterwards are closed immediately
+    stopped: Arc<AtomicBool>,
+    /// Shared by every clone, the read loop stops when the last clone drops it
+    task: Arc<StdMutex<Option<DispatchTask>>>,
+}
+
+impl MessageDispatcher {
+    /// Starts reading the websocket of `api` on a new task, the websocket must already be open.
+    ///
+    /// The task runs until [MessageDispatcher::stop] is called or the last clone of the
+    /// dispatcher is dropped.
+    pub fn new(api: &CBProAPI, config: DispatcherConfig) -> Self {
+        let dispatcher = MessageDispatcher {
+            config,
+            routes: Arc::new(StdMutex::new(Vec::new())),
+            stopped: Arc::new(AtomicBool::new(false)),
+            task: Arc::new(StdMutex::new(None)),
+        };
+
+        let handle = tokio::spawn(Self::dispatch(
+            api.websocket_stream(),
+            dispatcher.routes.clone(),
+            dispatcher.stopped.clone(),
+        ));
+        *dispatcher.task.lock().unwrap() = Some(DispatchTask {
+            handle,
+            routes: dispatcher.routes.clone(),
+            stopped: dispatcher.stopped.clone(),
+        });
+
+        dispatcher
+    }
+
+    /// A receiver for every message of type `T`.
+    pub fn receiver<T: DispatchMessage>(&self) -> DispatchReceiver<T> {
+        self.register(Vec::new())
+    }
+
+    /// A receiver for the messages of type `T` about `product_id`.
+    pub fn product_receiver<T: DispatchMessage>(
+        &self,
+        product_id: String,
+    ) -> DispatchReceiver<T> {
+        self.register(vec![product_id])
+    }
+
+    /// A receiver for the messages of type `T` about any of `product_ids`.
+    pub fn products_receiver<T: DispatchMessage>(
+        &self,
+        product_ids: Vec<String>,
+    ) -> DispatchReceiver<T> {
+        self.register(product_ids)
+    }
+
+    fn register<T: DispatchMessage>(&self, product_ids: Vec<String>) -> DispatchReceiver<T> {
+        let queue = Arc::new(Queue::new(self.config));
+        let route = TypedRoute {
+            product_ids,
+            queue: queue.clone(),
+        };
+
+        let mut routes = self.routes.lock().unwrap();
+        if self.stopped.load(Ordering::SeqCst) {
+            queue.close();
+        } else {
+            routes.push(Arc::new(route));
+        }
+
+        DispatchReceiver { queue }
+    }
+
+    /// Stops the read loop, every receiver returns None once its queue is drained.
+    /// The websocket itself stays open.
+    pub fn stop(&self) {
+        // Dropping the task aborts it and closes the receivers.
+        self.task.lock().unwrap().take();
+    }
+
+    /// True once the read loop has ended, either through [MessageDispatcher::stop] or because
+    /// the websocket could not be read any more.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    async fn dispatch(
+        messages: impl Stream<Item = Result<WebsocketMessage, WebsocketError>>,
+        routes: Arc<StdMutex<Vec<Arc<dyn Route>>>>,
+        stopped: Arc<AtomicBool>,
+    ) {
+        let mut messages = Box::pin(messages);
+
+        while let Some(message) = messages.next().await {
+            let message = match message {
+                Ok(message) => message,
+                Err(err) => {
+                    error!("Dispatcher failed to read websocket message: {}", err);
+                    continue;
+                }
+            };
+
+            // Delivering may wait on a blocked receiver so the routes are not locked meanwhile.
+            let current = routes.lock().unwrap().clone();
+            let mut disconnected = Vec::new();
+            for route in current {
+                if !route.deliver(&message).await {
+                    disconnected.push(route);
+                }
+            }
+
+            if !disconnected.is_empty() {
+                routes
+                    .lock()
+                    .unwrap()
+                    .retain(|route| !disconnected.iter().any(|gone| Arc::ptr_eq(route, gone)));
+            }
+        }
+
+        Self::close_routes(&routes, &stopped);
+    }
+
+    fn close_routes(routes: &StdMutex<Vec<Arc<dyn Route>>>, stopped: &AtomicBool) {
+        let mut routes = routes.lock().unwrap();
+        stopped.store(true, Ordering::SeqCst);
+        routes.drain(..).for_each(|route| route.close());
+    }
+}
+
+/// The read loop of a [MessageDispatcher], aborted when dropped.
+struct DispatchTask {
+    handle: JoinHandle<()>,
+    routes: Arc<StdMutex<Vec<Arc<dyn Route>>>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Drop for DispatchTask {
+    fn drop(&mut self) {
+        self.handle.abort();
+        MessageDispatcher::close_routes(&self.routes, &self.stopped);
+    }
+}
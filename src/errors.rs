@@ -6,6 +6,7 @@ use std::fmt::{
     Display,
     Formatter,
 };
+use std::time::Duration;
 
 use hmac::digest::InvalidLength;
 use serde::{
@@ -14,6 +15,35 @@ use serde::{
 };
 use thiserror::Error;
 
+/// Errors returned by the REST api.
+///
+/// Non-success HTTP statuses that callers commonly handle differently have their own variants,
+/// each carrying the [HttpErrorData] of the response.
+///
+/// ```
+/// use std::collections::HashMap;
+///
+/// use coinbase_pro::errors::{
+///     Error,
+///     HttpErrorData,
+/// };
+///
+/// fn worth_retrying(error: &Error) -> bool {
+///     matches!(error, Error::RateLimited(_) | Error::ServerUnavailable(_))
+/// }
+///
+/// let error = Error::RateLimited(Box::new(HttpErrorData {
+///     status: 429,
+///     path: "/products/ETH-USD/ticker".to_string(),
+///     headers: HashMap::new(),
+///     message: Some("Public rate limit exceeded".to_string()),
+///     body: String::new(),
+/// }));
+///
+/// assert!(worth_retrying(&error));
+/// assert_eq!(error.http_error_data().unwrap().status, 429);
+/// assert!(!worth_retrying(&Error::InvalidGranularity(7)));
+/// ```
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("HTTP request error")]
@@ -51,6 +81,58 @@ pub enum Error {
 
     #[error("Invalid length of secret string")]
     InvalidSecretLength(#[from] InvalidLength),
+
+    #[error("Invalid candle granularity: {0} seconds")]
+    InvalidGranularity(u64),
+
+    #[error("Invalid page count: {0}, at least one page must be requested")]
+    InvalidPageCount(usize),
+
+    #[error("Rate limited by Coinbase Pro server: {0}")]
+    RateLimited(Box<HttpErrorData>),
+
+    #[error("Unauthorized request: {0}")]
+    Unauthorized(Box<HttpErrorData>),
+
+    #[error("Resource not found: {0}")]
+    NotFound(Box<HttpErrorData>),
+
+    #[error("Coinbase Pro server unavailable: {0}")]
+    ServerUnavailable(Box<HttpErrorData>),
+
+    #[error("Unexpected HTTP status: {0}")]
+    UnexpectedStatus(Box<HttpErrorData>),
+}
+
+impl Error {
+    /// Map a non-success HTTP response onto the matching error variant.
+    /// Statuses without a dedicated variant fall back on the server's error message when one was
+    /// returned.
+    pub(crate) fn from_http_response(data: HttpErrorData) -> Self {
+        let data = Box::new(data);
+        match data.status {
+            429 => Error::RateLimited(data),
+            401 | 403 => Error::Unauthorized(data),
+            404 => Error::NotFound(data),
+            500..=599 => Error::ServerUnavailable(data),
+            _ => match data.message.clone() {
+                Some(message) => Error::CBProServerErrorVariant(CBProServerError { message }),
+                None => Error::UnexpectedStatus(data),
+            },
+        }
+    }
+
+    /// The HTTP response details of a status error, if this is one.
+    pub fn http_error_data(&self) -> Option<&HttpErrorData> {
+        match self {
+            Error::RateLimited(data)
+            | Error::Unauthorized(data)
+            | Error::NotFound(data)
+            | Error::ServerUnavailable(data)
+            | Error::UnexpectedStatus(data) => Some(data.as_ref()),
+            _ => None,
+        }
+    }
 }
 
 impl From<reqwest::Error> for Error {
@@ -137,6 +219,32 @@ impl Display for WebsocketFrameSizeErrorData {
     }
 }
 
+/// Details of a non-success HTTP response returned by the Coinbase Pro REST API.
+///
+/// `headers` only holds the response headers that are useful for handling the error such as
+/// `retry-after`. `message` is the server's error message when the body was a standard Coinbase Pro
+/// error message.
+#[derive(Debug, Clone)]
+pub struct HttpErrorData {
+    pub status: u16,
+    pub path: String,
+    pub headers: HashMap<String, String>,
+    pub message: Option<String>,
+    pub body: String,
+}
+
+impl Display for HttpErrorData {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} returned {}: {}",
+            self.path,
+            self.status,
+            self.message.as_deref().unwrap_or(self.body.as_str())
+        )
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Error, Clone)]
 #[error("Error from coinbase server: {message}")]
 pub struct CBProServerError {
@@ -156,42 +264,74 @@ pub enum WebsocketError {
     NoWebsocketConnectionError,
     ParseError(SerdeJSONParseError),
     URLParseError {
-        source: Box<dyn std::error::Error + 'static>,
+        source: Box<dyn std::error::Error + Send + Sync + 'static>,
         url: String,
     },
     WebsocketIOError {
-        source: Box<dyn std::error::Error + 'static>,
+        source: Box<dyn std::error::Error + Send + Sync + 'static>,
         #[cfg(backtrace)]
         backtrace: Backtrace,
         context: Option<HashMap<String, String>>,
     },
     WebsocketInitializationError(String),
     SocketAddressError {
-        source: Box<dyn std::error::Error + 'static>,
+        source: Box<dyn std::error::Error + Send + Sync + 'static>,
         url: String,
     },
     NoSocketAddressError {
         url: String,
     },
     TCPConnectionError {
-        source: Box<dyn std::error::Error + 'static>,
+        source: Box<dyn std::error::Error + Send + Sync + 'static>,
         url: String,
     },
     TLSConnectionError {
-        source: Box<dyn std::error::Error + 'static>,
+        source: Box<dyn std::error::Error + Send + Sync + 'static>,
         #[cfg(backtrace)]
         backtrace: Backtrace,
         url: String,
     },
     WebsocketConnectionError {
-        source: Box<dyn std::error::Error + 'static>,
+        source: Box<dyn std::error::Error + Send + Sync + 'static>,
         url: String,
     },
     WebsocketUpgradeError,
     NoDomainError {
         url: String,
     },
-    StdError(Box<dyn std::error::Error + 'static>),
+    StdError(Box<dyn std::error::Error + Send + Sync + 'static>),
+    /// The websocket was closed with the status code and reason carried by the close frame.
+    ConnectionClosed {
+        code: Option<u16>,
+        reason: String,
+    },
+    /// Coinbase's subscriptions message did not include these `channel:product` entries.
+    UnconfirmedSubscription {
+        missing: Vec<String>,
+    },
+    /// Coinbase answered a subscription with an error message.
+    SubscriptionRejected {
+        message: String,
+        reason: Option<String>,
+    },
+    /// The feed ended before the order book of the product could be seeded.
+    OrderBookSnapshotMissing {
+        product_id: String,
+    },
+    /// No snapshot or product book seeded the order book of the product within the timeout.
+    OrderBookSnapshotTimeout {
+        product_id: String,
+        timeout: Duration,
+    },
+    /// Fetching the book of the product to seed an order book failed.
+    OrderBookSeedError {
+        product_id: String,
+        source: Error,
+    },
+    /// Reading the server clock to sign a subscription failed.
+    ServerClockError {
+        source: Error,
+    },
 }
 
 impl From<std::io::Error> for WebsocketError {
@@ -205,8 +345,8 @@ impl From<std::io::Error> for WebsocketError {
     }
 }
 
-impl From<Box<dyn std::error::Error + 'static>> for WebsocketError {
-    fn from(error: Box<dyn std::error::Error + 'static>) -> Self {
+impl From<Box<dyn std::error::Error + Send + Sync + 'static>> for WebsocketError {
+    fn from(error: Box<dyn std::error::Error + Send + Sync + 'static>) -> Self {
         Self::StdError(error)
     }
 }
@@ -309,6 +449,35 @@ impl Display for WebsocketError {
             WebsocketError::NoDomainError { url } => {
                 format!("No domain found for url: {}", url)
             }
+            WebsocketError::ConnectionClosed { code, reason } => match code {
+                Some(code) => format!("Websocket closed with status {}: {}", code, reason),
+                None => "Websocket closed without a status code".to_string(),
+            },
+            WebsocketError::UnconfirmedSubscription { missing } => {
+                format!("Subscription not confirmed for: {}", missing.join(", "))
+            }
+            WebsocketError::SubscriptionRejected { message, reason } => match reason {
+                Some(reason) => format!("Subscription rejected: {}: {}", message, reason),
+                None => format!("Subscription rejected: {}", message),
+            },
+            WebsocketError::OrderBookSnapshotMissing { product_id } => {
+                format!("Websocket feed ended before the order book of {} was seeded", product_id)
+            }
+            WebsocketError::OrderBookSnapshotTimeout {
+                product_id,
+                timeout,
+            } => {
+                format!(
+                    "The order book of {} was not seeded within {:?}",
+                    product_id, timeout
+                )
+            }
+            WebsocketError::OrderBookSeedError { product_id, source } => {
+                format!("Failed to fetch the book of {}\nSource error: {}", product_id, source)
+            }
+            WebsocketError::ServerClockError { source } => {
+                format!("Failed to read the server clock to sign a subscription\nSource error: {}", source)
+            }
             _ => "Unimplemented Websocket Error".to_string(),
         };
 
@@ -348,6 +517,8 @@ impl std::error::Error for WebsocketError {
             WebsocketError::TCPConnectionError { source, .. } => Some(&**source),
             WebsocketError::TLSConnectionError { source, .. } => Some(&**source),
             WebsocketError::WebsocketConnectionError { source, .. } => Some(&**source),
+            WebsocketError::OrderBookSeedError { source, .. } => Some(source),
+            WebsocketError::ServerClockError { source } => Some(source),
             _ => None,
         }
     }
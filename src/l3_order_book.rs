@@ -0,0 +1,366 @@
+use std::collections::{
+    BTreeMap,
+    HashMap,
+    VecDeque,
+};
+use std::sync::Arc;
+
+use log::error;
+use tokio::sync::oneshot::Sender;
+use tokio::sync::{
+    Mutex,
+    MutexGuard,
+};
+
+use crate::datastructs::orders::Side;
+use crate::datastructs::products::Level3ProductBook;
+use crate::datastructs::websocket::WebsocketMessage;
+use crate::order_book::{
+    BookSide,
+    OrderBook,
+    OrderBookEntry,
+    RealFloat,
+};
+use crate::sequence::{
+    SequenceFeed,
+    SequenceGap,
+};
+
+/// Messages held back behind a missing sequence number before the gap is reported.
+const REORDER_WINDOW: usize = 64;
+/// Messages buffered while the book waits to be seeded, older ones are dropped first.
+const MAX_BUFFERED: usize = 100_000;
+
+/// An order resting on an [L3OrderBook].
+#[derive(Clone, Debug, PartialEq)]
+pub struct L3Order {
+    pub order_id: String,
+    pub price: f64,
+    /// Size not yet filled
+    pub size: f64,
+}
+
+type Levels = BTreeMap<RealFloat, VecDeque<L3Order>>;
+
+/// # Level 3 Order Book
+/// Every open order of a product, built from a level 3 product book and kept up to date with the
+/// `open`, `done`, `match` and `change` messages of the full channel.
+///
+/// Messages are applied in sequence order. Messages received before the book is seeded are
+/// buffered, those already contained in the seed are dropped. A missing message makes the book
+/// unseeded again, it keeps its last levels until it is seeded with a newer product book.
+#[derive(Debug)]
+pub struct L3OrderBook {
+    product_id: String,
+    /// Sequence of the last message applied, None until the book is seeded
+    sequence: Option<u64>,
+    // Levels ordered least to greatest, each queue in time priority
+    bids: Levels,
+    asks: Levels,
+    /// Side and price of every resting order
+    orders: HashMap<String, (Side, RealFloat)>,
+    /// Messages waiting for the seed or for the messages before them, keyed by sequence
+    pending: BTreeMap<u64, WebsocketMessage>,
+}
+
+impl L3OrderBook {
+    pub fn new(product_id: String) -> Self {
+        L3OrderBook {
+            product_id,
+            sequence: None,
+            bids: Levels::new(),
+            asks: Levels::new(),
+            orders: HashMap::new(),
+            pending: BTreeMap::new(),
+        }
+    }
+
+    pub fn product_id(&self) -> &str {
+        &self.product_id
+    }
+
+    /// Sequence of the last message applied, None while the book is not seeded.
+    pub fn sequence(&self) -> Option<u64> {
+        self.sequence
+    }
+
+    pub fn is_seeded(&self) -> bool {
+        self.sequence.is_some()
+    }
+
+    /// Number of resting orders.
+    pub fn len(&self) -> usize {
+        self.orders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    pub fn order(&self, order_id: &str) -> Option<&L3Order> {
+        let (side, price) = self.orders.get(order_id)?;
+        self.levels_of(side)
+            .get(price)?
+            .iter()
+            .find(|order| order.order_id == order_id)
+    }
+
+    /// Orders resting at `price` in time priority.
+    pub fn orders_at(&self, side: Side, price: f64) -> Option<&VecDeque<L3Order>> {
+        let price = RealFloat::try_from(price).ok()?;
+        self.levels_of(&side).get(&price)
+    }
+
+    /// Price levels and their orders from the best price outwards.
+    pub fn levels(&self, side: Side) -> impl Iterator<Item = (f64, &VecDeque<L3Order>)> + '_ {
+        let levels: Box<dyn Iterator<Item = (&RealFloat, &VecDeque<L3Order>)>> = match side {
+            Side::BUY => Box::new(self.bids.iter().rev()),
+            Side::SELL => Box::new(self.asks.iter()),
+        };
+
+        levels.map(|(price, orders)| (price.clone().into(), orders))
+    }
+
+    /// The side aggregated to the size resting at each price, as in an [OrderBook].
+    pub fn book_side(&self, side: Side) -> BookSide {
+        let entries = self
+            .levels_of(&side)
+            .iter()
+            .filter_map(|(price, orders)| {
+                let size = orders.iter().map(|order| order.size).sum::<f64>();
+                OrderBookEntry::try_from((price.clone().into(), size)).ok()
+            })
+            .collect::<Vec<_>>();
+
+        BookSide::from_entries(side, entries)
+    }
+
+    /// The book aggregated to price levels.
+    pub fn to_order_book(&self) -> OrderBook {
+        OrderBook::new(self.book_side(Side::BUY), self.book_side(Side::SELL))
+    }
+
+    /// Replaces the orders with those of a level 3 product book and applies the buffered messages
+    /// that follow it.
+    ///
+    /// A gap is returned when the buffered messages start after the book's sequence, the book is
+    /// then left unseeded and should be seeded with a newer product book.
+    pub fn seed(&mut self, product_book: Level3ProductBook) -> Result<(), SequenceGap> {
+        self.bids.clear();
+        self.asks.clear();
+        self.orders.clear();
+
+        for order in product_book.bids {
+            self.insert_order(Side::BUY, order.order_id, order.price, order.size);
+        }
+        for order in product_book.asks {
+            self.insert_order(Side::SELL, order.order_id, order.price, order.size);
+        }
+
+        self.sequence = Some(product_book.sequence);
+        self.pending = self.pending.split_off(&(product_book.sequence + 1));
+        self.apply_pending();
+
+        if !self.pending.is_empty() {
+            return Err(self.unseed());
+        }
+        Ok(())
+    }
+
+    /// Applies a full channel message of the product, other messages are ignored.
+    ///
+    /// A gap is returned when a message is still missing after 64 later ones arrived, the book is
+    /// then unseeded and buffers messages until it is seeded again.
+    pub fn apply_message(&mut self, message: WebsocketMessage) -> Result<(), SequenceGap> {
+        let sequence = match self.full_channel_sequence(&message) {
+            None => return Ok(()),
+            Some(sequence) => sequence,
+        };
+
+        let latest = match self.sequence {
+            None => {
+                self.pending.insert(sequence, message);
+                if self.pending.len() > MAX_BUFFERED {
+                    self.pending.pop_first();
+                }
+                return Ok(());
+            }
+            Some(latest) => latest,
+        };
+
+        // Already applied, or contained in the seed.
+        if sequence <= latest {
+            return Ok(());
+        }
+
+        self.pending.insert(sequence, message);
+        self.apply_pending();
+
+        if self.pending.len() >= REORDER_WINDOW {
+            return Err(self.unseed());
+        }
+        Ok(())
+    }
+
+    fn full_channel_sequence(&self, message: &WebsocketMessage) -> Option<u64> {
+        match message {
+            WebsocketMessage::Received(_)
+            | WebsocketMessage::Open(_)
+            | WebsocketMessage::Done(_)
+            | WebsocketMessage::Match(_)
+            | WebsocketMessage::Change(_) => {}
+            _ => return None,
+        }
+
+        if message.product_id() != Some(self.product_id.as_str()) {
+            return None;
+        }
+        message.sequence()
+    }
+
+    /// Applies buffered messages for as long as they follow the book's sequence.
+    fn apply_pending(&mut self) {
+        while let Some(latest) = self.sequence {
+            let message = match self.pending.remove(&(latest + 1)) {
+                None => return,
+                Some(message) => message,
+            };
+
+            self.apply(message);
+            self.sequence = Some(latest + 1);
+        }
+    }
+
+    /// Marks the book unseeded, returning the gap between its sequence and the buffered messages.
+    fn unseed(&mut self) -> SequenceGap {
+        let latest = self.sequence.take().unwrap_or_default();
+        let next = self.pending.keys().next().copied().unwrap_or(latest + 1);
+
+        SequenceGap {
+            product_id: self.product_id.clone(),
+            feed: SequenceFeed::Full,
+            first_missing: latest + 1,
+            last_missing: next - 1,
+        }
+    }
+
+    fn apply(&mut self, message: WebsocketMessage) {
+        match message {
+            WebsocketMessage::Open(open) => match parse_side(&open.side) {
+                Some(side) => {
+                    self.insert_order(side, open.order_id, open.price, open.remaining_size)
+                }
+                None => error!("Skipping open message with unknown side: {}", open.side),
+            },
+            WebsocketMessage::Done(done) => self.remove_order(&done.order_id),
+            WebsocketMessage::Match(fill) => {
+                if let Some(maker) = self.order_mut(&fill.maker_order_id) {
+                    maker.size = (maker.size - fill.size).max(0f64);
+                }
+            }
+            WebsocketMessage::Change(change) => {
+                if let Some(order) = self.order_mut(&change.order_id) {
+                    order.size = change.new_size;
+                }
+            }
+            // Received orders are not on the book until they are opened.
+            _ => {}
+        }
+    }
+
+    fn insert_order(&mut self, side: Side, order_id: String, price: f64, size: f64) {
+        let level = match RealFloat::try_from(price) {
+            Ok(level) => level,
+            Err(_) => {
+                error!("Skipping order {} with invalid price {}", order_id, price);
+                return;
+            }
+        };
+
+        self.levels_of_mut(&side)
+            .entry(level.clone())
+            .or_default()
+            .push_back(L3Order {
+                order_id: order_id.clone(),
+                price,
+                size,
+            });
+        self.orders.insert(order_id, (side, level));
+    }
+
+    fn remove_order(&mut self, order_id: &str) {
+        let (side, price) = match self.orders.remove(order_id) {
+            None => return,
+            Some(location) => location,
+        };
+
+        let levels = self.levels_of_mut(&side);
+        if let Some(orders) = levels.get_mut(&price) {
+            orders.retain(|order| order.order_id != order_id);
+            if orders.is_empty() {
+                levels.remove(&price);
+            }
+        }
+    }
+
+    fn order_mut(&mut self, order_id: &str) -> Option<&mut L3Order> {
+        let (side, price) = self.orders.get(order_id)?.clone();
+        self.levels_of_mut(&side)
+            .get_mut(&price)?
+            .iter_mut()
+            .find(|order| order.order_id == order_id)
+    }
+
+    fn levels_of(&self, side: &Side) -> &Levels {
+        match side {
+            Side::BUY => &self.bids,
+            Side::SELL => &self.asks,
+        }
+    }
+
+    fn levels_of_mut(&mut self, side: &Side) -> &mut Levels {
+        match side {
+            Side::BUY => &mut self.bids,
+            Side::SELL => &mut self.asks,
+        }
+    }
+}
+
+fn parse_side(side: &str) -> Option<Side> {
+    match side {
+        "buy" => Some(Side::BUY),
+        "sell" => Some(Side::SELL),
+        _ => None,
+    }
+}
+
+/// # Live Level 3 Order Book
+/// An [L3OrderBook] kept up to date by a background task, returned by
+/// `CBProAPI::new_order_book_l3`.
+///
+/// Dropping the handle stops the task.
+#[derive(Debug)]
+pub struct LiveL3OrderBook {
+    order_book: Arc<Mutex<L3OrderBook>>,
+    /// Dropped with the handle, which tells the task to stop
+    shutdown: Sender<()>,
+}
+
+impl LiveL3OrderBook {
+    pub(crate) fn new(order_book: Arc<Mutex<L3OrderBook>>, shutdown: Sender<()>) -> Self {
+        LiveL3OrderBook {
+            order_book,
+            shutdown,
+        }
+    }
+
+    /// Locks the book, the task waits to apply messages until the guard is dropped.
+    pub async fn lock(&self) -> MutexGuard<'_, L3OrderBook> {
+        self.order_book.lock().await
+    }
+
+    /// False once the task stopped updating the book, after the websocket feed ended.
+    pub fn is_live(&self) -> bool {
+        !self.shutdown.is_closed()
+    }
+}
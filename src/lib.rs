@@ -59,22 +59,34 @@ extern crate core;
 
 pub mod api;
 mod deserialization;
-mod errors;
+pub mod errors;
 pub mod requests;
 mod websocket_lite;
 
 pub mod datastructs;
+pub mod depth;
+pub mod dispatcher;
 mod mocked;
+pub mod l3_order_book;
 pub mod order_book;
+pub mod sequence;
+pub mod watchdog;
 
 #[cfg(all(test, feature = "mock"))]
 mod tests {
 
     use std::ops::Deref;
+    use std::time::{
+        Duration,
+        Instant,
+    };
 
+    use chrono::NaiveDateTime;
+    use futures::StreamExt;
     use log::LevelFilter;
 
     use reqwest::header::HeaderValue;
+    use reqwest::StatusCode;
 
     use simple_logger::SimpleLogger;
     use tokio::io::{
@@ -85,16 +97,27 @@ mod tests {
     use crate::api::{
         APIKeyData,
         CBProAPI,
+        CBProAPIBuilder,
+        Granularity,
         Level,
+        RateLimit,
+        RateLimitedPool,
+        RateLimiter,
+        RateLimits,
+        RetryPolicy,
         SubscriptionBuilder,
     };
 
     use crate::datastructs::orders::{
+        LimitOrder,
         MarketOrder,
         MarketOrderValue,
         Side,
     };
-    use crate::errors::Error;
+    use crate::errors::{
+        Error,
+        HttpErrorData,
+    };
     use crate::mocked::{
         CallInfo,
         MockClient,
@@ -104,10 +127,18 @@ mod tests {
         MockTcpStream,
         MockTlsStream,
     };
+    use crate::requests::{
+        encode_query,
+        signature,
+        CBRequestBuilder,
+        Pagination,
+        RequestMethod,
+    };
 
     #[tokio::test]
     async fn mocked_api_coinbase_server_error() {
         let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
         let respone2 = MockResponse::new();
 
         respone1.expect_text().return_once(|| {
@@ -140,6 +171,7 @@ mod tests {
     #[tokio::test]
     async fn mocked_api_get_all_products_minimum_required_response() {
         let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
         let respone2 = MockResponse::new();
 
         respone1.expect_text().return_once(|| {
@@ -200,6 +232,7 @@ mod tests {
     #[tokio::test]
     async fn mocked_api_get_all_products_json_header() {
         let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
         let respone2 = MockResponse::new();
 
         respone1
@@ -218,8 +251,9 @@ mod tests {
 
         let header_calls: Vec<CallInfo> = mock_request_builder
             .call_info
-            .deref()
-            .take()
+            .lock()
+            .unwrap()
+            .split_off(0)
             .into_iter()
             .filter(|x| {
                 x.method_name == "header"
@@ -234,6 +268,7 @@ mod tests {
     #[tokio::test]
     async fn mocked_api_get_all_products_all_fields() {
         let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
         let respone2 = MockResponse::new();
 
         respone1.expect_text().return_once(|| {
@@ -282,6 +317,7 @@ mod tests {
     #[tokio::test]
     async fn mocked_api_get_product_minimum_required_response() {
         let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
         let respone2 = MockResponse::new();
 
         respone1.expect_text().return_once(|| {
@@ -339,6 +375,7 @@ mod tests {
     #[tokio::test]
     async fn mocked_api_get_product_path_check() {
         let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
         let respone2 = MockResponse::new();
 
         respone1
@@ -354,233 +391,312 @@ mod tests {
 
         assert!(mock_client
             .requested_url
-            .borrow_mut()
+            .lock()
+            .unwrap()
             .contains("/MyProduct"));
     }
 
     #[tokio::test]
-    async fn mocked_api_get_product_book_minimum_response() {
+    async fn mocked_api_get_product_ticker() {
         let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
         let respone2 = MockResponse::new();
 
         respone1.expect_text().return_once(|| {
             Ok(r#"
             {
-                "bids": [],
-                "asks": [],
-                "sequence": 100
-            }
-        "#
+                "ask": "1958.37",
+                "bid": "1958.18",
+                "volume": "163658.03343361",
+                "trade_id": 280884307,
+                "price": "1958.18",
+                "size": "0.001",
+                "time": "2022-05-25T13:06:56.076339Z"
+            }"#
             .to_string())
         });
 
         let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
-
         let mock_client = MockClient::new_mock(mock_request_builder.clone());
-        let api = CBProAPI::from_client(mock_client);
+        let api = CBProAPI::from_client(mock_client.clone());
 
-        let output = api
-            .get_product_book("MyProduct".to_string(), Some(Level::One))
-            .await
-            .unwrap();
+        let output = api.get_product_ticker("ETH-USD".to_string()).await.unwrap();
 
-        assert_eq!(output.bids.len(), 0);
-        assert_eq!(output.asks.len(), 0);
-        assert_eq!(output.sequence, 100.0);
-        assert_eq!(output.auction_mode, None);
-        assert!(output.auction.is_none());
+        assert_eq!(output.ask, 1958.37);
+        assert_eq!(output.bid, 1958.18);
+        assert_eq!(output.volume, 163658.03343361);
+        assert_eq!(output.trade_id, 280884307);
+        assert_eq!(output.price, 1958.18);
+        assert_eq!(output.size, 0.001);
+        assert!(mock_client
+            .requested_url
+            .lock()
+            .unwrap()
+            .contains("/products/ETH-USD/ticker"));
     }
 
     #[tokio::test]
-    async fn mocked_api_get_product_book_minimum_response_2() {
+    async fn mocked_api_get_product_stats() {
         let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
         let respone2 = MockResponse::new();
 
         respone1.expect_text().return_once(|| {
             Ok(r#"
             {
-                "bids": [{"price":"1","size":"1","num_orders":1}],
-                "asks": [{"price":"1","size":"1","num_orders":1}],
-                "sequence": 100,
-                "auction_mode": true,
-                "auction": {
-                    "open_price": "0.1",
-                    "open_size": "0.1",
-                    "best_bid_price": "0.1",
-                    "best_bid_size": "0.1",
-                    "best_ask_price": "0.1",
-                    "best_ask_size": "0.1",
-                    "auction_state": "0.1"
-                }
-            }
-        "#
+                "open": "1930",
+                "high": "2020",
+                "low": "1909.51",
+                "last": "1958.18",
+                "volume": "163658.03343361",
+                "volume_30day": "6409735.37447281"
+            }"#
             .to_string())
         });
 
         let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client.clone());
+
+        let output = api.get_product_stats("ETH-USD".to_string()).await.unwrap();
+
+        assert_eq!(output.open, 1930.0);
+        assert_eq!(output.high, 2020.0);
+        assert_eq!(output.low, 1909.51);
+        assert_eq!(output.last, 1958.18);
+        assert_eq!(output.volume, 163658.03343361);
+        assert_eq!(output.volume_30day, 6409735.37447281);
+        assert!(mock_client
+            .requested_url
+            .lock()
+            .unwrap()
+            .contains("/products/ETH-USD/stats"));
+    }
+
+    #[tokio::test]
+    async fn mocked_api_get_product_trades() {
+        let respone1 = MockResponse::new();
+        let mut respone2 = MockResponse::new();
+        respone2.expect_status().return_const(StatusCode::OK);
+        let mut headers = MockHeaderMap::new();
+
+        headers.expect_get::<&str>().return_const(Some(&HEADER));
+
+        respone2.expect_text().return_once(|| {
+            Ok(r#"[
+                {
+                    "time": "2022-05-25T13:06:56.076Z",
+                    "trade_id": 280884307,
+                    "price": "1958.18",
+                    "size": "0.001",
+                    "side": "sell"
+                }
+            ]"#
+            .to_string())
+        });
+        respone2.expect_headers().return_const(headers);
 
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
         let mock_client = MockClient::new_mock(mock_request_builder.clone());
-        let api = CBProAPI::from_client(mock_client);
+        let api = CBProAPI::from_client(mock_client.clone());
 
         let output = api
-            .get_product_book("MyProduct".to_string(), Some(Level::One))
+            .get_product_trades("ETH-USD".to_string(), Some("280884400".to_string()), 5)
             .await
             .unwrap();
 
-        assert_eq!(output.bids.len(), 1);
-        assert_eq!(output.asks.len(), 1);
-        assert_eq!(output.sequence, 100.0);
-        assert_eq!(output.auction_mode, Some(true));
-        assert!(output.auction.is_some());
-        assert_eq!(output.auction.as_ref().unwrap().open_price, 0.1);
-        assert_eq!(output.auction.as_ref().unwrap().open_size, 0.1);
-        assert_eq!(output.auction.as_ref().unwrap().best_bid_price, 0.1);
-        assert_eq!(output.auction.as_ref().unwrap().best_bid_size, 0.1);
-        assert_eq!(output.auction.as_ref().unwrap().best_ask_price, 0.1);
-        assert_eq!(output.auction.as_ref().unwrap().best_ask_size, 0.1);
-        assert_eq!(output.auction.as_ref().unwrap().auction_state, 0.1);
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].trade_id, 280884307);
+        assert_eq!(output[0].side, "sell");
+        assert!(mock_client
+            .requested_url
+            .lock()
+            .unwrap()
+            .contains("/products/ETH-USD/trades"));
+
+        let query_calls: Vec<CallInfo> = mock_request_builder
+            .call_info
+            .lock()
+            .unwrap()
+            .split_off(0)
+            .into_iter()
+            .filter(|x| x.method_name == "query")
+            .collect();
+
+        assert_eq!(1, query_calls.len());
+        assert!(query_calls[0].arguments[0]
+            .argument_value
+            .contains(r#"("after", "280884400")"#));
     }
 
     #[tokio::test]
-    async fn mocked_api_get_fees() {
-        let mut respone1 = MockResponse::new();
-        let respone2 = MockResponse::new();
+    async fn mocked_api_get_product_trades_page_limit() {
+        let respone1 = MockResponse::new();
+        let mut respone2 = MockResponse::new();
+        respone2.expect_status().return_const(StatusCode::OK);
+        let mut headers = MockHeaderMap::new();
 
-        respone1.expect_text().return_once(|| {
-            Ok(r#"
-            {
-                "taker_fee_rate": "0.1",
-                "maker_fee_rate": "0.1",
-                "usd_volume": "0.1"
-            }
-        "#
-            .to_string())
-        });
+        headers.expect_get::<&str>().return_const(Some(&HEADER));
 
-        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+        let trade = r#"{"time":"2022-05-25T13:06:56.076Z","trade_id":1,"price":"1","size":"1","side":"buy"}"#;
+        let full_page = format!("[{}]", vec![trade; 1000].join(","));
+        respone2.expect_text().return_once(|| Ok(full_page));
+        respone2.expect_headers().return_const(headers);
 
+        // A second page would pop respone1 which has no expectations set.
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
         let mock_client = MockClient::new_mock(mock_request_builder.clone());
         let api = CBProAPI::from_client(mock_client);
 
-        let account = APIKeyData {
-            key: base64::encode("API KEY"),
-            secret: base64::encode("API Secret"),
-            passphrase: "passphrase".to_string(),
-        };
+        let output = api
+            .get_product_trades("ETH-USD".to_string(), None, 1)
+            .await
+            .unwrap();
 
-        let output = api.get_fees(account).await.unwrap();
+        assert_eq!(output.len(), 1000);
+    }
 
-        assert_eq!(output.maker_fee_rate, 0.1);
-        assert_eq!(output.taker_fee_rate, 0.1);
-        assert_eq!(output.usd_volume, 0.1);
+    #[tokio::test]
+    async fn mocked_api_get_product_trades_rejects_zero_pages() {
+        // Any request would pop a response without expectations set.
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![MockResponse::new()]);
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client);
+
+        match api.get_product_trades("ETH-USD".to_string(), None, 0).await {
+            Err(Error::InvalidPageCount(pages)) => assert_eq!(pages, 0),
+            _ => unreachable!(),
+        }
     }
 
     #[tokio::test]
-    async fn mocked_api_get_accounts() {
+    async fn mocked_api_get_product_candles() {
         let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
         let respone2 = MockResponse::new();
 
         respone1.expect_text().return_once(|| {
             Ok(r#"[
-            {
-                "id": "id",
-                "currency": "currency",
-                "balance": "0.1",
-                "available": "0.1",
-                "hold": "0.1",
-                "profile_id": "profile_id",
-                "trading_enabled": false
-            }
-        ]"#
+                [1653004920, 1955.5, 1958.1, 1956.0, 1957.2, 12.5],
+                [1653004860, 1954.0, 1956.9, 1955.1, 1956.0, 10.25]
+            ]"#
             .to_string())
         });
 
         let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
-
         let mock_client = MockClient::new_mock(mock_request_builder.clone());
-        let api = CBProAPI::from_client(mock_client);
-
-        let account = APIKeyData {
-            key: base64::encode("API KEY"),
-            secret: base64::encode("API Secret"),
-            passphrase: "passphrase".to_string(),
-        };
+        let api = CBProAPI::from_client(mock_client.clone());
 
-        let output = api.get_accounts(account).await.unwrap();
+        let start =
+            NaiveDateTime::parse_from_str("2022-05-20 00:01:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let end = start + chrono::Duration::minutes(1);
 
-        let acct = output.get(0).unwrap();
+        let output = api
+            .get_product_candles("ETH-USD".to_string(), Granularity::OneMinute, start, end)
+            .await
+            .unwrap();
 
-        assert_eq!(output.len(), 1);
-        assert_eq!(acct.id, "id");
-        assert_eq!(acct.hold, 0.1);
+        assert_eq!(output.len(), 2);
+        assert_eq!(output[0].time, start);
+        assert_eq!(output[0].low, 1954.0);
+        assert_eq!(output[0].high, 1956.9);
+        assert_eq!(output[0].open, 1955.1);
+        assert_eq!(output[0].close, 1956.0);
+        assert_eq!(output[0].volume, 10.25);
+        assert_eq!(output[1].time, end);
+        assert!(mock_client
+            .requested_url
+            .lock()
+            .unwrap()
+            .contains("/products/ETH-USD/candles"));
     }
 
     #[tokio::test]
-    async fn mocked_api_get_account() {
+    async fn mocked_api_get_product_candles_chunked() {
         let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
         let respone2 = MockResponse::new();
+        let mut respone3 = MockResponse::new();
+        respone3.expect_status().return_const(StatusCode::OK);
+        let respone4 = MockResponse::new();
 
+        // Second chunk is requested first and overlaps the first chunk on its boundary.
         respone1.expect_text().return_once(|| {
-            Ok(r#"
-            {
-                "id": "id",
-                "currency": "currency",
-                "balance": "0.1",
-                "available": "0.1",
-                "hold": "0.1",
-                "profile_id": "profile_id",
-                "trading_enabled": false
-            }
-        "#
+            Ok(r#"[
+                [1653013200, 3.0, 3.0, 3.0, 3.0, 3.0],
+                [1653012000, 2.0, 2.0, 2.0, 2.0, 2.0]
+            ]"#
+            .to_string())
+        });
+        respone3.expect_text().return_once(|| {
+            Ok(r#"[
+                [1653012000, 2.0, 2.0, 2.0, 2.0, 2.0],
+                [1653004800, 1.0, 1.0, 1.0, 1.0, 1.0]
+            ]"#
             .to_string())
         });
 
-        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
-
-        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let first_chunk = MockRequestBuilder::new_mock(vec![respone3, respone4]);
+        let second_chunk = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+        let mock_client =
+            MockClient::new_mock_sequence(vec![first_chunk.clone(), second_chunk.clone()]);
         let api = CBProAPI::from_client(mock_client);
 
-        let account = APIKeyData {
-            key: base64::encode("API KEY"),
-            secret: base64::encode("API Secret"),
-            passphrase: "passphrase".to_string(),
-        };
+        let start =
+            NaiveDateTime::parse_from_str("2022-05-20 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let end = start + chrono::Duration::minutes(500);
 
-        let output = api.get_account(account, "id").await.unwrap();
+        let output = api
+            .get_product_candles("ETH-USD".to_string(), Granularity::OneMinute, start, end)
+            .await
+            .unwrap();
 
-        let acct = output;
-        assert_eq!(acct.id, "id");
-        assert_eq!(acct.hold, 0.1);
-    }
+        assert_eq!(output.len(), 3);
+        assert_eq!(output[0].open, 1.0);
+        assert_eq!(output[1].open, 2.0);
+        assert_eq!(output[2].open, 3.0);
 
-    static HEADER: HeaderValue = HeaderValue::from_static("");
+        let first_query = first_chunk.call_info.lock().unwrap().split_off(0);
+        let first_query = first_query
+            .iter()
+            .find(|x| x.method_name == "query")
+            .unwrap();
+        assert!(first_query.arguments[0]
+            .argument_value
+            .contains(r#"("start", "2022-05-20T00:00:00Z"), ("end", "2022-05-20T04:59:00Z")"#));
+
+        let second_query = second_chunk.call_info.lock().unwrap().split_off(0);
+        let second_query = second_query
+            .iter()
+            .find(|x| x.method_name == "query")
+            .unwrap();
+        assert!(second_query.arguments[0]
+            .argument_value
+            .contains(r#"("start", "2022-05-20T05:00:00Z"), ("end", "2022-05-20T08:20:00Z")"#));
+    }
 
     #[tokio::test]
-    async fn mocked_api_get_account_holds() {
-        let respone1 = MockResponse::new();
-        let mut respone2 = MockResponse::new();
-        let mut headers = MockHeaderMap::new();
+    async fn mocked_api_granularity_validation() {
+        assert_eq!(Granularity::try_from(3600).unwrap(), Granularity::OneHour);
 
-        headers.expect_get::<&str>().return_const(Some(&HEADER));
-
-        respone2.expect_text().return_once(|| {
-            Ok(r#"[
-        {
-            "id": "id",
-            "created_at": "2014-11-06T10:34:47.123456Z",
-            "amount": "string?",
-            "ref": "String",
-            "type": "hold_type"
+        match Granularity::try_from(120) {
+            Err(Error::InvalidGranularity(seconds)) => assert_eq!(seconds, 120),
+            _ => unreachable!(),
         }
-    ]"#
-            .to_string())
-        });
-        respone2.expect_headers().return_const(headers);
+    }
 
-        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+    static CURSOR: HeaderValue = HeaderValue::from_static("cursor_1");
 
-        let mock_client = MockClient::new_mock(mock_request_builder.clone());
-        let api = CBProAPI::from_client(mock_client);
+    const LEDGER_ENTRY: &str = r#"{"id":"1","amount":"1.0","created_at":"2022-01-20T00:00:00.000000Z","balance":"1.0","type":"fee","details":{}}"#;
+
+    #[tokio::test]
+    async fn mocked_api_get_account_ledger_stream() {
+        let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
+        let mut respone2 = MockResponse::new();
+        respone2.expect_status().return_const(StatusCode::OK);
+        let mut headers1 = MockHeaderMap::new();
+        let mut headers2 = MockHeaderMap::new();
 
         let account = APIKeyData {
             key: base64::encode("API KEY"),
@@ -588,209 +704,146 @@ mod tests {
             passphrase: "passphrase".to_string(),
         };
 
-        let output = api.get_account_holds(account, "id").await.unwrap();
+        headers1.expect_get::<&str>().return_const(Some(&CURSOR));
+        headers2.expect_get::<&str>().return_const(Some(&CURSOR));
 
-        let acct = output;
-        assert_eq!(acct.len(), 1);
+        respone1
+            .expect_text()
+            .return_once(|| Ok(format!("[{}]", LEDGER_ENTRY)));
+        respone1.expect_headers().return_const(headers1);
+        respone2
+            .expect_text()
+            .return_once(|| Ok(format!("[{},{}]", LEDGER_ENTRY, LEDGER_ENTRY)));
+        respone2.expect_headers().return_const(headers2);
+
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client);
+
+        let pagination = Pagination {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let output: Vec<_> = api
+            .get_account_ledger_stream(account, "account_id", pagination)
+            .collect()
+            .await;
+
+        assert_eq!(output.len(), 3);
+        assert!(output.iter().all(|ledger| ledger.is_ok()));
+
+        let queries: Vec<CallInfo> = mock_request_builder
+            .call_info
+            .lock()
+            .unwrap()
+            .split_off(0)
+            .into_iter()
+            .filter(|x| x.method_name == "query")
+            .collect();
+        assert_eq!(queries.len(), 2);
+        assert!(queries[0].arguments[0]
+            .argument_value
+            .contains(r#"("limit", "2")"#));
+        assert!(!queries[0].arguments[0].argument_value.contains("after"));
+        assert!(queries[1].arguments[0]
+            .argument_value
+            .contains(r#"("after", "cursor_1")"#));
     }
 
     #[tokio::test]
-    async fn mocked_api_get_account_ledger() {
+    async fn mocked_api_get_account_ledger_stream_early_drop() {
         let respone1 = MockResponse::new();
         let mut respone2 = MockResponse::new();
+        respone2.expect_status().return_const(StatusCode::OK);
         let mut headers = MockHeaderMap::new();
 
-        headers.expect_get::<&str>().return_const(Some(&HEADER));
-
-        // todo!(LedgerDetail Variants)
-        respone2.expect_text().return_once(|| {
-            Ok(r#"[
-        {
-            "id": "String",
-            "amount": "0.1",
-            "created_at": "2014-11-06T10:34:47.123456Z",
-            "balance": "0.1",
-            "type": "match",
-            "details": {
-                "order_id": "id strings",
-                "product_id": "id strings",
-                "trade_id": "id strings"
-            }
-        },
-        {
-            "id": "String",
-            "amount": "0.1",
-            "created_at": "2014-11-06T10:34:47.123456Z",
-            "balance": "0.1",
-            "type": "transfer",
-            "details": {
-                "transfer_id": "id strings",
-                "transfer_type": "id strings"
-            }
-        },
-        {
-            "id": "String",
-            "amount": "0.1",
-            "created_at": "2014-11-06T10:34:47.123456Z",
-            "balance": "0.1",
-            "type": "fee",
-            "details": {
-                "transfer_id": "id strings",
-                "transfer_type": "id strings"
-            }
-        },
-        {
-            "id": "String",
-            "amount": "0.1",
-            "created_at": "2014-11-06T10:34:47.123456Z",
-            "balance": "0.1",
-            "type": "rebate",
-            "details": {
-                "transfer_id": "id strings",
-                "transfer_type": "id strings"
-            }
-        },
-        {
-            "id": "String",
-            "amount": "0.1",
-            "created_at": "2014-11-06T10:34:47.123456Z",
-            "balance": "0.1",
-            "type": "conversion",
-            "details": {
-                "transfer_id": "id strings",
-                "transfer_type": "id strings"
-            }
-        }
-    ]"#
-            .to_string())
-        });
-        respone2.expect_headers().return_const(headers);
-
-        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
-
-        let mock_client = MockClient::new_mock(mock_request_builder.clone());
-        let api = CBProAPI::from_client(mock_client);
-
         let account = APIKeyData {
             key: base64::encode("API KEY"),
             secret: base64::encode("API Secret"),
             passphrase: "passphrase".to_string(),
         };
 
-        let output = api.get_account_ledger(account, "id").await.unwrap();
-
-        let acct = output;
-        assert_eq!(acct.len(), 5);
-        assert_eq!(acct[0].details.variant_name(), "match");
-        assert_eq!(acct[1].details.variant_name(), "transfer");
-        assert_eq!(acct[2].details.variant_name(), "fee");
-        assert_eq!(acct[3].details.variant_name(), "rebate");
-        assert_eq!(acct[4].details.variant_name(), "conversion");
-    }
-
-    #[tokio::test]
-    async fn mocked_api_get_account_transfers() {
-        let respone1 = MockResponse::new();
-        let mut respone2 = MockResponse::new();
-        let mut headers = MockHeaderMap::new();
-
-        headers.expect_get::<&str>().return_const(Some(&HEADER));
+        headers.expect_get::<&str>().return_const(Some(&CURSOR));
 
-        respone2.expect_text().return_once(|| {
-            Ok(r#"[
-        {
-            "id":"deadbeef-dead-beef-dead-beefdeadbeef",
-            "type":"withdraw",
-            "created_at":"2021-09-13 00:00:00.000000+00",
-            "completed_at":"2021-09-13 00:00:00.000000+00",
-            "canceled_at":null,
-            "processed_at":"2021-09-13 00:00:00.00000+00",
-            "user_nonce":"1234567891011",
-            "amount":"0.00000000",
-            "details":
-            {
-                    "fee":"0.000000",
-                    "subtotal":"0.00",
-                    "sent_to_address":"0xDEADBEEFDEADBEEFDEADBEEFDEADBEEFDEADBEEF",
-                    "coinbase_account_id":"deadbeef-dead-beef-dead-beefdeadbeef",
-                    "coinbase_withdrawal_id":"bofadeeznutsdeadbeefligm",
-                    "coinbase_transaction_id":"bofadeeznutsdeadbeefligm",
-                    "crypto_transaction_hash":"bofadeeznutsdeadbeefligmaballzsugmamikehuntjennytaliabofadeeznut",
-                    "coinbase_payment_method_id":""
-            }
-        },
-        {
-            "id":"deadbeef-dead-beef-dead-beefdeadbeef",
-            "type":"deposit",
-            "created_at":"2021-09-13 00:00:00.000000+00",
-            "completed_at":"2021-09-13 00:00:00.000000+00",
-            "canceled_at":null,
-            "processed_at":"2021-09-13 00:00:00.00000+00",
-            "user_nonce":"1234567891011",
-            "amount":"0.00000000",
-            "details":
-            {
-                    "crypto_address":"asdasd",
-                    "destination_tag":"asdasd",
-                    "destination_tag_name": "String",
-                    "coinbase_account_id":"deadbeef-dead-beef-dead-beefdeadbeef",
-                    "crypto_transaction_id": "transaction id",
-                    "coinbase_transaction_id":"bofadeeznutsdeadbeefligm",
-                    "crypto_transaction_hash":"bofadeeznutsdeadbeefligmaballzsugmamikehuntjennytaliabofadeeznut"
-            }
-        }
-    ]"#
-            .to_string())
-        });
+        respone2
+            .expect_text()
+            .return_once(|| Ok(format!("[{},{}]", LEDGER_ENTRY, LEDGER_ENTRY)));
         respone2.expect_headers().return_const(headers);
 
+        // A second page would pop respone1 which has no expectations set.
         let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
-
         let mock_client = MockClient::new_mock(mock_request_builder.clone());
         let api = CBProAPI::from_client(mock_client);
 
-        let account = APIKeyData {
-            key: base64::encode("API KEY"),
-            secret: base64::encode("API Secret"),
-            passphrase: "passphrase".to_string(),
+        let pagination = Pagination {
+            limit: Some(2),
+            before: None,
+            after: Some("cursor_0".to_string()),
         };
+        let output: Vec<_> = api
+            .get_account_ledger_stream(account, "account_id", pagination)
+            .take(2)
+            .collect()
+            .await;
 
-        let output = api.get_account_transfers(account, "id").await.unwrap();
+        assert_eq!(output.len(), 2);
 
-        let acct = output;
-        assert_eq!(acct.len(), 2);
+        let queries: Vec<CallInfo> = mock_request_builder
+            .call_info
+            .lock()
+            .unwrap()
+            .split_off(0)
+            .into_iter()
+            .filter(|x| x.method_name == "query")
+            .collect();
+        assert_eq!(queries.len(), 1);
+        assert!(queries[0].arguments[0]
+            .argument_value
+            .contains(r#"("after", "cursor_0")"#));
     }
 
+    static RETRY_AFTER: HeaderValue = HeaderValue::from_static("2");
+
     #[tokio::test]
-    async fn mocked_api_get_all_wallets() {
+    async fn mocked_api_rate_limited() {
         let mut respone1 = MockResponse::new();
-        let mut respone2 = MockResponse::new();
+        let respone2 = MockResponse::new();
         let mut headers = MockHeaderMap::new();
 
-        headers.expect_get::<&str>().return_const(Some(&HEADER));
+        headers
+            .expect_get::<&str>()
+            .returning(|key| (key == "retry-after").then_some(&RETRY_AFTER));
 
-        respone1.expect_text().return_once(|| {
-            Ok(r#"[{
-    "id": "String",
-    "name": "String",
-    "balance": "String",
-    "currency": "String",
-    "type": "String",
-    "primary": false,
-    "active": false,
-    "available_on_consumer": false,
-    "hold_balance": "0.1",
-    "hold_currency": "string"
-            }
-    ]"#
-            .to_string())
-        });
-        respone2.expect_headers().return_const(headers);
+        respone1
+            .expect_status()
+            .return_const(StatusCode::TOO_MANY_REQUESTS);
+        respone1.expect_headers().return_const(headers);
+        respone1
+            .expect_text()
+            .return_once(|| Ok(r#"{"message":"Public rate limit exceeded"}"#.to_string()));
 
         let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
-
         let mock_client = MockClient::new_mock(mock_request_builder.clone());
-        let api = CBProAPI::from_client(mock_client);
+        let mut api = CBProAPI::from_client(mock_client);
+        api.set_retry_policy(RetryPolicy::none());
+
+        match api.get_product_ticker("ETH-USD".to_string()).await {
+            Err(Error::RateLimited(data)) => {
+                assert_eq!(data.status, 429);
+                assert_eq!(data.path, "/products/ETH-USD/ticker");
+                assert_eq!(data.headers.get("retry-after").unwrap(), "2");
+                assert_eq!(data.headers.len(), 1);
+                assert_eq!(data.message.as_deref(), Some("Public rate limit exceeded"));
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn mocked_api_server_unavailable_html_body() {
+        let respone1 = MockResponse::new();
+        let mut respone2 = MockResponse::new();
+        let mut headers = MockHeaderMap::new();
 
         let account = APIKeyData {
             key: base64::encode("API KEY"),
@@ -798,203 +851,385 @@ mod tests {
             passphrase: "passphrase".to_string(),
         };
 
-        let output = api.get_all_wallets(account).await.unwrap();
+        headers.expect_get::<&str>().return_const(None);
 
-        let acct = output;
-        assert_eq!(acct.len(), 1);
+        respone2
+            .expect_status()
+            .return_const(StatusCode::SERVICE_UNAVAILABLE);
+        respone2.expect_headers().return_const(headers);
+        respone2
+            .expect_text()
+            .return_once(|| Ok("<html>Service Unavailable</html>".to_string()));
+
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let mut api = CBProAPI::from_client(mock_client);
+        api.set_retry_policy(RetryPolicy::none());
+
+        let output = api.get_fills(account, None, None, None).await;
+
+        match output {
+            Err(Error::ServerUnavailable(data)) => {
+                assert_eq!(data.status, 503);
+                assert_eq!(data.path, "/fills");
+                assert_eq!(data.message, None);
+                assert_eq!(data.body, "<html>Service Unavailable</html>");
+            }
+            _ => unreachable!(),
+        }
     }
 
     #[tokio::test]
-    async fn mocked_api_get_all_currencies() {
+    async fn mocked_api_status_error_variants() {
+        let status_error = |status: u16, message: Option<&str>| {
+            Error::from_http_response(HttpErrorData {
+                status,
+                path: "/orders".to_string(),
+                headers: Default::default(),
+                message: message.map(|x| x.to_string()),
+                body: "".to_string(),
+            })
+        };
+
+        assert!(matches!(status_error(401, None), Error::Unauthorized(_)));
+        assert!(matches!(status_error(403, None), Error::Unauthorized(_)));
+        assert!(matches!(status_error(404, None), Error::NotFound(_)));
+        assert!(matches!(
+            status_error(502, None),
+            Error::ServerUnavailable(_)
+        ));
+        assert!(matches!(
+            status_error(400, Some("Invalid order")),
+            Error::CBProServerErrorVariant(_)
+        ));
+        assert_eq!(
+            status_error(400, None).http_error_data().unwrap().status,
+            400
+        );
+    }
+
+    pub(crate) fn server_unavailable_request() -> MockRequestBuilder {
         let mut respone1 = MockResponse::new();
-        let mut respone2 = MockResponse::new();
+        let respone2 = MockResponse::new();
         let mut headers = MockHeaderMap::new();
 
-        headers.expect_get::<&str>().return_const(Some(&HEADER));
+        headers.expect_get::<&str>().return_const(None);
 
-        respone1.expect_text().return_once(|| {
-            Ok(r#"[{
-                "id": "String",
-                "name": "String",
-                "min_size": "0.1",
-                "status": "String",
-                "message": "String",
-                "max_precision": "0.1",
-                "convertible_to": ["asda"],
-                "details": {}
-            }
-    ]"#
-            .to_string())
-        });
-        respone2.expect_headers().return_const(headers);
+        respone1
+            .expect_status()
+            .return_const(StatusCode::SERVICE_UNAVAILABLE);
+        respone1.expect_headers().return_const(headers);
+        respone1
+            .expect_text()
+            .return_once(|| Ok("<html>Service Unavailable</html>".to_string()));
 
-        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+        MockRequestBuilder::new_mock(vec![respone1, respone2])
+    }
 
-        let mock_client = MockClient::new_mock(mock_request_builder.clone());
-        let api = CBProAPI::from_client(mock_client);
+    fn new_order_request() -> MockRequestBuilder {
+        let mut respone1 = MockResponse::new();
+        let respone2 = MockResponse::new();
 
-        let output = api.get_currencies().await.unwrap();
+        respone1.expect_status().return_const(StatusCode::OK);
+        respone1.expect_text().return_once(|| {
+            Ok(r#"{"id":"deadbeef-dead-beef-dead-beefdeadbeef","price":"0.10000000","size":"0.01000000","product_id":"BTC-USD","side":"buy","stp":"dc","type":"limit","time_in_force":"GTC","post_only":false,"created_at":"2022-01-20T00:00:00.000000Z","fill_fees":"0.0000000000000000","filled_size":"0.00000000","executed_value":"0.0000000000000000","status":"pending","settled":false}"#.to_string())
+        });
 
-        let acct = output;
-        assert_eq!(acct.len(), 1);
+        MockRequestBuilder::new_mock(vec![respone1, respone2])
     }
 
-    #[tokio::test]
-    async fn mocked_api_get_all_currency() {
+    fn product_stats_request() -> MockRequestBuilder {
         let mut respone1 = MockResponse::new();
-        let mut respone2 = MockResponse::new();
-        let mut headers = MockHeaderMap::new();
-
-        headers.expect_get::<&str>().return_const(Some(&HEADER));
+        let respone2 = MockResponse::new();
 
+        respone1.expect_status().return_const(StatusCode::OK);
         respone1.expect_text().return_once(|| {
-            Ok(r#"{
-                "id": "String",
-                "name": "String",
-                "min_size": "0.1",
-                "status": "String",
-                "message": "String",
-                "max_precision": "0.1",
-                "convertible_to": ["asda"],
-                "details": {}
-            }
-    "#
-            .to_string())
+            Ok(r#"{"open":"1","high":"2","low":"0.5","last":"1.5","volume":"10","volume_30day":"300"}"#.to_string())
         });
-        respone2.expect_headers().return_const(headers);
 
-        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+        MockRequestBuilder::new_mock(vec![respone1, respone2])
+    }
 
-        let mock_client = MockClient::new_mock(mock_request_builder.clone());
-        let api = CBProAPI::from_client(mock_client);
+    fn fast_retry_policy() -> RetryPolicy {
+        RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
 
-        api.get_currency("mine".to_string()).await.unwrap();
+    #[tokio::test]
+    async fn mocked_api_retry_server_unavailable() {
+        let mock_client = MockClient::new_mock_sequence(vec![
+            server_unavailable_request(),
+            product_stats_request(),
+        ]);
+        let mut api = CBProAPI::from_client(mock_client);
+        api.set_retry_policy(fast_retry_policy());
+
+        let output = api.get_product_stats("ETH-USD".to_string()).await.unwrap();
+
+        assert_eq!(output.last, 1.5);
     }
 
     #[tokio::test]
-    async fn mocked_api_get_conversion_invalid() {
-        let mut respone1 = MockResponse::new();
-        let mut respone2 = MockResponse::new();
-        let mut headers = MockHeaderMap::new();
+    async fn mocked_api_retry_gives_up_after_max_attempts() {
+        let mock_client = MockClient::new_mock_sequence(vec![
+            server_unavailable_request(),
+            server_unavailable_request(),
+            server_unavailable_request(),
+        ]);
+        let mut api = CBProAPI::from_client(mock_client);
+        api.set_retry_policy(fast_retry_policy());
+
+        let output = api.get_product_stats("ETH-USD".to_string()).await;
+
+        assert!(matches!(output, Err(Error::ServerUnavailable(_))));
+    }
 
-        let account = APIKeyData {
-            key: base64::encode("API KEY"),
-            secret: base64::encode("API Secret"),
-            passphrase: "passphrase".to_string(),
-        };
+    #[tokio::test]
+    async fn mocked_api_retry_resumes_failed_page() {
+        let trade = r#"{"time":"2022-05-25T13:06:56.076Z","trade_id":1,"price":"1","size":"1","side":"buy"}"#;
 
-        headers.expect_get::<&str>().return_const(Some(&HEADER));
+        let mut first_page = MockResponse::new();
+        let mut headers = MockHeaderMap::new();
+        headers.expect_get::<&str>().return_const(Some(&CURSOR));
+        first_page.expect_status().return_const(StatusCode::OK);
+        first_page.expect_headers().return_const(headers);
+        let full_page = format!("[{}]", vec![trade; 1000].join(","));
+        first_page.expect_text().return_once(|| Ok(full_page));
 
-        respone1
+        let mut unavailable = MockResponse::new();
+        let mut headers = MockHeaderMap::new();
+        headers.expect_get::<&str>().return_const(None);
+        unavailable
+            .expect_status()
+            .return_const(StatusCode::SERVICE_UNAVAILABLE);
+        unavailable.expect_headers().return_const(headers);
+        unavailable
             .expect_text()
-            .return_once(|| Ok(r#"{"my json": 123}"#.to_string()));
-        respone2.expect_headers().return_const(headers);
-
-        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+            .return_once(|| Ok("<html>Service Unavailable</html>".to_string()));
 
+        let mut last_page = MockResponse::new();
+        let mut headers = MockHeaderMap::new();
+        headers.expect_get::<&str>().return_const(None);
+        last_page.expect_status().return_const(StatusCode::OK);
+        last_page.expect_headers().return_const(headers);
+        let short_page = format!("[{}]", trade);
+        last_page.expect_text().return_once(|| Ok(short_page));
+
+        let mock_request_builder =
+            MockRequestBuilder::new_mock(vec![last_page, unavailable, first_page]);
         let mock_client = MockClient::new_mock(mock_request_builder.clone());
-        let api = CBProAPI::from_client(mock_client);
+        let mut api = CBProAPI::from_client(mock_client);
+        api.set_retry_policy(fast_retry_policy());
 
-        let output = api.get_conversion(account, "", "").await;
-        assert!(output.is_err());
+        let output = api
+            .get_product_trades("ETH-USD".to_string(), None, 5)
+            .await
+            .unwrap();
+
+        assert_eq!(output.len(), 1001);
+
+        // The failed second page is retried from its own cursor instead of the first page.
+        let queries: Vec<String> = mock_request_builder
+            .call_info
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|x| x.method_name == "query")
+            .map(|x| x.arguments[0].argument_value.clone())
+            .collect();
+
+        assert_eq!(queries.len(), 3);
+        assert!(!queries[0].contains(r#""after""#));
+        assert!(queries[1].contains(r#"("after", "cursor_1")"#));
+        assert!(queries[2].contains(r#"("after", "cursor_1")"#));
     }
 
     #[tokio::test]
-    async fn mocked_api_get_fills_invalid() {
-        let respone1 = MockResponse::new();
-        let mut respone2 = MockResponse::new();
-        let mut headers = MockHeaderMap::new();
-
+    async fn mocked_api_create_order_retry_requires_client_oid() {
         let account = APIKeyData {
             key: base64::encode("API KEY"),
             secret: base64::encode("API Secret"),
             passphrase: "passphrase".to_string(),
         };
 
-        headers.expect_get::<&str>().return_const(Some(&HEADER));
+        // Without a client_oid the order is sent exactly once.
+        let mock_client = MockClient::new_mock_sequence(vec![server_unavailable_request()]);
+        let mut api = CBProAPI::from_client(mock_client);
+        api.set_retry_policy(fast_retry_policy());
 
-        respone2
-            .expect_text()
-            .return_once(|| Ok(r#"{"my json": 123}"#.to_string()));
-        respone2.expect_headers().return_const(headers);
+        let order = LimitOrder::new("BTC-USD".to_string(), Side::BUY, 0.1, 0.01);
+        let output = api.create_order(account.clone(), order).await;
 
-        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+        assert!(matches!(output, Err(Error::ServerUnavailable(_))));
 
-        let mock_client = MockClient::new_mock(mock_request_builder.clone());
-        let api = CBProAPI::from_client(mock_client);
+        // With a client_oid a failed order is resent.
+        let mock_client =
+            MockClient::new_mock_sequence(vec![server_unavailable_request(), new_order_request()]);
+        let mut api = CBProAPI::from_client(mock_client);
+        api.set_retry_policy(fast_retry_policy());
 
-        let output = api
-            .get_fills(
-                account,
-                Some("".to_string()),
-                Some("".to_string()),
-                Some("".to_string()),
-            )
-            .await;
-        assert!(output.is_err());
+        let order = LimitOrder::new("BTC-USD".to_string(), Side::BUY, 0.1, 0.01)
+            .set_client_oid(Some("deadbeef-dead-beef-dead-beefdeadbeef".to_string()));
+        let output = api.create_order(account, order).await;
+
+        assert!(output.is_ok());
     }
 
     #[tokio::test]
-    async fn mocked_api_create_order_invalid() {
-        let mut respone1 = MockResponse::new();
-        let mut respone2 = MockResponse::new();
-        let mut headers = MockHeaderMap::new();
+    async fn rate_limited_pool_burst_then_refill() {
+        let pool = RateLimitedPool::new(RateLimit::new(20.0, 3));
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            pool.schedule_rate_limited_task(async {}).await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(25));
+
+        // The bucket is empty so the next task waits for a single token to refill.
+        pool.schedule_rate_limited_task(async {}).await;
+        assert!(start.elapsed() >= Duration::from_millis(45));
+    }
 
+    #[tokio::test]
+    async fn rate_limiter_selects_bucket() {
         let account = APIKeyData {
             key: base64::encode("API KEY"),
             secret: base64::encode("API Secret"),
             passphrase: "passphrase".to_string(),
         };
+        let client = MockClient::new();
+        let new_request = || CBRequestBuilder::new(&client, "Rust".to_string());
 
-        headers.expect_get::<&str>().return_const(Some(&HEADER));
-
-        respone1
-            .expect_text()
-            .return_once(|| Ok(r#"{"my json": 123}"#.to_string()));
-        respone2.expect_headers().return_const(headers);
+        let limits = RateLimits {
+            public: RateLimit::new(1.0, 1),
+            private: RateLimit::new(2.0, 2),
+            orders: RateLimit::new(3.0, 3),
+        };
+        let limiter = RateLimiter::new(limits);
+
+        let public = new_request().set_endpoint("/products".to_string());
+        let private = new_request()
+            .set_endpoint("/fills".to_string())
+            .sign(account.clone());
+        let order = new_request()
+            .set_endpoint("/orders".to_string())
+            .set_method(RequestMethod::POST)
+            .sign(account.clone());
+        let cancel = new_request()
+            .set_endpoint("/orders/order_id".to_string())
+            .set_method(RequestMethod::DELETE)
+            .sign(account.clone());
+        let list_orders = new_request()
+            .set_endpoint("/orders".to_string())
+            .sign(account);
+
+        assert_eq!(limiter.pool_for(&public).limit(), limits.public);
+        assert_eq!(limiter.pool_for(&private).limit(), limits.private);
+        assert_eq!(limiter.pool_for(&order).limit(), limits.orders);
+        assert_eq!(limiter.pool_for(&cancel).limit(), limits.orders);
+        assert_eq!(limiter.pool_for(&list_orders).limit(), limits.private);
+    }
 
-        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+    #[tokio::test]
+    async fn mocked_api_builder_custom_url_and_user_agent() {
+        let mock_request_builder = product_stats_request();
         let mock_client = MockClient::new_mock(mock_request_builder.clone());
-        let api = CBProAPI::from_client(mock_client);
+        let api = CBProAPIBuilder::new()
+            .set_client(mock_client.clone())
+            .set_url("http://localhost:8080".to_string())
+            .set_user_agent("stub-test".to_string())
+            .build();
 
-        let order = MarketOrder::new("".to_string(), Side::BUY, MarketOrderValue::Size(0.1));
+        api.get_product_stats("ETH-USD".to_string()).await.unwrap();
 
-        let output = api.create_order(account, order).await;
-        assert!(output.is_err());
+        assert_eq!(
+            mock_client.requested_url.lock().unwrap().as_str(),
+            r#"Ok("http://localhost:8080/products/ETH-USD/stats")"#
+        );
+        assert!(mock_request_builder
+            .call_info
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|x| x.method_name == "header" && x.called_with(("value", r#""stub-test""#))));
     }
 
     #[tokio::test]
-    async fn mocked_api_get_single_order_invalid() {
-        let mut respone1 = MockResponse::new();
-        let mut respone2 = MockResponse::new();
-        let mut headers = MockHeaderMap::new();
+    async fn mocked_api_builder_sandbox() {
+        let mock_client = MockClient::new_mock(product_stats_request());
+        let api = CBProAPIBuilder::new()
+            .set_client(mock_client.clone())
+            .sandbox()
+            .build();
 
-        let account = APIKeyData {
-            key: base64::encode("API KEY"),
-            secret: base64::encode("API Secret"),
-            passphrase: "passphrase".to_string(),
-        };
+        api.get_product_stats("ETH-USD".to_string()).await.unwrap();
 
-        headers.expect_get::<&str>().return_const(Some(&HEADER));
+        assert_eq!(
+            mock_client.requested_url.lock().unwrap().as_str(),
+            r#"Ok("https://api-public.sandbox.exchange.coinbase.com/products/ETH-USD/stats")"#
+        );
+    }
 
-        respone1
-            .expect_text()
-            .return_once(|| Ok(r#"{"my json": 123}"#.to_string()));
-        respone2.expect_headers().return_const(headers);
+    #[test]
+    fn request_signature_reference_values() {
+        let secret = base64::encode("API Secret");
 
-        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
-        let mock_client = MockClient::new_mock(mock_request_builder.clone());
-        let api = CBProAPI::from_client(mock_client);
+        assert_eq!(
+            signature(&secret, 1653004800, "GET", "/fills?product_id=BTC-USD", ""),
+            Some("eslCOPuP05WcTW2S780xp85BZZ4RbFkSA+MmFn4zpdY=".to_string())
+        );
+        assert_eq!(
+            signature(
+                &secret,
+                1653004800,
+                "GET",
+                "/products/BTC-USD/candles?start=2022-05-20T00%3A00%3A00Z&note=a+b%2Bc",
+                ""
+            ),
+            Some("WvPKtTR7ybvpmz3J/4e3whn9lamkGkb8C8MtXOp9aO0=".to_string())
+        );
+        assert_eq!(
+            signature(
+                &secret,
+                1653004800,
+                "POST",
+                "/orders",
+                r#"{"product_id":"BTC-USD"}"#
+            ),
+            Some("7OTicwQ3EMVySon537FxnBqXN5S7lHiZLxAIXmAR6Wk=".to_string())
+        );
+        assert_eq!(
+            signature("not base64!", 1653004800, "GET", "/fills", ""),
+            None
+        );
+    }
 
-        let output = api.get_single_order(account, "".to_string()).await;
-        assert!(output.is_err());
+    #[test]
+    fn request_query_encoding_matches_reqwest() {
+        let params = vec![
+            ("start".to_string(), "2022-05-20T00:00:00Z".to_string()),
+            ("note".to_string(), "a b+c&d=e".to_string()),
+        ];
+
+        let request = reqwest::Client::new()
+            .get("https://api.exchange.coinbase.com/products/BTC-USD/candles")
+            .query(&params)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            encode_query(&params),
+            "start=2022-05-20T00%3A00%3A00Z&note=a+b%2Bc%26d%3De"
+        );
+        assert_eq!(request.url().query(), Some(encode_query(&params).as_str()));
     }
 
     #[tokio::test]
-    async fn mocked_api_get_orders_invalid() {
-        let respone1 = MockResponse::new();
-        let mut respone2 = MockResponse::new();
-        let mut headers = MockHeaderMap::new();
+    async fn mocked_api_signed_query_is_encoded() {
+        let mut respone1 = MockResponse::new();
+        let respone2 = MockResponse::new();
 
         let account = APIKeyData {
             key: base64::encode("API KEY"),
@@ -1002,98 +1237,997 @@ mod tests {
             passphrase: "passphrase".to_string(),
         };
 
-        headers.expect_get::<&str>().return_const(Some(&HEADER));
-
-        respone2
+        respone1.expect_status().return_const(StatusCode::OK);
+        respone1
             .expect_text()
-            .return_once(|| Ok(r#"{"my json": 123}"#.to_string()));
-        respone2.expect_headers().return_const(headers);
+            .return_once(|| Ok(r#"["order_1"]"#.to_string()));
 
         let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
         let mock_client = MockClient::new_mock(mock_request_builder.clone());
         let api = CBProAPI::from_client(mock_client);
 
-        let output = api
-            .get_orders(account, Some("".to_string()), Some("".to_string()))
-            .await;
-        assert!(output.is_err());
+        api.cancel_all_orders(account.clone(), Some("a b+c:".to_string()), None)
+            .await
+            .unwrap();
+
+        let header_value = |name: &str| {
+            mock_request_builder
+                .call_info
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|x| x.method_name == "header" && x.called_with(("key", name)))
+                .map(|x| x.arguments[1].argument_value.trim_matches('"').to_string())
+                .unwrap()
+        };
+
+        let timestamp = header_value(r#""CB-ACCESS-TIMESTAMP""#).parse().unwrap();
+        let expected = signature(
+            &account.secret,
+            timestamp,
+            "DELETE",
+            "/orders?product_id=a+b%2Bc%3A",
+            "",
+        );
+
+        assert_eq!(Some(header_value(r#""CB-ACCESS-SIGN""#)), expected);
     }
 
-    #[tokio::test]
-    async fn mocked_api_subscription_request() {
-        let builder = SubscriptionBuilder::new()
-            .subscribe_to_heartbeat("".to_string())
-            .subscribe_to_status()
-            .subscribe_to_ticker("".to_string())
-            .subscribe_to_full("".to_string())
-            .subscribe_to_snapshot("".to_string());
+    pub(crate) fn server_time_response(epoch: f64) -> MockResponse {
+        let mut respone = MockResponse::new();
 
-        let request = builder.build();
+        respone.expect_status().return_const(StatusCode::OK);
+        respone.expect_text().return_once(move || {
+            Ok(format!(
+                r#"{{"iso":"2015-01-07T23:47:25.201Z","epoch":{}}}"#,
+                epoch
+            ))
+        });
 
-        let serialized = serde_json::to_string(&request).unwrap();
-        assert!(serialized.contains(r#""name":"heartbeat","product_ids":[""]"#));
-        assert!(serialized.contains(r#""name":"ticker","product_ids":[""]"#));
-        assert!(serialized.contains(r#""name":"level2","product_ids":[""]"#));
-        assert!(serialized.contains(r#""name":"full","product_ids":[""]"#));
-        assert!(serialized.contains(r#""name":"status""#));
+        respone
+    }
+
+    fn signed_timestamp(request: &MockRequestBuilder) -> i64 {
+        request
+            .call_info
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|x| {
+                x.method_name == "header" && x.called_with(("key", r#""CB-ACCESS-TIMESTAMP""#))
+            })
+            .map(|x| x.arguments[1].argument_value.parse().unwrap())
+            .unwrap()
     }
 
     #[tokio::test]
-    async fn mocked_api_subscription_request_vecs() {
-        let builder = SubscriptionBuilder::new()
-            .subscribe_to_heartbeat_vec(&mut vec!["".to_string()])
-            .subscribe_to_ticker_vec(&mut vec!["".to_string()])
-            .subscribe_to_full_vec(&mut vec!["".to_string()])
-            .subscribe_to_snapshot_vec(&mut vec!["".to_string()]);
+    async fn mocked_api_get_time() {
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![
+            server_time_response(1420674445.201),
+            MockResponse::new(),
+        ]);
+        let mock_client = MockClient::new_mock(mock_request_builder);
+        let api = CBProAPI::from_client(mock_client.clone());
 
-        let request = builder.build();
+        let output = api.get_time().await.unwrap();
 
-        let serialized = serde_json::to_string(&request).unwrap();
-        assert!(serialized.contains(r#""name":"heartbeat","product_ids":[""]"#));
-        assert!(serialized.contains(r#""name":"ticker","product_ids":[""]"#));
-        assert!(serialized.contains(r#""name":"level2","product_ids":[""]"#));
-        assert!(serialized.contains(r#""name":"full","product_ids":[""]"#));
+        assert_eq!(output.epoch, 1420674445.201);
+        assert_eq!(
+            output.iso,
+            NaiveDateTime::parse_from_str("2015-01-07 23:47:25.201", "%Y-%m-%d %H:%M:%S%.f")
+                .unwrap()
+        );
+        assert!(mock_client.requested_url.lock().unwrap().contains("/time"));
     }
 
     #[tokio::test]
-    async fn mock_stream_tc1() {
-        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
-            Ok(_) => {}
-            Err(_) => {}
+    async fn mocked_api_server_time_sync() {
+        let account = APIKeyData {
+            key: base64::encode("API KEY"),
+            secret: base64::encode("API Secret"),
+            passphrase: "passphrase".to_string(),
         };
+        let order = LimitOrder::new("BTC-USD".to_string(), Side::BUY, 0.1, 0.01);
+
+        // The server clock runs an hour ahead of the local clock.
+        let server_epoch = chrono::Utc::now().timestamp() + 3600;
+        let first_order = new_order_request();
+        let second_order = new_order_request();
+        let mock_client = MockClient::new_mock_sequence(vec![
+            // Measuring the offset only sends the request so a single response is enough.
+            MockRequestBuilder::new_mock(vec![server_time_response(server_epoch as f64)]),
+            first_order.clone(),
+            second_order.clone(),
+        ]);
+        let api = CBProAPIBuilder::new()
+            .set_client(mock_client)
+            .sync_server_time(Duration::from_secs(60))
+            .build();
 
-        let mocked_tcp = MockTcpStream::new();
-        let mut mock_tls = MockTlsStream::new(&mocked_tcp);
+        api.create_order(account.clone(), order.clone())
+            .await
+            .unwrap();
+        // The measured offset is reused until the refresh interval passes.
+        api.create_order(account, order).await.unwrap();
 
-        mock_tls.expect_poll_write(|buf| buf == vec![0, 0, 0].as_slice());
-        let out = mock_tls.write(vec![0, 0, 0].as_slice()).await.unwrap();
-        assert_eq!(out, 3);
+        assert!((signed_timestamp(&first_order) - server_epoch).abs() <= 2);
+        assert!((signed_timestamp(&second_order) - server_epoch).abs() <= 2);
     }
 
     #[tokio::test]
-    async fn mock_stream_tc2() {
-        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
-            Ok(_) => {}
-            Err(_) => {}
-        };
+    async fn mocked_api_get_product_book_minimum_response() {
+        let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
+        let respone2 = MockResponse::new();
 
-        let mocked_tcp = MockTcpStream::new();
-        let mut mock_tls = MockTlsStream::new(&mocked_tcp);
+        respone1.expect_text().return_once(|| {
+            Ok(r#"
+            {
+                "bids": [],
+                "asks": [],
+                "sequence": 100
+            }
+        "#
+            .to_string())
+        });
 
-        let output = vec![0, 1, 1];
-        mock_tls.expect_poll_read(output.clone());
-        let mut out: [u8; 3] = [0; 3];
-        mock_tls.read_exact(&mut out).await.unwrap();
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
 
-        assert_eq!(out.as_slice(), output.as_slice());
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client);
+
+        let output = api
+            .get_product_book("MyProduct".to_string(), Some(Level::One))
+            .await
+            .unwrap();
+
+        assert_eq!(output.bids.len(), 0);
+        assert_eq!(output.asks.len(), 0);
+        assert_eq!(output.sequence, 100.0);
+        assert_eq!(output.auction_mode, None);
+        assert!(output.auction.is_none());
     }
 
-    // #[tokio::test]
-    // async fn mock_stream_tc3() {
-    //     let request_builder = MockRequestBuilder::new_mock(vec![]);
-    //     let client = MockClient::new_mock(request_builder);
-    //
-    //     let mocked_tcp = MockTcpStream::new();
+    #[tokio::test]
+    async fn mocked_api_get_product_book_minimum_response_2() {
+        let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
+        let respone2 = MockResponse::new();
+
+        respone1.expect_text().return_once(|| {
+            Ok(r#"
+            {
+                "bids": [{"price":"1","size":"1","num_orders":1}],
+                "asks": [{"price":"1","size":"1","num_orders":1}],
+                "sequence": 100,
+                "auction_mode": true,
+                "auction": {
+                    "open_price": "0.1",
+                    "open_size": "0.1",
+                    "best_bid_price": "0.1",
+                    "best_bid_size": "0.1",
+                    "best_ask_price": "0.1",
+                    "best_ask_size": "0.1",
+                    "auction_state": "0.1"
+                }
+            }
+        "#
+            .to_string())
+        });
+
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client);
+
+        let output = api
+            .get_product_book("MyProduct".to_string(), Some(Level::One))
+            .await
+            .unwrap();
+
+        assert_eq!(output.bids.len(), 1);
+        assert_eq!(output.asks.len(), 1);
+        assert_eq!(output.sequence, 100.0);
+        assert_eq!(output.auction_mode, Some(true));
+        assert!(output.auction.is_some());
+        assert_eq!(output.auction.as_ref().unwrap().open_price, 0.1);
+        assert_eq!(output.auction.as_ref().unwrap().open_size, 0.1);
+        assert_eq!(output.auction.as_ref().unwrap().best_bid_price, 0.1);
+        assert_eq!(output.auction.as_ref().unwrap().best_bid_size, 0.1);
+        assert_eq!(output.auction.as_ref().unwrap().best_ask_price, 0.1);
+        assert_eq!(output.auction.as_ref().unwrap().best_ask_size, 0.1);
+        assert_eq!(output.auction.as_ref().unwrap().auction_state, 0.1);
+    }
+
+    #[tokio::test]
+    async fn mocked_api_get_fees() {
+        let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
+        let respone2 = MockResponse::new();
+
+        respone1.expect_text().return_once(|| {
+            Ok(r#"
+            {
+                "taker_fee_rate": "0.1",
+                "maker_fee_rate": "0.1",
+                "usd_volume": "0.1"
+            }
+        "#
+            .to_string())
+        });
+
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client);
+
+        let account = APIKeyData {
+            key: base64::encode("API KEY"),
+            secret: base64::encode("API Secret"),
+            passphrase: "passphrase".to_string(),
+        };
+
+        let output = api.get_fees(account).await.unwrap();
+
+        assert_eq!(output.maker_fee_rate, 0.1);
+        assert_eq!(output.taker_fee_rate, 0.1);
+        assert_eq!(output.usd_volume, 0.1);
+    }
+
+    #[tokio::test]
+    async fn mocked_api_get_accounts() {
+        let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
+        let respone2 = MockResponse::new();
+
+        respone1.expect_text().return_once(|| {
+            Ok(r#"[
+            {
+                "id": "id",
+                "currency": "currency",
+                "balance": "0.1",
+                "available": "0.1",
+                "hold": "0.1",
+                "profile_id": "profile_id",
+                "trading_enabled": false
+            }
+        ]"#
+            .to_string())
+        });
+
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client);
+
+        let account = APIKeyData {
+            key: base64::encode("API KEY"),
+            secret: base64::encode("API Secret"),
+            passphrase: "passphrase".to_string(),
+        };
+
+        let output = api.get_accounts(account).await.unwrap();
+
+        let acct = output.get(0).unwrap();
+
+        assert_eq!(output.len(), 1);
+        assert_eq!(acct.id, "id");
+        assert_eq!(acct.hold, 0.1);
+    }
+
+    #[tokio::test]
+    async fn mocked_api_get_account() {
+        let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
+        let respone2 = MockResponse::new();
+
+        respone1.expect_text().return_once(|| {
+            Ok(r#"
+            {
+                "id": "id",
+                "currency": "currency",
+                "balance": "0.1",
+                "available": "0.1",
+                "hold": "0.1",
+                "profile_id": "profile_id",
+                "trading_enabled": false
+            }
+        "#
+            .to_string())
+        });
+
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client);
+
+        let account = APIKeyData {
+            key: base64::encode("API KEY"),
+            secret: base64::encode("API Secret"),
+            passphrase: "passphrase".to_string(),
+        };
+
+        let output = api.get_account(account, "id").await.unwrap();
+
+        let acct = output;
+        assert_eq!(acct.id, "id");
+        assert_eq!(acct.hold, 0.1);
+    }
+
+    static HEADER: HeaderValue = HeaderValue::from_static("");
+
+    #[tokio::test]
+    async fn mocked_api_get_account_holds() {
+        let respone1 = MockResponse::new();
+        let mut respone2 = MockResponse::new();
+        respone2.expect_status().return_const(StatusCode::OK);
+        let mut headers = MockHeaderMap::new();
+
+        headers.expect_get::<&str>().return_const(Some(&HEADER));
+
+        respone2.expect_text().return_once(|| {
+            Ok(r#"[
+        {
+            "id": "id",
+            "created_at": "2014-11-06T10:34:47.123456Z",
+            "amount": "string?",
+            "ref": "String",
+            "type": "hold_type"
+        }
+    ]"#
+            .to_string())
+        });
+        respone2.expect_headers().return_const(headers);
+
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client);
+
+        let account = APIKeyData {
+            key: base64::encode("API KEY"),
+            secret: base64::encode("API Secret"),
+            passphrase: "passphrase".to_string(),
+        };
+
+        let output = api.get_account_holds(account, "id").await.unwrap();
+
+        let acct = output;
+        assert_eq!(acct.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn mocked_api_get_account_ledger() {
+        let respone1 = MockResponse::new();
+        let mut respone2 = MockResponse::new();
+        respone2.expect_status().return_const(StatusCode::OK);
+        let mut headers = MockHeaderMap::new();
+
+        headers.expect_get::<&str>().return_const(Some(&HEADER));
+
+        // todo!(LedgerDetail Variants)
+        respone2.expect_text().return_once(|| {
+            Ok(r#"[
+        {
+            "id": "String",
+            "amount": "0.1",
+            "created_at": "2014-11-06T10:34:47.123456Z",
+            "balance": "0.1",
+            "type": "match",
+            "details": {
+                "order_id": "id strings",
+                "product_id": "id strings",
+                "trade_id": "id strings"
+            }
+        },
+        {
+            "id": "String",
+            "amount": "0.1",
+            "created_at": "2014-11-06T10:34:47.123456Z",
+            "balance": "0.1",
+            "type": "transfer",
+            "details": {
+                "transfer_id": "id strings",
+                "transfer_type": "id strings"
+            }
+        },
+        {
+            "id": "String",
+            "amount": "0.1",
+            "created_at": "2014-11-06T10:34:47.123456Z",
+            "balance": "0.1",
+            "type": "fee",
+            "details": {
+                "transfer_id": "id strings",
+                "transfer_type": "id strings"
+            }
+        },
+        {
+            "id": "String",
+            "amount": "0.1",
+            "created_at": "2014-11-06T10:34:47.123456Z",
+            "balance": "0.1",
+            "type": "rebate",
+            "details": {
+                "transfer_id": "id strings",
+                "transfer_type": "id strings"
+            }
+        },
+        {
+            "id": "String",
+            "amount": "0.1",
+            "created_at": "2014-11-06T10:34:47.123456Z",
+            "balance": "0.1",
+            "type": "conversion",
+            "details": {
+                "transfer_id": "id strings",
+                "transfer_type": "id strings"
+            }
+        }
+    ]"#
+            .to_string())
+        });
+        respone2.expect_headers().return_const(headers);
+
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client);
+
+        let account = APIKeyData {
+            key: base64::encode("API KEY"),
+            secret: base64::encode("API Secret"),
+            passphrase: "passphrase".to_string(),
+        };
+
+        let output = api.get_account_ledger(account, "id").await.unwrap();
+
+        let acct = output;
+        assert_eq!(acct.len(), 5);
+        assert_eq!(acct[0].details.variant_name(), "match");
+        assert_eq!(acct[1].details.variant_name(), "transfer");
+        assert_eq!(acct[2].details.variant_name(), "fee");
+        assert_eq!(acct[3].details.variant_name(), "rebate");
+        assert_eq!(acct[4].details.variant_name(), "conversion");
+    }
+
+    #[tokio::test]
+    async fn mocked_api_get_account_transfers() {
+        let respone1 = MockResponse::new();
+        let mut respone2 = MockResponse::new();
+        respone2.expect_status().return_const(StatusCode::OK);
+        let mut headers = MockHeaderMap::new();
+
+        headers.expect_get::<&str>().return_const(Some(&HEADER));
+
+        respone2.expect_text().return_once(|| {
+            Ok(r#"[
+        {
+            "id":"deadbeef-dead-beef-dead-beefdeadbeef",
+            "type":"withdraw",
+            "created_at":"2021-09-13 00:00:00.000000+00",
+            "completed_at":"2021-09-13 00:00:00.000000+00",
+            "canceled_at":null,
+            "processed_at":"2021-09-13 00:00:00.00000+00",
+            "user_nonce":"1234567891011",
+            "amount":"0.00000000",
+            "details":
+            {
+                    "fee":"0.000000",
+                    "subtotal":"0.00",
+                    "sent_to_address":"0xDEADBEEFDEADBEEFDEADBEEFDEADBEEFDEADBEEF",
+                    "coinbase_account_id":"deadbeef-dead-beef-dead-beefdeadbeef",
+                    "coinbase_withdrawal_id":"bofadeeznutsdeadbeefligm",
+                    "coinbase_transaction_id":"bofadeeznutsdeadbeefligm",
+                    "crypto_transaction_hash":"bofadeeznutsdeadbeefligmaballzsugmamikehuntjennytaliabofadeeznut",
+                    "coinbase_payment_method_id":""
+            }
+        },
+        {
+            "id":"deadbeef-dead-beef-dead-beefdeadbeef",
+            "type":"deposit",
+            "created_at":"2021-09-13 00:00:00.000000+00",
+            "completed_at":"2021-09-13 00:00:00.000000+00",
+            "canceled_at":null,
+            "processed_at":"2021-09-13 00:00:00.00000+00",
+            "user_nonce":"1234567891011",
+            "amount":"0.00000000",
+            "details":
+            {
+                    "crypto_address":"asdasd",
+                    "destination_tag":"asdasd",
+                    "destination_tag_name": "String",
+                    "coinbase_account_id":"deadbeef-dead-beef-dead-beefdeadbeef",
+                    "crypto_transaction_id": "transaction id",
+                    "coinbase_transaction_id":"bofadeeznutsdeadbeefligm",
+                    "crypto_transaction_hash":"bofadeeznutsdeadbeefligmaballzsugmamikehuntjennytaliabofadeeznut"
+            }
+        }
+    ]"#
+            .to_string())
+        });
+        respone2.expect_headers().return_const(headers);
+
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client);
+
+        let account = APIKeyData {
+            key: base64::encode("API KEY"),
+            secret: base64::encode("API Secret"),
+            passphrase: "passphrase".to_string(),
+        };
+
+        let output = api.get_account_transfers(account, "id").await.unwrap();
+
+        let acct = output;
+        assert_eq!(acct.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn mocked_api_get_all_wallets() {
+        let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
+        let mut respone2 = MockResponse::new();
+        respone2.expect_status().return_const(StatusCode::OK);
+        let mut headers = MockHeaderMap::new();
+
+        headers.expect_get::<&str>().return_const(Some(&HEADER));
+
+        respone1.expect_text().return_once(|| {
+            Ok(r#"[{
+    "id": "String",
+    "name": "String",
+    "balance": "String",
+    "currency": "String",
+    "type": "String",
+    "primary": false,
+    "active": false,
+    "available_on_consumer": false,
+    "hold_balance": "0.1",
+    "hold_currency": "string"
+            }
+    ]"#
+            .to_string())
+        });
+        respone2.expect_headers().return_const(headers);
+
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client);
+
+        let account = APIKeyData {
+            key: base64::encode("API KEY"),
+            secret: base64::encode("API Secret"),
+            passphrase: "passphrase".to_string(),
+        };
+
+        let output = api.get_all_wallets(account).await.unwrap();
+
+        let acct = output;
+        assert_eq!(acct.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn mocked_api_get_all_currencies() {
+        let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
+        let mut respone2 = MockResponse::new();
+        respone2.expect_status().return_const(StatusCode::OK);
+        let mut headers = MockHeaderMap::new();
+
+        headers.expect_get::<&str>().return_const(Some(&HEADER));
+
+        respone1.expect_text().return_once(|| {
+            Ok(r#"[{
+                "id": "String",
+                "name": "String",
+                "min_size": "0.1",
+                "status": "String",
+                "message": "String",
+                "max_precision": "0.1",
+                "convertible_to": ["asda"],
+                "details": {}
+            }
+    ]"#
+            .to_string())
+        });
+        respone2.expect_headers().return_const(headers);
+
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client);
+
+        let output = api.get_currencies().await.unwrap();
+
+        let acct = output;
+        assert_eq!(acct.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn mocked_api_get_all_currency() {
+        let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
+        let mut respone2 = MockResponse::new();
+        respone2.expect_status().return_const(StatusCode::OK);
+        let mut headers = MockHeaderMap::new();
+
+        headers.expect_get::<&str>().return_const(Some(&HEADER));
+
+        respone1.expect_text().return_once(|| {
+            Ok(r#"{
+                "id": "String",
+                "name": "String",
+                "min_size": "0.1",
+                "status": "String",
+                "message": "String",
+                "max_precision": "0.1",
+                "convertible_to": ["asda"],
+                "details": {}
+            }
+    "#
+            .to_string())
+        });
+        respone2.expect_headers().return_const(headers);
+
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client);
+
+        api.get_currency("mine".to_string()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn mocked_api_get_conversion_invalid() {
+        let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
+        let mut respone2 = MockResponse::new();
+        respone2.expect_status().return_const(StatusCode::OK);
+        let mut headers = MockHeaderMap::new();
+
+        let account = APIKeyData {
+            key: base64::encode("API KEY"),
+            secret: base64::encode("API Secret"),
+            passphrase: "passphrase".to_string(),
+        };
+
+        headers.expect_get::<&str>().return_const(Some(&HEADER));
+
+        respone1
+            .expect_text()
+            .return_once(|| Ok(r#"{"my json": 123}"#.to_string()));
+        respone2.expect_headers().return_const(headers);
+
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client);
+
+        let output = api.get_conversion(account, "", "").await;
+        assert!(output.is_err());
+    }
+
+    #[tokio::test]
+    async fn mocked_api_get_fills_invalid() {
+        let respone1 = MockResponse::new();
+        let mut respone2 = MockResponse::new();
+        respone2.expect_status().return_const(StatusCode::OK);
+        let mut headers = MockHeaderMap::new();
+
+        let account = APIKeyData {
+            key: base64::encode("API KEY"),
+            secret: base64::encode("API Secret"),
+            passphrase: "passphrase".to_string(),
+        };
+
+        headers.expect_get::<&str>().return_const(Some(&HEADER));
+
+        respone2
+            .expect_text()
+            .return_once(|| Ok(r#"{"my json": 123}"#.to_string()));
+        respone2.expect_headers().return_const(headers);
+
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client);
+
+        let output = api
+            .get_fills(
+                account,
+                Some("".to_string()),
+                Some("".to_string()),
+                Some("".to_string()),
+            )
+            .await;
+        assert!(output.is_err());
+    }
+
+    #[tokio::test]
+    async fn mocked_api_create_order_invalid() {
+        let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
+        let mut respone2 = MockResponse::new();
+        respone2.expect_status().return_const(StatusCode::OK);
+        let mut headers = MockHeaderMap::new();
+
+        let account = APIKeyData {
+            key: base64::encode("API KEY"),
+            secret: base64::encode("API Secret"),
+            passphrase: "passphrase".to_string(),
+        };
+
+        headers.expect_get::<&str>().return_const(Some(&HEADER));
+
+        respone1
+            .expect_text()
+            .return_once(|| Ok(r#"{"my json": 123}"#.to_string()));
+        respone2.expect_headers().return_const(headers);
+
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client);
+
+        let order = MarketOrder::new("".to_string(), Side::BUY, MarketOrderValue::Size(0.1));
+
+        let output = api.create_order(account, order).await;
+        assert!(output.is_err());
+    }
+
+    #[tokio::test]
+    async fn mocked_api_get_single_order_invalid() {
+        let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
+        let mut respone2 = MockResponse::new();
+        respone2.expect_status().return_const(StatusCode::OK);
+        let mut headers = MockHeaderMap::new();
+
+        let account = APIKeyData {
+            key: base64::encode("API KEY"),
+            secret: base64::encode("API Secret"),
+            passphrase: "passphrase".to_string(),
+        };
+
+        headers.expect_get::<&str>().return_const(Some(&HEADER));
+
+        respone1
+            .expect_text()
+            .return_once(|| Ok(r#"{"my json": 123}"#.to_string()));
+        respone2.expect_headers().return_const(headers);
+
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client);
+
+        let output = api.get_single_order(account, "".to_string()).await;
+        assert!(output.is_err());
+    }
+
+    #[tokio::test]
+    async fn mocked_api_get_orders_invalid() {
+        let respone1 = MockResponse::new();
+        let mut respone2 = MockResponse::new();
+        respone2.expect_status().return_const(StatusCode::OK);
+        let mut headers = MockHeaderMap::new();
+
+        let account = APIKeyData {
+            key: base64::encode("API KEY"),
+            secret: base64::encode("API Secret"),
+            passphrase: "passphrase".to_string(),
+        };
+
+        headers.expect_get::<&str>().return_const(Some(&HEADER));
+
+        respone2
+            .expect_text()
+            .return_once(|| Ok(r#"{"my json": 123}"#.to_string()));
+        respone2.expect_headers().return_const(headers);
+
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client);
+
+        let output = api
+            .get_orders(account, Some("".to_string()), Some("".to_string()))
+            .await;
+        assert!(output.is_err());
+    }
+
+    #[tokio::test]
+    async fn mocked_api_cancel_order() {
+        let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
+        let respone2 = MockResponse::new();
+
+        let account = APIKeyData {
+            key: base64::encode("API KEY"),
+            secret: base64::encode("API Secret"),
+            passphrase: "passphrase".to_string(),
+        };
+
+        respone1
+            .expect_text()
+            .return_once(|| Ok(r#""my_order_id""#.to_string()));
+
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client.clone());
+
+        let output = api
+            .cancel_order(account, "my_order_id".to_string(), None)
+            .await
+            .unwrap();
+
+        assert_eq!(output, "my_order_id");
+        assert_eq!(
+            mock_client.requested_method.lock().unwrap().clone(),
+            Some(reqwest::Method::DELETE)
+        );
+        assert!(mock_client
+            .requested_url
+            .lock()
+            .unwrap()
+            .contains("/orders/my_order_id"));
+    }
+
+    #[tokio::test]
+    async fn mocked_api_cancel_order_by_client_oid() {
+        let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
+        let respone2 = MockResponse::new();
+
+        let account = APIKeyData {
+            key: base64::encode("API KEY"),
+            secret: base64::encode("API Secret"),
+            passphrase: "passphrase".to_string(),
+        };
+
+        respone1
+            .expect_text()
+            .return_once(|| Ok(r#""my_order_id""#.to_string()));
+
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client.clone());
+
+        let output = api
+            .cancel_order_by_client_oid(account, "my_client_oid".to_string(), None)
+            .await
+            .unwrap();
+
+        assert_eq!(output, "my_order_id");
+        assert!(mock_client
+            .requested_url
+            .lock()
+            .unwrap()
+            .contains("/orders/client:my_client_oid"));
+    }
+
+    #[tokio::test]
+    async fn mocked_api_cancel_all_orders() {
+        let mut respone1 = MockResponse::new();
+        respone1.expect_status().return_const(StatusCode::OK);
+        let respone2 = MockResponse::new();
+
+        let account = APIKeyData {
+            key: base64::encode("API KEY"),
+            secret: base64::encode("API Secret"),
+            passphrase: "passphrase".to_string(),
+        };
+
+        respone1
+            .expect_text()
+            .return_once(|| Ok(r#"["order_1", "order_2"]"#.to_string()));
+
+        let mock_request_builder = MockRequestBuilder::new_mock(vec![respone1, respone2]);
+        let mock_client = MockClient::new_mock(mock_request_builder.clone());
+        let api = CBProAPI::from_client(mock_client.clone());
+
+        let output = api
+            .cancel_all_orders(account, Some("ETH-USD".to_string()), None)
+            .await
+            .unwrap();
+
+        assert_eq!(output, vec!["order_1", "order_2"]);
+        assert_eq!(
+            mock_client.requested_method.lock().unwrap().clone(),
+            Some(reqwest::Method::DELETE)
+        );
+
+        let query_calls: Vec<CallInfo> = mock_request_builder
+            .call_info
+            .lock()
+            .unwrap()
+            .split_off(0)
+            .into_iter()
+            .filter(|x| x.method_name == "query")
+            .collect();
+
+        assert_eq!(1, query_calls.len());
+        assert!(query_calls[0].arguments[0]
+            .argument_value
+            .contains(r#"("product_id", "ETH-USD")"#));
+    }
+
+    #[tokio::test]
+    async fn mocked_api_subscription_request() {
+        let builder = SubscriptionBuilder::new()
+            .subscribe_to_heartbeat("".to_string())
+            .subscribe_to_status()
+            .subscribe_to_ticker("".to_string())
+            .subscribe_to_full("".to_string())
+            .subscribe_to_snapshot("".to_string());
+
+        let request = builder.build();
+
+        let serialized = serde_json::to_string(&request).unwrap();
+        assert!(serialized.contains(r#""name":"heartbeat","product_ids":[""]"#));
+        assert!(serialized.contains(r#""name":"ticker","product_ids":[""]"#));
+        assert!(serialized.contains(r#""name":"level2","product_ids":[""]"#));
+        assert!(serialized.contains(r#""name":"full","product_ids":[""]"#));
+        assert!(serialized.contains(r#""name":"status""#));
+    }
+
+    #[tokio::test]
+    async fn mocked_api_subscription_request_vecs() {
+        let builder = SubscriptionBuilder::new()
+            .subscribe_to_heartbeat_vec(&mut vec!["".to_string()])
+            .subscribe_to_ticker_vec(&mut vec!["".to_string()])
+            .subscribe_to_full_vec(&mut vec!["".to_string()])
+            .subscribe_to_snapshot_vec(&mut vec!["".to_string()]);
+
+        let request = builder.build();
+
+        let serialized = serde_json::to_string(&request).unwrap();
+        assert!(serialized.contains(r#""name":"heartbeat","product_ids":[""]"#));
+        assert!(serialized.contains(r#""name":"ticker","product_ids":[""]"#));
+        assert!(serialized.contains(r#""name":"level2","product_ids":[""]"#));
+        assert!(serialized.contains(r#""name":"full","product_ids":[""]"#));
+    }
+
+    #[tokio::test]
+    async fn mock_stream_tc1() {
+        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
+            Ok(_) => {}
+            Err(_) => {}
+        };
+
+        let mocked_tcp = MockTcpStream::new();
+        let mut mock_tls = MockTlsStream::new(&mocked_tcp);
+
+        mock_tls.expect_poll_write(|buf| buf == vec![0, 0, 0].as_slice());
+        let out = mock_tls.write(vec![0, 0, 0].as_slice()).await.unwrap();
+        assert_eq!(out, 3);
+    }
+
+    #[tokio::test]
+    async fn mock_stream_tc2() {
+        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
+            Ok(_) => {}
+            Err(_) => {}
+        };
+
+        let mocked_tcp = MockTcpStream::new();
+        let mut mock_tls = MockTlsStream::new(&mocked_tcp);
+
+        let output = vec![0, 1, 1];
+        mock_tls.expect_poll_read(output.clone());
+        let mut out: [u8; 3] = [0; 3];
+        mock_tls.read_exact(&mut out).await.unwrap();
+
+        assert_eq!(out.as_slice(), output.as_slice());
+    }
+
+    // #[tokio::test]
+    // async fn mock_stream_tc3() {
+    //     let request_builder = MockRequestBuilder::new_mock(vec![]);
+    //     let client = MockClient::new_mock(request_builder);
+    //
+    //     let mocked_tcp = MockTcpStream::new();
     //     let mut mock_tls = MockTlsStream::new(&mocked_tcp);
     //     let mock_connector = MockTlsConnector::new(&mock_tls);
     //     mock_tls.expect_poll_write(|buf| {
@@ -1130,376 +2264,2029 @@ mod tests {
     // }
 }
 
-#[cfg(all(test, feature = "mock"))]
-mod websocket_stream_tests {
-    use log::LevelFilter;
-    use reqwest::Url;
-    use simple_logger::SimpleLogger;
-    use std::sync::Arc;
-    use tokio::io::{
-        AsyncReadExt,
-        AsyncWriteExt,
-    };
-    use tokio::sync::Mutex;
-    use tokio::time::Instant;
+#[cfg(all(test, feature = "mock"))]
+mod websocket_stream_tests {
+    use futures::StreamExt;
+    use log::LevelFilter;
+    use reqwest::Url;
+    use simple_logger::SimpleLogger;
+    use std::sync::Arc;
+    use tokio::io::{
+        AsyncReadExt,
+        AsyncWriteExt,
+    };
+    use tokio::sync::Mutex;
+
+    use crate::api::{
+        APIKeyData,
+        CBProAPI,
+        CBProAPIBuilder,
+        RetryPolicy,
+        SubscriptionBuilder,
+        UnsubscribeBuilder,
+    };
+    use crate::datastructs::orders::Side;
+    use crate::datastructs::products::{
+        Level3BookOrder,
+        Level3ProductBook,
+    };
+    use crate::datastructs::websocket::{
+        HeartbeatMessage,
+        Level2Change,
+        Level2Snapshot,
+        Quote,
+        StatusMessage,
+        TickerMessage,
+        WebsocketMessage,
+    };
+    use crate::depth::{
+        BookDepth,
+        Depth,
+        Fill,
+        FillAmount,
+    };
+    use crate::dispatcher::{
+        DispatcherConfig,
+        MessageDispatcher,
+        SlowConsumerPolicy,
+    };
+    use crate::errors::{
+        Error,
+        WebsocketError,
+    };
+    use crate::l3_order_book::L3OrderBook;
+    use crate::sequence::{
+        SequenceEvent,
+        SequenceFeed,
+        SequenceTracker,
+    };
+    use crate::tests::{
+        server_time_response,
+        server_unavailable_request,
+    };
+    use crate::watchdog::{
+        FeedEvent,
+        HeartbeatWatchdog,
+        WatchdogConfig,
+    };
+
+    use crate::mocked::{
+        MockClient,
+        MockIOBuilder,
+        MockRequestBuilder,
+        MockResponse,
+        MockStream,
+        MockWebsocketIOBuilder,
+    };
+    use crate::order_book::{
+        BookSide,
+        OrderBook,
+        OrderBookEntry,
+    };
+    use crate::websocket_lite::{
+        ControlFrame,
+        FrameParser,
+        ParsedFrame,
+        ParserState,
+        WebsocketStream,
+        WebsocketStreamConnector,
+    };
+
+    pub(crate) fn default_websocket_upgrade_resp() -> Vec<u8> {
+        format!("{}{}{}{}{}{}{}{}{}{}",
+            "HTTP/1.1 101 Switching Protocols\r\n",
+            "Date: Sun, 10 Apr 2022 13:23:52 GMT\r\n",
+            "Connection: upgrade\r\n",
+            "Upgrade: websocket\r\n",
+            "Sec-WebSocket-Accept: HSmrc0sMlYUkAGmm5OPpG2HaGWk=\r\n",
+            "CF-Cache-Status: DYNAMIC\r\n",
+            "Expect-CT: max-age=604800, report-uri=\"https://report-uri.cloudflare.com/cdn-cgi/beacon/expect-ct\"\r\n",
+            "Server: cloudflare\r\n",
+            "CF-RAY: 6f9bccaa4a8f02ed-MIA\r\n",
+            "\r\n")
+        .as_bytes().to_vec()
+    }
+
+    pub(crate) fn frame_fin_16_0mask_256() -> Vec<u8> {
+        let mut return_val: Vec<u8> = vec![
+            0b10001111, 0b11111110, 0b00000001, 0b00000000, 0x00, 0x00, 0x00, 0x00,
+        ];
+        return_val.append(&mut [0u8; 256].to_vec());
+        return_val
+    }
+
+    pub(crate) fn frame_fin_16_0mask_256_long() -> Vec<u8> {
+        let mut return_val: Vec<u8> = vec![
+            0b10001111, 0b11111111, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000,
+            0b00000000, 0b00000001, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000,
+        ];
+        return_val.append(&mut [0u8; 256].to_vec());
+        return_val
+    }
+
+    pub(crate) fn frame_fin_16_0mask_0_long() -> Vec<u8> {
+        let return_val: Vec<u8> = vec![
+            0b10001111, 0b11111111, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000,
+            0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000,
+        ];
+        return_val
+    }
+
+    pub(crate) fn frame_fin_16_0mask_0_medium() -> Vec<u8> {
+        let return_val: Vec<u8> = vec![
+            0b10001111, 0b11111110, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000,
+            0b00000000,
+        ];
+        return_val
+    }
+
+    pub(crate) fn frame_fin_16_1_short() -> Vec<u8> {
+        let return_val: Vec<u8> = vec![0b10001111, 0b00000001, 0x00];
+        return_val
+    }
+
+    pub(crate) fn frame_fin_16_1_long() -> Vec<u8> {
+        let return_val: Vec<u8> = vec![
+            0b10001111, 0b01111111, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000,
+            0b00000000, 0b00000000, 0b00000001, 0x00,
+        ];
+        return_val
+    }
+
+    pub(crate) fn frame_fin_16_1_medium() -> Vec<u8> {
+        let return_val: Vec<u8> = vec![0b10001111, 0b01111110, 0b00000000, 0b00000001, 0x00];
+        return_val
+    }
+
+    #[allow(unused)]
+    pub(crate) fn frame_0() -> Vec<u8> {
+        vec![0x00, 0x00]
+    }
+
+    pub(crate) fn frame_fin_0() -> Vec<u8> {
+        vec![0b10000000, 0x00]
+    }
+
+    #[tokio::test]
+    async fn websocket_stream_tc1() {
+        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
+            Ok(_) => {}
+            Err(_) => {}
+        };
+        let mut stream = MockStream::new(&[0, 1, 2, 3]);
+
+        assert_eq!(stream.read_u8().await.unwrap(), 0);
+        assert_eq!(stream.read_u8().await.unwrap(), 1);
+        assert_eq!(stream.read_u8().await.unwrap(), 2);
+        assert_eq!(stream.read_u8().await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn websocket_stream_tc2() {
+        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
+            Ok(_) => {}
+            Err(_) => {}
+        };
+
+        let mut handshake_response = default_websocket_upgrade_resp();
+
+        let frame: Vec<u8> = frame_fin_16_0mask_256();
+
+        handshake_response.append(&mut frame.clone());
+        let mock_stream = MockStream::new(&handshake_response);
+
+        //let stream = default_tls_stream("https://ws-feed.exchange.coinbase.com/").await;
+
+        let mut websocket = WebsocketStreamConnector::new_no_sec()
+            .connect(
+                mock_stream,
+                &Url::parse("https://ws-feed.exchange.coinbase.com/").unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let mut output = Vec::new();
+        websocket.read_to_end(&mut output).await.unwrap();
+
+        //let writes: Vec<u8> = stream.writes.lock().unwrap().clone().into_iter().collect();
+        assert_eq!(frame[8..], output);
+    }
+
+    #[tokio::test]
+    async fn websocket_stream_tc3() {
+        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
+            Ok(_) => {}
+            Err(_) => {}
+        };
+
+        let mut handshake_response = default_websocket_upgrade_resp();
+
+        let frame: Vec<u8> = frame_fin_0();
+
+        handshake_response.append(&mut frame.clone());
+        let mock_stream = MockStream::new(&handshake_response);
+
+        //let stream = default_tls_stream("https://ws-feed.exchange.coinbase.com/").await;
+
+        let mut websocket = WebsocketStreamConnector::new_no_sec()
+            .connect(
+                mock_stream,
+                &Url::parse("https://ws-feed.exchange.coinbase.com/").unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let mut output = Vec::new();
+        websocket.read_to_end(&mut output).await.unwrap();
+
+        //let writes: Vec<u8> = stream.writes.lock().unwrap().clone().into_iter().collect();
+        assert_eq!(output.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn websocket_stream_tc4() {
+        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
+            Ok(_) => {}
+            Err(_) => {}
+        };
+
+        let mut handshake_response = default_websocket_upgrade_resp();
+
+        let frame: Vec<u8> = frame_fin_16_0mask_256_long();
+
+        handshake_response.append(&mut frame.clone());
+        let mock_stream = MockStream::new(&handshake_response);
+
+        //let stream = default_tls_stream("https://ws-feed.exchange.coinbase.com/").await;
+
+        let mut websocket = WebsocketStreamConnector::new_no_sec()
+            .connect(
+                mock_stream,
+                &Url::parse("https://ws-feed.exchange.coinbase.com/").unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let mut output = Vec::new();
+        websocket.read_to_end(&mut output).await.unwrap();
+
+        //let writes: Vec<u8> = stream.writes.lock().unwrap().clone().into_iter().collect();
+        assert_eq!(frame[14..], output);
+    }
+
+    #[tokio::test]
+    async fn websocket_stream_tc5() {
+        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
+            Ok(_) => {}
+            Err(_) => {}
+        };
+
+        let mut handshake_response = default_websocket_upgrade_resp();
+
+        let frame: Vec<u8> = frame_fin_16_0mask_0_long();
+
+        handshake_response.append(&mut frame.clone());
+        let mock_stream = MockStream::new(&handshake_response);
+
+        //let stream = default_tls_stream("https://ws-feed.exchange.coinbase.com/").await;
+
+        let mut websocket = WebsocketStreamConnector::new_no_sec()
+            .connect(
+                mock_stream,
+                &Url::parse("https://ws-feed.exchange.coinbase.com/").unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let mut output = Vec::new();
+        websocket.read_to_end(&mut output).await.unwrap();
+
+        //let writes: Vec<u8> = stream.writes.lock().unwrap().clone().into_iter().collect();
+        assert_eq!(0, output.len());
+    }
+
+    #[tokio::test]
+    async fn websocket_stream_tc6() {
+        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
+            Ok(_) => {}
+            Err(_) => {}
+        };
+
+        let mut handshake_response = default_websocket_upgrade_resp();
+
+        let frame: Vec<u8> = frame_fin_16_0mask_0_medium();
+
+        handshake_response.append(&mut frame.clone());
+        let mock_stream = MockStream::new(&handshake_response);
+
+        //let stream = default_tls_stream("https://ws-feed.exchange.coinbase.com/").await;
+
+        let mut websocket = WebsocketStreamConnector::new_no_sec()
+            .connect(
+                mock_stream,
+                &Url::parse("https://ws-feed.exchange.coinbase.com/").unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let mut output = Vec::new();
+        websocket.read_to_end(&mut output).await.unwrap();
+
+        //let writes: Vec<u8> = stream.writes.lock().unwrap().clone().into_iter().collect();
+        assert_eq!(frame[8..], output);
+    }
+
+    #[tokio::test]
+    async fn websocket_stream_tc7() {
+        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
+            Ok(_) => {}
+            Err(_) => {}
+        };
+
+        let mut handshake_response = default_websocket_upgrade_resp();
+
+        let frame: Vec<u8> = frame_fin_16_1_short();
+
+        handshake_response.append(&mut frame.clone());
+        let mock_stream = MockStream::new(&handshake_response);
+
+        //let stream = default_tls_stream("https://ws-feed.exchange.coinbase.com/").await;
+
+        let mut websocket = WebsocketStreamConnector::new_no_sec()
+            .connect(
+                mock_stream,
+                &Url::parse("https://ws-feed.exchange.coinbase.com/").unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let mut output = Vec::new();
+        websocket.read_to_end(&mut output).await.unwrap();
+
+        //let writes: Vec<u8> = stream.writes.lock().unwrap().clone().into_iter().collect();
+        assert_eq!(1, output.len());
+    }
+
+    #[tokio::test]
+    async fn websocket_stream_tc8() {
+        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
+            Ok(_) => {}
+            Err(_) => {}
+        };
+
+        let mut handshake_response = default_websocket_upgrade_resp();
+
+        let frame: Vec<u8> = frame_fin_16_1_medium();
+
+        handshake_response.append(&mut frame.clone());
+        let mock_stream = MockStream::new(&handshake_response);
+
+        //let stream = default_tls_stream("https://ws-feed.exchange.coinbase.com/").await;
+
+        let mut websocket = WebsocketStreamConnector::new_no_sec()
+            .connect(
+                mock_stream,
+                &Url::parse("https://ws-feed.exchange.coinbase.com/").unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let mut output = Vec::new();
+        websocket.read_to_end(&mut output).await.unwrap();
+
+        //let writes: Vec<u8> = stream.writes.lock().unwrap().clone().into_iter().collect();
+        assert_eq!(1, output.len());
+    }
+
+    #[tokio::test]
+    async fn websocket_stream_tc9() {
+        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
+            Ok(_) => {}
+            Err(_) => {}
+        };
+
+        let mut handshake_response = default_websocket_upgrade_resp();
+
+        let frame: Vec<u8> = frame_fin_16_1_long();
+
+        handshake_response.append(&mut frame.clone());
+        let mock_stream = MockStream::new(&handshake_response);
+
+        //let stream = default_tls_stream("https://ws-feed.exchange.coinbase.com/").await;
+
+        let mut websocket = WebsocketStreamConnector::new_no_sec()
+            .connect(
+                mock_stream,
+                &Url::parse("https://ws-feed.exchange.coinbase.com/").unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let mut output = Vec::new();
+        websocket.read_to_end(&mut output).await.unwrap();
+
+        //let writes: Vec<u8> = stream.writes.lock().unwrap().clone().into_iter().collect();
+        assert_eq!(1, output.len());
+    }
+
+    /// Unmasked final frame as the server would send it.
+    pub(crate) fn server_frame(op_code: u8, payload: &[u8]) -> Vec<u8> {
+        server_fragment(op_code, true, payload)
+    }
+
+    /// Unmasked frame as the server would send it, with the FIN bit set only when `final_flag` is.
+    pub(crate) fn server_fragment(op_code: u8, final_flag: bool, payload: &[u8]) -> Vec<u8> {
+        let fin = if final_flag { 0b10000000 } else { 0 };
+        let mut frame = vec![fin | op_code];
+        if payload.len() < 126 {
+            frame.push(payload.len() as u8);
+        } else {
+            frame.push(126);
+            frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        }
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    async fn connect_mock_websocket(frames: &[u8]) -> (MockStream, WebsocketStream<MockStream>) {
+        let mut response = default_websocket_upgrade_resp();
+        response.extend_from_slice(frames);
+        let mock_stream = MockStream::new(&response);
+
+        let websocket = WebsocketStreamConnector::new_no_sec()
+            .connect(
+                mock_stream.clone(),
+                &Url::parse("https://ws-feed.exchange.coinbase.com/").unwrap(),
+            )
+            .await
+            .unwrap();
+
+        (mock_stream, websocket)
+    }
+
+    /// Parses the frame written by the client at the given write index.
+    fn written_frame(stream: &MockStream, index: usize) -> ParsedFrame {
+        let writes = stream.writes.lock().unwrap();
+        let mut parser = FrameParser::default();
+        writes[index]
+            .iter()
+            .find_map(|byte| parser.process_byte(byte).unwrap())
+            .unwrap()
+    }
+
+    fn control_frame(err: std::io::Error) -> ControlFrame {
+        err.into_inner()
+            .unwrap()
+            .downcast::<ControlFrame>()
+            .map(|control| *control)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn websocket_stream_ping_is_answered_and_surfaced() {
+        let mut frames = server_frame(0x9, b"hi");
+        frames.append(&mut server_frame(0x1, b"data"));
+        let (mock_stream, mut websocket) = connect_mock_websocket(&frames).await;
+
+        let mut output = Vec::new();
+        let err = websocket.read_to_end(&mut output).await.unwrap_err();
+        assert_eq!(control_frame(err), ControlFrame::Ping(b"hi".to_vec()));
+
+        let pong = written_frame(&mock_stream, 1);
+        assert_eq!(pong.op_code, 0xA);
+        assert_eq!(pong.payload, b"hi");
+
+        websocket.read_to_end(&mut output).await.unwrap();
+        assert_eq!(output, b"data");
+    }
+
+    #[tokio::test]
+    async fn websocket_stream_pong_is_ignored() {
+        let mut frames = server_frame(0xA, b"");
+        frames.append(&mut server_frame(0x1, b"data"));
+        let (mock_stream, mut websocket) = connect_mock_websocket(&frames).await;
+
+        let mut output = Vec::new();
+        websocket.read_to_end(&mut output).await.unwrap();
+        assert_eq!(output, b"data");
+        assert_eq!(mock_stream.writes.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn websocket_stream_server_close_is_echoed() {
+        let mut payload = 1001u16.to_be_bytes().to_vec();
+        payload.extend_from_slice(b"going away");
+        let (mock_stream, mut websocket) =
+            connect_mock_websocket(&server_frame(0x8, &payload)).await;
+
+        let mut output = Vec::new();
+        let err = websocket.read_to_end(&mut output).await.unwrap_err();
+        assert_eq!(
+            control_frame(err),
+            ControlFrame::Close {
+                code: Some(1001),
+                reason: "going away".to_string()
+            }
+        );
+
+        let reply = written_frame(&mock_stream, 1);
+        assert_eq!(reply.op_code, 0x8);
+        assert_eq!(reply.payload, 1001u16.to_be_bytes());
+
+        websocket.read_to_end(&mut output).await.unwrap();
+        assert!(output.is_empty());
+        assert!(websocket.write_all(b"late").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn websocket_stream_shutdown_waits_for_close() {
+        let mut frames = server_frame(0x1, b"in flight");
+        frames.append(&mut server_frame(0x8, &1000u16.to_be_bytes()));
+        let (mock_stream, mut websocket) = connect_mock_websocket(&frames).await;
+
+        websocket.shutdown().await.unwrap();
+
+        let close = written_frame(&mock_stream, 1);
+        assert_eq!(close.op_code, 0x8);
+        assert_eq!(close.payload, 1000u16.to_be_bytes());
+        assert_eq!(mock_stream.writes.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn api_websocket_ping_and_close() {
+        let mut frames = server_frame(0x1, &websocket_sub_response());
+        frames.append(&mut server_frame(0x9, b"ping"));
+        frames.append(&mut server_frame(0x8, &1000u16.to_be_bytes()));
+        let mut response = default_websocket_upgrade_resp();
+        response.append(&mut frames);
+        let stream = MockStream::new(&response);
+
+        let client = MockClient::new_mock(MockRequestBuilder::new_mock(vec![]));
+        let api =
+            CBProAPI::from_client_and_io_builder(client, MockWebsocketIOBuilder::new(&stream));
+
+        api.subscribe_to_websocket(
+            SubscriptionBuilder::new()
+                .subscribe_to_heartbeat("ETH-EUR".to_string())
+                .build(),
+        )
+        .await
+        .unwrap();
+
+        match api.read_websocket().await.unwrap() {
+            WebsocketMessage::Ping(payload) => assert_eq!(payload, b"ping"),
+            other => panic!("expected ping, got {:?}", other),
+        }
+
+        match api.read_websocket().await {
+            Err(WebsocketError::ConnectionClosed { code, .. }) => assert_eq!(code, Some(1000)),
+            other => panic!("expected close, got {:?}", other),
+        }
+
+        api.close_websocket().await.unwrap();
+    }
+
+    /// Splits a message into a start frame followed by continuation frames of at most `size` bytes.
+    pub(crate) fn fragmented_message(payload: &[u8], size: usize) -> Vec<u8> {
+        let chunks = payload.chunks(size).collect::<Vec<&[u8]>>();
+        let last = chunks.len() - 1;
+
+        chunks
+            .into_iter()
+            .enumerate()
+            .flat_map(|(index, chunk)| {
+                let op_code = if index == 0 { 0x1 } else { 0x0 };
+                server_fragment(op_code, index == last, chunk)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn websocket_stream_tc10() {
+        let frames = fragmented_message(b"split across frames", 6);
+        let (_, mut websocket) = connect_mock_websocket(&frames).await;
+
+        let mut output = Vec::new();
+        websocket.read_to_end(&mut output).await.unwrap();
+
+        assert_eq!(output, b"split across frames");
+    }
+
+    #[tokio::test]
+    async fn websocket_stream_tc11() {
+        let mut frames = server_fragment(0x1, false, b"first ");
+        frames.append(&mut server_frame(0x9, b"ping"));
+        frames.append(&mut server_fragment(0x0, true, b"second"));
+        let (_, mut websocket) = connect_mock_websocket(&frames).await;
+
+        let mut output = Vec::new();
+        let err = websocket.read_to_end(&mut output).await.unwrap_err();
+        assert_eq!(control_frame(err), ControlFrame::Ping(b"ping".to_vec()));
+
+        websocket.read_to_end(&mut output).await.unwrap();
+        assert_eq!(output, b"first second");
+    }
+
+    #[tokio::test]
+    async fn websocket_stream_tc12() {
+        let mut frames = fragmented_message(b"fragmented", 3);
+        frames.append(&mut server_frame(0x1, b"whole"));
+        let (_, mut websocket) = connect_mock_websocket(&frames).await;
+
+        let mut output = Vec::new();
+        websocket.read_to_end(&mut output).await.unwrap();
+        assert_eq!(output, b"fragmented");
+
+        let mut output = Vec::new();
+        websocket.read_to_end(&mut output).await.unwrap();
+        assert_eq!(output, b"whole");
+    }
+
+    #[tokio::test]
+    async fn websocket_stream_tc13() {
+        let mut frames = server_fragment(0x1, false, b"unfinished");
+        frames.append(&mut server_frame(0x1, b"interrupting"));
+        let (_, mut websocket) = connect_mock_websocket(&frames).await;
 
-    use crate::api::{
-        CBProAPI,
-        SubscriptionBuilder,
-    };
-    use crate::datastructs::orders::Side;
+        let mut output = Vec::new();
+        let err = websocket.read_to_end(&mut output).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
 
-    use crate::mocked::{
-        MockClient,
-        MockIOBuilder,
-        MockRequestBuilder,
-        MockStream,
-    };
-    use crate::order_book::{
-        OrderBook,
-        OrderBookEntry,
-    };
-    use crate::websocket_lite::{
-        FrameParser,
-        ParserState,
-        WebsocketStreamConnector,
-    };
+    #[tokio::test]
+    async fn websocket_stream_tc14() {
+        let (mock_stream, mut websocket) = connect_mock_websocket(&[]).await;
 
-    pub(crate) fn default_websocket_upgrade_resp() -> Vec<u8> {
-        format!("{}{}{}{}{}{}{}{}{}{}",
-            "HTTP/1.1 101 Switching Protocols\r\n",
-            "Date: Sun, 10 Apr 2022 13:23:52 GMT\r\n",
-            "Connection: upgrade\r\n",
-            "Upgrade: websocket\r\n",
-            "Sec-WebSocket-Accept: HSmrc0sMlYUkAGmm5OPpG2HaGWk=\r\n",
-            "CF-Cache-Status: DYNAMIC\r\n",
-            "Expect-CT: max-age=604800, report-uri=\"https://report-uri.cloudflare.com/cdn-cgi/beacon/expect-ct\"\r\n",
-            "Server: cloudflare\r\n",
-            "CF-RAY: 6f9bccaa4a8f02ed-MIA\r\n",
-            "\r\n")
-        .as_bytes().to_vec()
+        let message = vec![b'a'; 300];
+        websocket.write_all(&message).await.unwrap();
+
+        assert_eq!(written_frame(&mock_stream, 1).payload, message);
     }
 
-    pub(crate) fn frame_fin_16_0mask_256() -> Vec<u8> {
-        let mut return_val: Vec<u8> = vec![
-            0b10001111, 0b11111110, 0b00000001, 0b00000000, 0x00, 0x00, 0x00, 0x00,
-        ];
-        return_val.append(&mut [0u8; 256].to_vec());
-        return_val
+    #[tokio::test]
+    async fn api_websocket_fragmented_snapshot() {
+        let mut frames = server_frame(0x1, &websocket_sub_response());
+        frames.append(&mut fragmented_message(
+            &websocket_l2snapshot_message(),
+            100,
+        ));
+        let mut response = default_websocket_upgrade_resp();
+        response.append(&mut frames);
+        let stream = MockStream::new(&response);
+
+        let client = MockClient::new_mock(MockRequestBuilder::new_mock(vec![]));
+        let api =
+            CBProAPI::from_client_and_io_builder(client, MockWebsocketIOBuilder::new(&stream));
+
+        api.subscribe_to_websocket(
+            SubscriptionBuilder::new()
+                .subscribe_to_snapshot("ETH-USD".to_string())
+                .build(),
+        )
+        .await
+        .unwrap();
+
+        match api.read_websocket().await.unwrap() {
+            WebsocketMessage::Snapshot(snapshot) => assert_eq!(snapshot.asks.len(), 11),
+            other => panic!("expected snapshot, got {:?}", other),
+        }
+    }
+
+    /// Mock stream that completes the upgrade and then serves the given frames before EOF.
+    fn websocket_session(frames: &[u8]) -> MockStream {
+        let mut response = default_websocket_upgrade_resp();
+        response.extend_from_slice(frames);
+        MockStream::new(&response)
+    }
+
+    fn reconnecting_api(streams: Vec<MockStream>) -> CBProAPI {
+        CBProAPIBuilder::new()
+            .set_client(MockClient::new_mock(MockRequestBuilder::new_mock(vec![])))
+            .set_io_builder(MockWebsocketIOBuilder::new_sequence(streams))
+            .auto_reconnect_websocket(RetryPolicy {
+                base_delay: std::time::Duration::from_millis(1),
+                ..Default::default()
+            })
+            .build()
+    }
+
+    #[tokio::test]
+    async fn api_websocket_reconnect_replays_subscriptions() {
+        let first = websocket_session(&server_frame(0x1, &websocket_sub_response()));
+        let mut frames = server_frame(0x1, &websocket_sub_response());
+        frames.append(&mut server_frame(0x1, &websocket_heartbeat_message()));
+        let second = websocket_session(&frames);
+
+        let api = reconnecting_api(vec![first, second.clone()]);
+        api.subscribe_to_websocket(
+            SubscriptionBuilder::new()
+                .subscribe_to_heartbeat("ETH-USD".to_string())
+                .build(),
+        )
+        .await
+        .unwrap();
+
+        match api.read_websocket().await.unwrap() {
+            WebsocketMessage::Reconnected => {}
+            other => panic!("expected reconnect, got {:?}", other),
+        }
+
+        let replayed = written_frame(&second, 1);
+        assert_eq!(
+            String::from_utf8(replayed.payload).unwrap(),
+            r#"{"type":"subscribe","channels":[{"name":"heartbeat","product_ids":["ETH-USD"]}]}"#
+        );
+
+        match api.read_websocket().await.unwrap() {
+            WebsocketMessage::Subscribe(_) => {}
+            other => panic!("expected subscription response, got {:?}", other),
+        }
+        match api.read_websocket().await.unwrap() {
+            WebsocketMessage::Heartbeat(_) => {}
+            other => panic!("expected heartbeat, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn api_websocket_reconnect_after_close() {
+        let mut frames = server_frame(0x1, &websocket_sub_response());
+        frames.append(&mut server_frame(0x8, &1001u16.to_be_bytes()));
+        let first = websocket_session(&frames);
+        let second = websocket_session(&server_frame(0x1, &websocket_sub_response()));
+
+        let api = reconnecting_api(vec![first, second]);
+        api.subscribe_to_websocket(SubscriptionBuilder::new().subscribe_to_status().build())
+            .await
+            .unwrap();
+
+        match api.read_websocket().await.unwrap() {
+            WebsocketMessage::Reconnected => {}
+            other => panic!("expected reconnect, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn api_websocket_reconnect_gives_up() {
+        let first = websocket_session(&server_frame(0x1, &websocket_sub_response()));
+
+        let api = reconnecting_api(vec![first]);
+        api.subscribe_to_websocket(SubscriptionBuilder::new().subscribe_to_status().build())
+            .await
+            .unwrap();
+
+        match api.read_websocket().await {
+            Err(WebsocketError::WebsocketConnectionError { .. }) => {}
+            other => panic!("expected connection error, got {:?}", other),
+        }
+    }
+
+    fn subscriptions_message(channels: &str) -> Vec<u8> {
+        format!(r#"{{"type":"subscriptions","channels":[{}]}}"#, channels)
+            .as_bytes()
+            .to_vec()
+    }
+
+    #[tokio::test]
+    async fn api_websocket_unsubscribe() {
+        let mut frames = server_frame(
+            0x1,
+            &subscriptions_message(
+                r#"{"name":"heartbeat","product_ids":["ETH-USD","BTC-USD"]},{"name":"status"}"#,
+            ),
+        );
+        frames.append(&mut server_frame(
+            0x1,
+            &subscriptions_message(r#"{"name":"heartbeat","product_ids":["BTC-USD"]}"#),
+        ));
+        let first = websocket_session(&frames);
+        let second = websocket_session(&[]);
+
+        let api = reconnecting_api(vec![first.clone(), second.clone()]);
+        api.subscribe_to_websocket(
+            SubscriptionBuilder::new()
+                .subscribe_to_heartbeat("ETH-USD".to_string())
+                .subscribe_to_heartbeat("BTC-USD".to_string())
+                .subscribe_to_status()
+                .build(),
+        )
+        .await
+        .unwrap();
+
+        let active = api.active_subscriptions().await;
+        assert_eq!(active.len(), 2);
+        assert_eq!(active[0].product_ids(), ["ETH-USD", "BTC-USD"]);
+
+        api.unsubscribe_from_websocket(
+            UnsubscribeBuilder::new()
+                .unsubscribe_from_heartbeat("ETH-USD".to_string())
+                .unsubscribe_from_status()
+                .build(),
+        )
+        .await
+        .unwrap();
+
+        let unsubscribe = written_frame(&first, 2);
+        assert_eq!(
+            String::from_utf8(unsubscribe.payload).unwrap(),
+            r#"{"type":"unsubscribe","channels":[{"name":"heartbeat","product_ids":["ETH-USD"]},{"name":"status"}]}"#
+        );
+
+        let active = api.active_subscriptions().await;
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].name(), "heartbeat");
+        assert_eq!(active[0].product_ids(), ["BTC-USD"]);
+
+        api.reconnect_websocket().await.unwrap();
+        let replayed = written_frame(&second, 1);
+        assert_eq!(
+            String::from_utf8(replayed.payload).unwrap(),
+            r#"{"type":"subscribe","channels":[{"name":"heartbeat","product_ids":["BTC-USD"]}]}"#
+        );
+        assert!(api.active_subscriptions().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn api_websocket_subscription_unconfirmed() {
+        let stream = websocket_session(&server_frame(
+            0x1,
+            &subscriptions_message(r#"{"name":"ticker","product_ids":["BTC-USD"]}"#),
+        ));
+
+        let api = reconnecting_api(vec![stream]);
+        let response = api
+            .subscribe_to_websocket(
+                SubscriptionBuilder::new()
+                    .subscribe_to_ticker("BTC-USD".to_string())
+                    .subscribe_to_ticker("ETH-USD".to_string())
+                    .subscribe_to_status()
+                    .build(),
+            )
+            .await;
+
+        match response {
+            Err(WebsocketError::UnconfirmedSubscription { missing }) => {
+                assert_eq!(missing, vec!["ticker:ETH-USD", "status"])
+            }
+            other => panic!("expected unconfirmed subscription, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn api_websocket_stream() {
+        let mut frames = server_frame(0x1, &websocket_sub_response());
+        frames.append(&mut server_frame(0x1, &websocket_heartbeat_message()));
+        frames.append(&mut server_frame(0x1, br#"{"type":"not_a_message"}"#));
+        let session = websocket_session(&frames);
+
+        let api = reconnecting_api(vec![session]);
+        api.subscribe_to_websocket(
+            SubscriptionBuilder::new()
+                .subscribe_to_heartbeat("ETH-USD".to_string())
+                .build(),
+        )
+        .await
+        .unwrap();
+
+        let messages: Vec<_> = api.websocket_stream().collect().await;
+        assert_eq!(messages.len(), 3);
+        match &messages[0] {
+            Ok(WebsocketMessage::Heartbeat(_)) => {}
+            other => panic!("expected heartbeat, got {:?}", other),
+        }
+        match &messages[1] {
+            Err(WebsocketError::ParseError(_)) => {}
+            other => panic!("expected parse error, got {:?}", other),
+        }
+        match &messages[2] {
+            Err(WebsocketError::WebsocketConnectionError { .. }) => {}
+            other => panic!("expected connection error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn api_websocket_subscribe_while_streaming() {
+        let mut session = websocket_session(&server_frame(0x1, &websocket_sub_response()));
+
+        let api = reconnecting_api(vec![session.clone()]);
+        api.subscribe_to_websocket(SubscriptionBuilder::new().subscribe_to_status().build())
+            .await
+            .unwrap();
+        // Nothing left to read, the stream waits for the next message.
+        session.stream_contents.lock().unwrap().clear();
+
+        let mut stream = Box::pin(api.websocket_stream());
+        let mut next = stream.next();
+        assert!(futures::poll!(&mut next).is_pending());
+
+        api.send_subscribe(
+            SubscriptionBuilder::new()
+                .subscribe_to_heartbeat("ETH-USD".to_string())
+                .build(),
+        )
+        .await
+        .unwrap();
+        let subscribe = written_frame(&session, 2);
+        assert_eq!(
+            String::from_utf8(subscribe.payload).unwrap(),
+            r#"{"type":"subscribe","channels":[{"name":"heartbeat","product_ids":["ETH-USD"]}]}"#
+        );
+
+        session
+            .append_response(&server_frame(0x1, &websocket_heartbeat_message()))
+            .await;
+        match next.await {
+            Some(Ok(WebsocketMessage::Heartbeat(_))) => {}
+            other => panic!("expected heartbeat, got {:?}", other),
+        }
+    }
+
+    fn ticker_message(product_id: &str, sequence: u64) -> Vec<u8> {
+        format!(
+            r#"{{"type":"ticker","trade_id":20153558,"sequence":{},"time":"2017-09-02T17:05:49.250000Z","product_id":"{}","price":"4388.01","side":"buy","last_size":"0.03","best_bid":"4388","best_ask":"4388.01"}}"#,
+            sequence, product_id
+        )
+        .as_bytes()
+        .to_vec()
+    }
+
+    fn heartbeat_message(product_id: &str, sequence: u64) -> Vec<u8> {
+        format!(
+            r#"{{"type":"heartbeat","last_trade_id":278953096,"product_id":"{}","sequence":{},"time":"2022-05-20T21:22:34.751219Z"}}"#,
+            product_id, sequence
+        )
+        .as_bytes()
+        .to_vec()
+    }
+
+    /// Subscribed api whose websocket delivers `messages` and then closes for good.
+    async fn dispatcher_api(messages: Vec<Vec<u8>>) -> CBProAPI {
+        let mut frames = server_frame(0x1, &websocket_sub_response());
+        for message in messages {
+            frames.append(&mut server_frame(0x1, &message));
+        }
+
+        let api = reconnecting_api(vec![websocket_session(&frames)]);
+        api.subscribe_to_websocket(SubscriptionBuilder::new().subscribe_to_status().build())
+            .await
+            .unwrap();
+        api
+    }
+
+    fn heartbeat_sequences(messages: &[HeartbeatMessage]) -> Vec<u64> {
+        messages.iter().map(|message| message.sequence).collect()
+    }
+
+    #[tokio::test]
+    async fn dispatcher_fans_out_by_type_and_product() {
+        let api = dispatcher_api(vec![
+            ticker_message("ETH-USD", 1),
+            ticker_message("BTC-USD", 2),
+            heartbeat_message("ETH-USD", 3),
+        ])
+        .await;
+
+        let dispatcher = MessageDispatcher::new(&api, DispatcherConfig::default());
+        let tickers = dispatcher.receiver::<TickerMessage>();
+        let eth_tickers = dispatcher.product_receiver::<TickerMessage>("ETH-USD".to_string());
+        let heartbeats = dispatcher.receiver::<HeartbeatMessage>();
+        let eth_messages = dispatcher.product_receiver::<WebsocketMessage>("ETH-USD".to_string());
+
+        let tickers: Vec<_> = tickers.into_stream().collect().await;
+        assert_eq!(
+            tickers
+                .iter()
+                .map(|ticker| ticker.product_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["ETH-USD", "BTC-USD"]
+        );
+
+        let eth_tickers: Vec<_> = eth_tickers.into_stream().collect().await;
+        assert_eq!(eth_tickers.len(), 1);
+        assert_eq!(eth_tickers[0].sequence, 1);
+
+        let heartbeats: Vec<_> = heartbeats.into_stream().collect().await;
+        assert_eq!(heartbeat_sequences(&heartbeats), vec![3]);
+
+        let eth_messages: Vec<_> = eth_messages.into_stream().collect().await;
+        assert_eq!(eth_messages.len(), 2);
+        assert!(matches!(eth_messages[0], WebsocketMessage::Ticker(_)));
+        assert!(matches!(eth_messages[1], WebsocketMessage::Heartbeat(_)));
+
+        assert!(dispatcher.is_stopped());
+    }
+
+    #[tokio::test]
+    async fn dispatcher_slow_consumer_policies() {
+        let messages = || {
+            (1..=3)
+                .map(|sequence| heartbeat_message("ETH-USD", sequence))
+                .collect::<Vec<_>>()
+        };
+
+        for (policy, expected) in [
+            (SlowConsumerPolicy::DropOldest, vec![3]),
+            (SlowConsumerPolicy::Disconnect, vec![1]),
+            (SlowConsumerPolicy::Block, vec![1, 2, 3]),
+        ] {
+            let api = dispatcher_api(messages()).await;
+            let dispatcher = MessageDispatcher::new(
+                &api,
+                DispatcherConfig {
+                    capacity: 1,
+                    policy,
+                },
+            );
+            let mut heartbeats = dispatcher.receiver::<HeartbeatMessage>();
+
+            // Let the dispatcher run into the full queue before receiving anything.
+            while !dispatcher.is_stopped() && policy != SlowConsumerPolicy::Block {
+                tokio::task::yield_now().await;
+            }
+
+            let mut received = Vec::new();
+            while let Some(heartbeat) = heartbeats.recv().await {
+                received.push(heartbeat);
+            }
+            assert_eq!(heartbeat_sequences(&received), expected, "{:?}", policy);
+
+            let dropped = if policy == SlowConsumerPolicy::DropOldest { 2 } else { 0 };
+            assert_eq!(heartbeats.dropped(), dropped);
+        }
+    }
+
+    #[tokio::test]
+    async fn dispatcher_stop_closes_receivers() {
+        let api = dispatcher_api(vec![]).await;
+        let dispatcher = MessageDispatcher::new(&api, DispatcherConfig::default());
+        let mut statuses = dispatcher.receiver::<StatusMessage>();
+
+        dispatcher.stop();
+        assert!(statuses.recv().await.is_none());
+        assert!(dispatcher
+            .receiver::<StatusMessage>()
+            .recv()
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn dispatcher_drop_closes_receivers() {
+        let session = websocket_session(&server_frame(0x1, &websocket_sub_response())).hold_open();
+        let api = reconnecting_api(vec![session]);
+        api.subscribe_to_websocket(SubscriptionBuilder::new().subscribe_to_status().build())
+            .await
+            .unwrap();
+
+        let dispatcher = MessageDispatcher::new(&api, DispatcherConfig::default());
+        let clone = dispatcher.clone();
+        let mut statuses = dispatcher.receiver::<StatusMessage>();
+
+        // The read loop keeps running while any clone is alive.
+        drop(dispatcher);
+        assert!(!clone.is_stopped());
+
+        drop(clone);
+        let closed = tokio::time::timeout(std::time::Duration::from_secs(1), statuses.recv()).await;
+        assert!(closed.unwrap().is_none());
+    }
+
+    #[test]
+    fn sequence_tracker_full_feed() {
+        let mut tracker = SequenceTracker::new();
+        assert_eq!(
+            tracker.check(SequenceFeed::Full, "ETH-USD", 10),
+            SequenceEvent::InOrder
+        );
+        assert_eq!(
+            tracker.check(SequenceFeed::Full, "ETH-USD", 11),
+            SequenceEvent::InOrder
+        );
+        assert_eq!(
+            tracker.check(SequenceFeed::Full, "BTC-USD", 3),
+            SequenceEvent::InOrder
+        );
+
+        match tracker.check(SequenceFeed::Full, "ETH-USD", 15) {
+            SequenceEvent::Gap(gap) => {
+                assert_eq!(gap.product_id, "ETH-USD");
+                assert_eq!(gap.missing(), 12..=14);
+                assert_eq!(gap.missing_count(), 3);
+            }
+            other => panic!("expected gap, got {:?}", other),
+        }
+
+        assert_eq!(
+            tracker.check(SequenceFeed::Full, "ETH-USD", 13),
+            SequenceEvent::OutOfOrder {
+                product_id: "ETH-USD".to_string(),
+                sequence: 13,
+                latest: 15,
+            }
+        );
+        assert_eq!(tracker.outstanding_gaps("ETH-USD"), vec![12..=12, 14..=14]);
+
+        assert_eq!(
+            tracker.check(SequenceFeed::Full, "ETH-USD", 13),
+            SequenceEvent::Duplicate {
+                product_id: "ETH-USD".to_string(),
+                sequence: 13,
+            }
+        );
+        assert_eq!(
+            tracker.check(SequenceFeed::Full, "ETH-USD", 15),
+            SequenceEvent::Duplicate {
+                product_id: "ETH-USD".to_string(),
+                sequence: 15,
+            }
+        );
+
+        tracker.resync(SequenceFeed::Full, "ETH-USD", 100);
+        assert!(tracker.outstanding_gaps("ETH-USD").is_empty());
+        assert_eq!(
+            tracker.check(SequenceFeed::Full, "ETH-USD", 101),
+            SequenceEvent::InOrder
+        );
+        assert_eq!(tracker.latest(SequenceFeed::Full, "BTC-USD"), Some(3));
+    }
+
+    #[test]
+    fn sequence_tracker_ticker_messages() {
+        let parse =
+            |message: Vec<u8>| serde_json::from_slice::<WebsocketMessage>(&message).unwrap();
+        let mut tracker = SequenceTracker::new();
+
+        assert_eq!(
+            tracker.check_message(&parse(ticker_message("ETH-USD", 10))),
+            Some(SequenceEvent::InOrder)
+        );
+        // Tickers skip the sequence numbers of the full channel messages between trades.
+        assert_eq!(
+            tracker.check_message(&parse(ticker_message("ETH-USD", 20))),
+            Some(SequenceEvent::InOrder)
+        );
+        assert_eq!(
+            tracker.check_message(&parse(ticker_message("ETH-USD", 15))),
+            Some(SequenceEvent::OutOfOrder {
+                product_id: "ETH-USD".to_string(),
+                sequence: 15,
+                latest: 20,
+            })
+        );
+        assert_eq!(
+            tracker.check_message(&parse(ticker_message("ETH-USD", 20))),
+            Some(SequenceEvent::Duplicate {
+                product_id: "ETH-USD".to_string(),
+                sequence: 20,
+            })
+        );
+        assert_eq!(
+            tracker.check_message(&parse(heartbeat_message("ETH-USD", 21))),
+            None
+        );
+        assert_eq!(tracker.latest(SequenceFeed::Full, "ETH-USD"), None);
     }
 
-    pub(crate) fn frame_fin_16_0mask_256_long() -> Vec<u8> {
-        let mut return_val: Vec<u8> = vec![
-            0b10001111, 0b11111111, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000,
-            0b00000000, 0b00000001, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000,
-        ];
-        return_val.append(&mut [0u8; 256].to_vec());
-        return_val
-    }
+    #[tokio::test]
+    async fn watchdog_reconnects_stale_feed() {
+        let first = websocket_session(&server_frame(0x1, &websocket_sub_response()));
+        let mut frames = server_frame(0x1, &websocket_sub_response());
+        frames.append(&mut server_frame(0x1, &heartbeat_message("ETH-USD", 7)));
+        let second = websocket_session(&frames);
+
+        let api = reconnecting_api(vec![first.clone(), second.clone()]);
+        api.subscribe_to_websocket(SubscriptionBuilder::new().subscribe_to_status().build())
+            .await
+            .unwrap();
+        // The first connection freezes without closing.
+        first.stream_contents.lock().unwrap().clear();
+
+        let dispatcher = MessageDispatcher::new(&api, DispatcherConfig::default());
+        let mut watchdog = HeartbeatWatchdog::start(
+            &api,
+            &dispatcher,
+            vec!["ETH-USD".to_string()],
+            WatchdogConfig {
+                timeout: std::time::Duration::from_millis(50),
+                reconnect: true,
+            },
+        )
+        .await
+        .unwrap();
 
-    pub(crate) fn frame_fin_16_0mask_0_long() -> Vec<u8> {
-        let return_val: Vec<u8> = vec![
-            0b10001111, 0b11111111, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000,
-            0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000,
-        ];
-        return_val
-    }
+        match watchdog.recv().await {
+            Some(FeedEvent::Stale {
+                product_id,
+                last_heartbeat: None,
+                ..
+            }) => assert_eq!(product_id, "ETH-USD"),
+            other => panic!("expected stale feed, got {:?}", other),
+        }
+        assert_eq!(watchdog.recv().await, Some(FeedEvent::Reconnected));
+        assert_eq!(
+            watchdog.recv().await,
+            Some(FeedEvent::Recovered {
+                product_id: "ETH-USD".to_string()
+            })
+        );
 
-    pub(crate) fn frame_fin_16_0mask_0_medium() -> Vec<u8> {
-        let return_val: Vec<u8> = vec![
-            0b10001111, 0b11111110, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000,
-            0b00000000,
-        ];
-        return_val
-    }
+        let heartbeat = watchdog.last_heartbeat("ETH-USD").unwrap();
+        assert_eq!(heartbeat.sequence, 7);
+        assert_eq!(heartbeat.last_trade_id, 278953096);
+        assert!(watchdog.stale_products().is_empty());
 
-    pub(crate) fn frame_fin_16_1_short() -> Vec<u8> {
-        let return_val: Vec<u8> = vec![0b10001111, 0b00000001, 0x00];
-        return_val
+        let replayed = written_frame(&second, 2);
+        assert_eq!(
+            String::from_utf8(replayed.payload).unwrap(),
+            r#"{"type":"subscribe","channels":[{"name":"heartbeat","product_ids":["ETH-USD"]}]}"#
+        );
     }
 
-    pub(crate) fn frame_fin_16_1_long() -> Vec<u8> {
-        let return_val: Vec<u8> = vec![
-            0b10001111, 0b01111111, 0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000,
-            0b00000000, 0b00000000, 0b00000001, 0x00,
-        ];
-        return_val
+    fn level2_snapshot(bids: &str, asks: &str) -> Vec<u8> {
+        format!(
+            r#"{{"type":"snapshot","product_id":"ETH-USD","bids":{},"asks":{}}}"#,
+            bids, asks
+        )
+        .as_bytes()
+        .to_vec()
     }
 
-    pub(crate) fn frame_fin_16_1_medium() -> Vec<u8> {
-        let return_val: Vec<u8> = vec![0b10001111, 0b01111110, 0b00000000, 0b00000001, 0x00];
-        return_val
+    fn level2_update(changes: &str) -> Vec<u8> {
+        format!(
+            r#"{{"type":"l2update","product_id":"ETH-USD","time":"2019-08-14T20:42:27.265Z","changes":{}}}"#,
+            changes
+        )
+        .as_bytes()
+        .to_vec()
     }
 
-    #[allow(unused)]
-    pub(crate) fn frame_0() -> Vec<u8> {
-        vec![0x00, 0x00]
+    fn book_side_prices(book_side: &BookSide) -> Vec<f64> {
+        book_side.levels().map(|entry| entry.price()).collect()
     }
 
-    pub(crate) fn frame_fin_0() -> Vec<u8> {
-        vec![0b10000000, 0x00]
+    async fn book_prices(lock: &Arc<Mutex<BookSide>>) -> Vec<f64> {
+        lock.lock().await.iter().map(|entry| entry.price()).collect()
     }
 
-    #[tokio::test]
-    async fn websocket_stream_tc1() {
-        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
-            Ok(_) => {}
-            Err(_) => {}
-        };
-        let mut stream = MockStream::new(&[0, 1, 2, 3]);
-
-        assert_eq!(stream.read_u8().await.unwrap(), 0);
-        assert_eq!(stream.read_u8().await.unwrap(), 1);
-        assert_eq!(stream.read_u8().await.unwrap(), 2);
-        assert_eq!(stream.read_u8().await.unwrap(), 3);
+    /// Waits for the task maintaining the book to apply the expected bids.
+    async fn wait_for_bids(order_book: &OrderBook, bids: Vec<f64>) {
+        tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            while book_prices(&order_book.bids).await != bids {
+                tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
     }
 
     #[tokio::test]
-    async fn websocket_stream_tc2() {
-        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
-            Ok(_) => {}
-            Err(_) => {}
-        };
+    async fn live_order_book_resyncs_and_unsubscribes() {
+        let mut frames = server_frame(0x1, &websocket_sub_response());
+        frames.append(&mut server_frame(
+            0x1,
+            &level2_snapshot(
+                r#"[["100.00","1.0"],["99.00","2.0"]]"#,
+                r#"[["101.00","1.0"],["102.00","3.0"]]"#,
+            ),
+        ));
+        frames.append(&mut server_frame(
+            0x1,
+            &level2_update(r#"[["buy","100.00","0"],["sell","101.50","2.0"]]"#),
+        ));
+        let first = websocket_session(&frames).hold_open();
+
+        let mut frames = server_frame(0x1, &websocket_sub_response());
+        // Sent before the snapshot of the replayed subscription, ignored until it arrives.
+        frames.append(&mut server_frame(
+            0x1,
+            &level2_update(r#"[["buy","98.00","5.0"]]"#),
+        ));
+        frames.append(&mut server_frame(
+            0x1,
+            &level2_snapshot(r#"[["200.00","1.0"]]"#, r#"[["201.00","1.0"]]"#),
+        ));
+        frames.append(&mut server_frame(
+            0x1,
+            &level2_update(r#"[["buy","199.00","4.0"]]"#),
+        ));
+        let second = websocket_session(&frames).hold_open();
+
+        let api = reconnecting_api(vec![first, second.clone()]);
+        api.subscribe_to_websocket(SubscriptionBuilder::new().subscribe_to_status().build())
+            .await
+            .unwrap();
 
-        let mut handshake_response = default_websocket_upgrade_resp();
+        let dispatcher = MessageDispatcher::new(&api, DispatcherConfig::default());
+        let live = api
+            .new_order_book_l2(&dispatcher, "ETH-USD".to_string())
+            .await
+            .unwrap();
 
-        let frame: Vec<u8> = frame_fin_16_0mask_256();
+        wait_for_bids(&live, vec![99.0]).await;
+        assert_eq!(book_prices(&live.asks).await, vec![102.0, 101.5, 101.0]);
 
-        handshake_response.append(&mut frame.clone());
-        let mock_stream = MockStream::new(&handshake_response);
+        api.reconnect_websocket().await.unwrap();
+        wait_for_bids(&live, vec![199.0, 200.0]).await;
+        assert_eq!(book_prices(&live.asks).await, vec![201.0]);
+        assert_eq!(live.mid_price().await, 200.5);
+        assert!(live.is_live());
 
-        //let stream = default_tls_stream("https://ws-feed.exchange.coinbase.com/").await;
+        let writes = second.writes.lock().unwrap().len();
+        drop(live);
+        tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            while second.writes.lock().unwrap().len() == writes {
+                tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
 
-        let mut websocket = WebsocketStreamConnector::new_no_sec()
-            .connect(
-                mock_stream,
-                &Url::parse("https://ws-feed.exchange.coinbase.com/").unwrap(),
-            )
+        let unsubscribe = written_frame(&second, writes);
+        assert_eq!(
+            String::from_utf8(unsubscribe.payload).unwrap(),
+            r#"{"type":"unsubscribe","channels":[{"name":"level2","product_ids":["ETH-USD"]}]}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn live_order_book_applies_snapshot_after_drops() {
+        let mut frames = server_frame(0x1, &websocket_sub_response());
+        frames.append(&mut server_frame(
+            0x1,
+            &level2_update(r#"[["buy","98.00","5.0"]]"#),
+        ));
+        frames.append(&mut server_frame(
+            0x1,
+            &level2_snapshot(r#"[["200.00","1.0"]]"#, r#"[["201.00","1.0"]]"#),
+        ));
+        let session = websocket_session(&frames).hold_open();
+        let api = reconnecting_api(vec![session.clone()]);
+        api.subscribe_to_websocket(SubscriptionBuilder::new().subscribe_to_status().build())
             .await
             .unwrap();
 
-        let mut output = Vec::new();
-        websocket.read_to_end(&mut output).await.unwrap();
+        // A single slot queue drops the update to make room for the snapshot behind it.
+        let config = DispatcherConfig {
+            capacity: 1,
+            policy: SlowConsumerPolicy::DropOldest,
+        };
+        let dispatcher = MessageDispatcher::new(&api, config);
+        let messages = dispatcher.product_receiver::<WebsocketMessage>("ETH-USD".to_string());
+
+        let order_book = test_order_book();
+        let writes = session.writes.lock().unwrap().len();
+        let (shutdown, stopped) = tokio::sync::oneshot::channel();
+        let manage = api.clone().manage_order_book(
+            "ETH-USD".to_string(),
+            order_book.clone(),
+            messages,
+            stopped,
+        );
+        let check = async {
+            wait_for_bids(&order_book, vec![200.0]).await;
+            drop(shutdown);
+        };
+        tokio::join!(manage, check);
 
-        //let writes: Vec<u8> = stream.writes.lock().unwrap().clone().into_iter().collect();
-        assert_eq!(frame[8..], output);
+        assert_eq!(book_prices(&order_book.asks).await, vec![201.0]);
+        // The snapshot resynced the book, only the unsubscribe on shutdown was written.
+        assert_eq!(session.writes.lock().unwrap().len(), writes + 1);
+        let unsubscribe = written_frame(&session, writes);
+        assert_eq!(
+            String::from_utf8(unsubscribe.payload).unwrap(),
+            r#"{"type":"unsubscribe","channels":[{"name":"level2","product_ids":["ETH-USD"]}]}"#
+        );
     }
 
     #[tokio::test]
-    async fn websocket_stream_tc3() {
-        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
-            Ok(_) => {}
-            Err(_) => {}
-        };
+    async fn live_order_book_without_snapshot() {
+        let api = dispatcher_api(vec![]).await;
+        let dispatcher = MessageDispatcher::new(&api, DispatcherConfig::default());
 
-        let mut handshake_response = default_websocket_upgrade_resp();
+        match api
+            .new_order_book_l2(&dispatcher, "ETH-USD".to_string())
+            .await
+        {
+            Err(WebsocketError::OrderBookSnapshotMissing { product_id }) => {
+                assert_eq!(product_id, "ETH-USD")
+            }
+            other => panic!("expected missing snapshot, got {:?}", other.map(|_| ())),
+        }
+    }
 
-        let frame: Vec<u8> = frame_fin_0();
+    #[tokio::test]
+    async fn live_order_book_subscription_rejected() {
+        let api = dispatcher_api(vec![
+            br#"{"type":"error","message":"Failed to subscribe","reason":"ETH-FOO is not a valid product"}"#
+                .to_vec(),
+        ])
+        .await;
+        let dispatcher = MessageDispatcher::new(&api, DispatcherConfig::default());
+
+        match api
+            .new_order_book_l2(&dispatcher, "ETH-FOO".to_string())
+            .await
+        {
+            Err(WebsocketError::SubscriptionRejected { message, reason }) => {
+                assert_eq!(message, "Failed to subscribe");
+                assert_eq!(reason.as_deref(), Some("ETH-FOO is not a valid product"));
+            }
+            other => panic!(
+                "expected rejected subscription, got {:?}",
+                other.map(|_| ())
+            ),
+        }
+    }
 
-        handshake_response.append(&mut frame.clone());
-        let mock_stream = MockStream::new(&handshake_response);
+    #[tokio::test]
+    async fn live_order_book_ignores_rejection_of_other_product() {
+        let api = dispatcher_api(vec![
+            br#"{"type":"error","message":"Failed to subscribe","reason":"ETH-USDX is not a valid product"}"#
+                .to_vec(),
+            level2_snapshot(r#"[["100.00","1.0"]]"#, r#"[["101.00","1.0"]]"#),
+        ])
+        .await;
+        let dispatcher = MessageDispatcher::new(&api, DispatcherConfig::default());
+
+        let live = api
+            .new_order_book_l2(&dispatcher, "ETH-USD".to_string())
+            .await
+            .unwrap();
 
-        //let stream = default_tls_stream("https://ws-feed.exchange.coinbase.com/").await;
+        assert_eq!(book_prices(&live.bids).await, vec![100.0]);
+        assert!(live.is_live());
+    }
 
-        let mut websocket = WebsocketStreamConnector::new_no_sec()
-            .connect(
-                mock_stream,
-                &Url::parse("https://ws-feed.exchange.coinbase.com/").unwrap(),
-            )
+    #[tokio::test]
+    async fn live_order_books_share_level2_subscription() {
+        let mut frames = server_frame(0x1, &websocket_sub_response());
+        frames.append(&mut server_frame(
+            0x1,
+            &level2_snapshot(r#"[["100.00","1.0"]]"#, r#"[["101.00","1.0"]]"#),
+        ));
+        frames.append(&mut server_frame(
+            0x1,
+            &level2_snapshot(r#"[["100.00","2.0"]]"#, r#"[["101.00","2.0"]]"#),
+        ));
+        let session = websocket_session(&frames).hold_open();
+        let api = reconnecting_api(vec![session.clone()]);
+        api.subscribe_to_websocket(SubscriptionBuilder::new().subscribe_to_status().build())
             .await
             .unwrap();
+        let dispatcher = MessageDispatcher::new(&api, DispatcherConfig::default());
 
-        let mut output = Vec::new();
-        websocket.read_to_end(&mut output).await.unwrap();
+        let (first, second) = tokio::join!(
+            api.new_order_book_l2(&dispatcher, "ETH-USD".to_string()),
+            api.new_order_book_l2(&dispatcher, "ETH-USD".to_string()),
+        );
+        let (first, second) = (first.unwrap(), second.unwrap());
+
+        let writes = session.writes.lock().unwrap().len();
+        drop(first);
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        assert_eq!(session.writes.lock().unwrap().len(), writes);
+        assert!(second.is_live());
+
+        drop(second);
+        tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            while session.writes.lock().unwrap().len() == writes {
+                tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
 
-        //let writes: Vec<u8> = stream.writes.lock().unwrap().clone().into_iter().collect();
-        assert_eq!(output.len(), 0);
+        let unsubscribe = written_frame(&session, writes);
+        assert_eq!(
+            String::from_utf8(unsubscribe.payload).unwrap(),
+            r#"{"type":"unsubscribe","channels":[{"name":"level2","product_ids":["ETH-USD"]}]}"#
+        );
     }
 
     #[tokio::test]
-    async fn websocket_stream_tc4() {
-        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
-            Ok(_) => {}
-            Err(_) => {}
-        };
+    async fn live_order_book_snapshot_timeout() {
+        let session = websocket_session(&server_frame(0x1, &websocket_sub_response())).hold_open();
+        let api = reconnecting_api(vec![session.clone()]);
+        api.subscribe_to_websocket(SubscriptionBuilder::new().subscribe_to_status().build())
+            .await
+            .unwrap();
+        let dispatcher = MessageDispatcher::new(&api, DispatcherConfig::default());
 
-        let mut handshake_response = default_websocket_upgrade_resp();
+        let timeout = std::time::Duration::from_millis(20);
+        match api
+            .new_order_book_l2_within(&dispatcher, "ETH-USD".to_string(), timeout)
+            .await
+        {
+            Err(WebsocketError::OrderBookSnapshotTimeout {
+                product_id,
+                timeout: waited,
+            }) => {
+                assert_eq!(product_id, "ETH-USD");
+                assert_eq!(waited, timeout);
+            }
+            other => panic!("expected snapshot timeout, got {:?}", other.map(|_| ())),
+        }
 
-        let frame: Vec<u8> = frame_fin_16_0mask_256_long();
+        let writes = session.writes.lock().unwrap().len();
+        let unsubscribe = written_frame(&session, writes - 1);
+        assert_eq!(
+            String::from_utf8(unsubscribe.payload).unwrap(),
+            r#"{"type":"unsubscribe","channels":[{"name":"level2","product_ids":["ETH-USD"]}]}"#
+        );
+    }
 
-        handshake_response.append(&mut frame.clone());
-        let mock_stream = MockStream::new(&handshake_response);
+    fn full_message(fields: &str, sequence: u64) -> Vec<u8> {
+        format!(
+            r#"{{{},"product_id":"ETH-USD","time":"2022-05-25T13:29:57.980958Z","sequence":{}}}"#,
+            fields, sequence
+        )
+        .as_bytes()
+        .to_vec()
+    }
 
-        //let stream = default_tls_stream("https://ws-feed.exchange.coinbase.com/").await;
+    fn open_message(sequence: u64, order_id: &str, side: &str, price: &str, size: &str) -> Vec<u8> {
+        full_message(
+            &format!(
+                r#""type":"open","order_id":"{}","side":"{}","price":"{}","remaining_size":"{}""#,
+                order_id, side, price, size
+            ),
+            sequence,
+        )
+    }
 
-        let mut websocket = WebsocketStreamConnector::new_no_sec()
-            .connect(
-                mock_stream,
-                &Url::parse("https://ws-feed.exchange.coinbase.com/").unwrap(),
-            )
-            .await
-            .unwrap();
+    fn done_message(sequence: u64, order_id: &str, side: &str) -> Vec<u8> {
+        full_message(
+            &format!(
+                r#""type":"done","order_id":"{}","side":"{}","reason":"canceled","price":"1","remaining_size":"0""#,
+                order_id, side
+            ),
+            sequence,
+        )
+    }
 
-        let mut output = Vec::new();
-        websocket.read_to_end(&mut output).await.unwrap();
+    fn match_message(sequence: u64, maker_order_id: &str, side: &str, size: &str) -> Vec<u8> {
+        full_message(
+            &format!(
+                r#""type":"match","trade_id":1,"maker_order_id":"{}","taker_order_id":"taker","side":"{}","price":"1","size":"{}""#,
+                maker_order_id, side, size
+            ),
+            sequence,
+        )
+    }
 
-        //let writes: Vec<u8> = stream.writes.lock().unwrap().clone().into_iter().collect();
-        assert_eq!(frame[14..], output);
+    fn change_message(sequence: u64, order_id: &str, side: &str, new_size: &str) -> Vec<u8> {
+        full_message(
+            &format!(
+                r#""type":"change","order_id":"{}","side":"{}","price":"1","old_size":"1","new_size":"{}""#,
+                order_id, side, new_size
+            ),
+            sequence,
+        )
     }
 
-    #[tokio::test]
-    async fn websocket_stream_tc5() {
-        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
-            Ok(_) => {}
-            Err(_) => {}
-        };
+    fn parsed(message: Vec<u8>) -> WebsocketMessage {
+        serde_json::from_slice(&message).unwrap()
+    }
 
-        let mut handshake_response = default_websocket_upgrade_resp();
+    fn book_order(price: f64, size: f64, order_id: &str) -> Level3BookOrder {
+        Level3BookOrder {
+            price,
+            size,
+            order_id: order_id.to_string(),
+        }
+    }
 
-        let frame: Vec<u8> = frame_fin_16_0mask_0_long();
+    fn level_order_ids(order_book: &L3OrderBook, side: Side, price: f64) -> Vec<String> {
+        order_book
+            .orders_at(side, price)
+            .map(|orders| orders.iter().map(|order| order.order_id.clone()).collect())
+            .unwrap_or_default()
+    }
 
-        handshake_response.append(&mut frame.clone());
-        let mock_stream = MockStream::new(&handshake_response);
+    #[tokio::test]
+    async fn l3_order_book_buffers_until_seeded() {
+        let mut order_book = L3OrderBook::new("ETH-USD".to_string());
 
-        //let stream = default_tls_stream("https://ws-feed.exchange.coinbase.com/").await;
+        // Part of the seed, dropped when it arrives.
+        order_book
+            .apply_message(parsed(open_message(10, "stale", "buy", "99", "1")))
+            .unwrap();
+        order_book
+            .apply_message(parsed(open_message(12, "b2", "buy", "100", "2")))
+            .unwrap();
+        order_book
+            .apply_message(parsed(full_message(
+                r#""type":"received","order_id":"r1","order_type":"limit","side":"sell","price":"101","size":"1""#,
+                13,
+            )))
+            .unwrap();
+        assert!(!order_book.is_seeded());
+        assert!(order_book.is_empty());
 
-        let mut websocket = WebsocketStreamConnector::new_no_sec()
-            .connect(
-                mock_stream,
-                &Url::parse("https://ws-feed.exchange.coinbase.com/").unwrap(),
-            )
-            .await
+        order_book
+            .seed(Level3ProductBook {
+                bids: vec![book_order(100.0, 1.0, "b1"), book_order(99.0, 3.0, "b0")],
+                asks: vec![book_order(101.0, 1.0, "a1")],
+                sequence: 11,
+            })
             .unwrap();
+        assert_eq!(order_book.sequence(), Some(13));
+        assert!(order_book.order("stale").is_none());
+        assert!(order_book.order("r1").is_none());
+        assert_eq!(level_order_ids(&order_book, Side::BUY, 100.0), vec!["b1", "b2"]);
 
-        let mut output = Vec::new();
-        websocket.read_to_end(&mut output).await.unwrap();
+        // Applied in sequence order once the message before it arrives.
+        order_book
+            .apply_message(parsed(match_message(15, "b1", "buy", "0.25")))
+            .unwrap();
+        assert_eq!(order_book.sequence(), Some(13));
+        assert_eq!(order_book.order("b1").unwrap().size, 1.0);
+        order_book
+            .apply_message(parsed(change_message(14, "a1", "sell", "0.5")))
+            .unwrap();
+        assert_eq!(order_book.sequence(), Some(15));
+        assert_eq!(order_book.order("b1").unwrap().size, 0.75);
+        assert_eq!(order_book.order("a1").unwrap().size, 0.5);
 
-        //let writes: Vec<u8> = stream.writes.lock().unwrap().clone().into_iter().collect();
-        assert_eq!(0, output.len());
+        order_book
+            .apply_message(parsed(done_message(16, "b1", "buy")))
+            .unwrap();
+        assert_eq!(level_order_ids(&order_book, Side::BUY, 100.0), vec!["b2"]);
+        assert_eq!(order_book.len(), 3);
+
+        let bid_prices = order_book
+            .levels(Side::BUY)
+            .map(|(price, _)| price)
+            .collect::<Vec<_>>();
+        assert_eq!(bid_prices, vec![100.0, 99.0]);
+
+        let bids = order_book
+            .book_side(Side::BUY)
+            .levels()
+            .map(|entry| (entry.price(), entry.size()))
+            .collect::<Vec<_>>();
+        assert_eq!(bids, vec![(100.0, 2.0), (99.0, 3.0)]);
+        assert_eq!(order_book.to_order_book().mid_price().await, 100.5);
     }
 
-    #[tokio::test]
-    async fn websocket_stream_tc6() {
-        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
-            Ok(_) => {}
-            Err(_) => {}
-        };
+    #[test]
+    fn l3_order_book_reports_gaps() {
+        let mut order_book = L3OrderBook::new("ETH-USD".to_string());
+        order_book.seed(Level3ProductBook::default()).unwrap();
+
+        // Sequence 1 never arrives.
+        for sequence in 2..65 {
+            let order_id = sequence.to_string();
+            order_book
+                .apply_message(parsed(open_message(sequence, &order_id, "sell", "5", "1")))
+                .unwrap();
+        }
+        let gap = order_book
+            .apply_message(parsed(open_message(65, "65", "sell", "5", "1")))
+            .unwrap_err();
+        assert_eq!(gap.missing(), 1..=1);
+        assert!(!order_book.is_seeded());
+
+        let gap = order_book
+            .seed(Level3ProductBook {
+                sequence: 0,
+                ..Default::default()
+            })
+            .unwrap_err();
+        assert_eq!(gap.missing(), 1..=1);
 
-        let mut handshake_response = default_websocket_upgrade_resp();
+        order_book
+            .seed(Level3ProductBook {
+                sequence: 1,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(order_book.sequence(), Some(65));
+        assert_eq!(order_book.len(), 64);
+        assert_eq!(level_order_ids(&order_book, Side::SELL, 5.0)[0], "2");
+    }
 
-        let frame: Vec<u8> = frame_fin_16_0mask_0_medium();
+    fn product_book_l3_request() -> MockRequestBuilder {
+        let mut respone1 = MockResponse::new();
+        let respone2 = MockResponse::new();
 
-        handshake_response.append(&mut frame.clone());
-        let mock_stream = MockStream::new(&handshake_response);
+        respone1.expect_status().return_const(reqwest::StatusCode::OK);
+        respone1.expect_text().return_once(|| {
+            Ok(r#"{"bids":[["100.00","1.0","b1"]],"asks":[["101.00","1.0","a1"]],"sequence":100}"#.to_string())
+        });
 
-        //let stream = default_tls_stream("https://ws-feed.exchange.coinbase.com/").await;
+        MockRequestBuilder::new_mock(vec![respone1, respone2])
+    }
 
-        let mut websocket = WebsocketStreamConnector::new_no_sec()
-            .connect(
-                mock_stream,
-                &Url::parse("https://ws-feed.exchange.coinbase.com/").unwrap(),
-            )
+    async fn l3_api(request: MockRequestBuilder, session: MockStream) -> CBProAPI {
+        let api = CBProAPIBuilder::new()
+            .set_client(MockClient::new_mock(request))
+            .set_io_builder(MockWebsocketIOBuilder::new_sequence(vec![session]))
+            .set_retry_policy(RetryPolicy::none())
+            .build();
+        api.subscribe_to_websocket(SubscriptionBuilder::new().subscribe_to_status().build())
             .await
             .unwrap();
-
-        let mut output = Vec::new();
-        websocket.read_to_end(&mut output).await.unwrap();
-
-        //let writes: Vec<u8> = stream.writes.lock().unwrap().clone().into_iter().collect();
-        assert_eq!(frame[8..], output);
+        api
     }
 
     #[tokio::test]
-    async fn websocket_stream_tc7() {
-        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
-            Ok(_) => {}
-            Err(_) => {}
-        };
+    async fn live_l3_order_book_seeds_and_unsubscribes() {
+        let mut frames = server_frame(0x1, &websocket_sub_response());
+        frames.append(&mut server_frame(0x1, &done_message(100, "b1", "buy")));
+        frames.append(&mut server_frame(0x1, &open_message(101, "b2", "buy", "100", "2")));
+        frames.append(&mut server_frame(0x1, &match_message(102, "a1", "sell", "0.25")));
+        let session = websocket_session(&frames).hold_open();
+
+        let api = l3_api(product_book_l3_request(), session.clone()).await;
+        let dispatcher = MessageDispatcher::new(&api, DispatcherConfig::default());
+        let live = api
+            .new_order_book_l3(&dispatcher, "ETH-USD".to_string())
+            .await
+            .unwrap();
 
-        let mut handshake_response = default_websocket_upgrade_resp();
+        tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            while live.lock().await.sequence() != Some(102) {
+                tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
 
-        let frame: Vec<u8> = frame_fin_16_1_short();
+        {
+            let order_book = live.lock().await;
+            assert_eq!(level_order_ids(&order_book, Side::BUY, 100.0), vec!["b1", "b2"]);
+            assert_eq!(order_book.order("a1").unwrap().size, 0.75);
+        }
+        assert!(live.is_live());
 
-        handshake_response.append(&mut frame.clone());
-        let mock_stream = MockStream::new(&handshake_response);
+        let writes = session.writes.lock().unwrap().len();
+        drop(live);
+        tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            while session.writes.lock().unwrap().len() == writes {
+                tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
 
-        //let stream = default_tls_stream("https://ws-feed.exchange.coinbase.com/").await;
+        let unsubscribe = written_frame(&session, writes);
+        assert_eq!(
+            String::from_utf8(unsubscribe.payload).unwrap(),
+            r#"{"type":"unsubscribe","channels":[{"name":"full","product_ids":["ETH-USD"]}]}"#
+        );
+    }
 
-        let mut websocket = WebsocketStreamConnector::new_no_sec()
-            .connect(
-                mock_stream,
-                &Url::parse("https://ws-feed.exchange.coinbase.com/").unwrap(),
-            )
+    #[tokio::test]
+    async fn live_l3_order_book_seed_error() {
+        let session = websocket_session(&server_frame(0x1, &websocket_sub_response())).hold_open();
+        let api = l3_api(server_unavailable_request(), session).await;
+        let dispatcher = MessageDispatcher::new(&api, DispatcherConfig::default());
+
+        match api
+            .new_order_book_l3(&dispatcher, "ETH-USD".to_string())
+            .await
+        {
+            Err(WebsocketError::OrderBookSeedError { product_id, source }) => {
+                assert_eq!(product_id, "ETH-USD");
+                assert!(matches!(source, Error::ServerUnavailable(_)));
+            }
+            other => panic!("expected seed error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn live_l3_order_book_seed_timeout() {
+        // Messages far past the book's sequence 100 leave a gap after every fetch.
+        let mut frames = server_frame(0x1, &websocket_sub_response());
+        frames.append(&mut server_frame(0x1, &open_message(200, "b2", "buy", "100", "2")));
+        let session = websocket_session(&frames).hold_open();
+        // The first fetch is retried, the messages are buffered before the book arrives.
+        let request = MockClient::new_mock_sequence(vec![
+            server_unavailable_request(),
+            product_book_l3_request(),
+        ]);
+        let api = CBProAPIBuilder::new()
+            .set_client(request)
+            .set_io_builder(MockWebsocketIOBuilder::new_sequence(vec![session.clone()]))
+            .set_retry_policy(RetryPolicy {
+                max_attempts: 2,
+                base_delay: std::time::Duration::from_millis(10),
+                jitter: 0.0,
+                ..Default::default()
+            })
+            .build();
+        api.subscribe_to_websocket(SubscriptionBuilder::new().subscribe_to_status().build())
             .await
             .unwrap();
+        let dispatcher = MessageDispatcher::new(&api, DispatcherConfig::default());
 
-        let mut output = Vec::new();
-        websocket.read_to_end(&mut output).await.unwrap();
+        let timeout = std::time::Duration::from_millis(100);
+        match api
+            .new_order_book_l3_within(&dispatcher, "ETH-USD".to_string(), timeout)
+            .await
+        {
+            Err(WebsocketError::OrderBookSnapshotTimeout {
+                product_id,
+                timeout: waited,
+            }) => {
+                assert_eq!(product_id, "ETH-USD");
+                assert_eq!(waited, timeout);
+            }
+            other => panic!("expected seed timeout, got {:?}", other.map(|_| ())),
+        }
 
-        //let writes: Vec<u8> = stream.writes.lock().unwrap().clone().into_iter().collect();
-        assert_eq!(1, output.len());
+        tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            loop {
+                let writes = session.writes.lock().unwrap().len();
+                let last = written_frame(&session, writes - 1);
+                if last.payload.starts_with(br#"{"type":"unsubscribe""#) {
+                    assert_eq!(
+                        String::from_utf8(last.payload).unwrap(),
+                        r#"{"type":"unsubscribe","channels":[{"name":"full","product_ids":["ETH-USD"]}]}"#
+                    );
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
     }
 
-    #[tokio::test]
-    async fn websocket_stream_tc8() {
-        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
-            Ok(_) => {}
-            Err(_) => {}
-        };
-
-        let mut handshake_response = default_websocket_upgrade_resp();
+    fn websocket_credentials() -> APIKeyData {
+        APIKeyData {
+            key: "my-key".to_string(),
+            secret: base64::encode("my-secret"),
+            passphrase: "my-passphrase".to_string(),
+        }
+    }
 
-        let frame: Vec<u8> = frame_fin_16_1_medium();
+    #[tokio::test]
+    async fn websocket_signed_user_subscription() {
+        let subscription = SubscriptionBuilder::new()
+            .subscribe_to_user("ETH-USD".to_string())
+            .sign(websocket_credentials())
+            .build();
 
-        handshake_response.append(&mut frame.clone());
-        let mock_stream = MockStream::new(&handshake_response);
+        let json: serde_json::Value =
+            serde_json::to_value(WebsocketMessage::Subscribe(subscription.clone())).unwrap();
+        assert_eq!(json["type"], "subscribe");
+        assert_eq!(json["channels"][0]["name"], "user");
+        assert_eq!(json["channels"][0]["product_ids"][0], "ETH-USD");
+        assert_eq!(json["key"], "my-key");
+        assert_eq!(json["passphrase"], "my-passphrase");
+
+        let timestamp = json["timestamp"].as_str().unwrap().parse::<i64>().unwrap();
+        let expected = crate::requests::signature(
+            &base64::encode("my-secret"),
+            timestamp,
+            "GET",
+            "/users/self/verify",
+            "",
+        )
+        .unwrap();
+        assert_eq!(json["signature"], expected);
 
-        //let stream = default_tls_stream("https://ws-feed.exchange.coinbase.com/").await;
+        let debug = format!("{:?}", subscription);
+        assert!(!debug.contains("my-passphrase"));
+        assert!(!debug.contains(&base64::encode("my-secret")));
+    }
 
-        let mut websocket = WebsocketStreamConnector::new_no_sec()
-            .connect(
-                mock_stream,
-                &Url::parse("https://ws-feed.exchange.coinbase.com/").unwrap(),
-            )
-            .await
-            .unwrap();
+    #[tokio::test]
+    async fn api_websocket_signed_subscription_is_resigned_on_replay() {
+        let first = websocket_session(&server_frame(0x1, &websocket_sub_response()));
+        let second = websocket_session(&[]);
 
-        let mut output = Vec::new();
-        websocket.read_to_end(&mut output).await.unwrap();
+        let api = reconnecting_api(vec![first, second.clone()]);
+        api.subscribe_to_websocket(
+            SubscriptionBuilder::new()
+                .subscribe_to_user("ETH-USD".to_string())
+                .sign(websocket_credentials())
+                .build(),
+        )
+        .await
+        .unwrap();
+        api.reconnect_websocket().await.unwrap();
 
-        //let writes: Vec<u8> = stream.writes.lock().unwrap().clone().into_iter().collect();
-        assert_eq!(1, output.len());
+        let replayed: serde_json::Value =
+            serde_json::from_slice(&written_frame(&second, 1).payload).unwrap();
+        assert_eq!(replayed["channels"][0]["name"], "user");
+        assert_eq!(replayed["key"], "my-key");
+        assert!(replayed["signature"].is_string());
     }
 
     #[tokio::test]
-    async fn websocket_stream_tc9() {
-        match SimpleLogger::new().with_level(LevelFilter::Trace).init() {
-            Ok(_) => {}
-            Err(_) => {}
-        };
+    async fn api_websocket_signed_subscription_uses_server_clock() {
+        // The server clock runs an hour ahead of the local clock.
+        let server_epoch = chrono::Utc::now().timestamp() + 3600;
+        let session = websocket_session(&server_frame(0x1, &websocket_sub_response()));
+        let api = CBProAPIBuilder::new()
+            .set_client(MockClient::new_mock_sequence(vec![
+                MockRequestBuilder::new_mock(vec![server_time_response(server_epoch as f64)]),
+            ]))
+            .set_io_builder(MockWebsocketIOBuilder::new_sequence(vec![session.clone()]))
+            .sync_server_time(std::time::Duration::from_secs(60))
+            .build();
 
-        let mut handshake_response = default_websocket_upgrade_resp();
+        api.subscribe_to_websocket(
+            SubscriptionBuilder::new()
+                .subscribe_to_user("ETH-USD".to_string())
+                .sign(websocket_credentials())
+                .build(),
+        )
+        .await
+        .unwrap();
 
-        let frame: Vec<u8> = frame_fin_16_1_long();
+        let subscribe: serde_json::Value =
+            serde_json::from_slice(&written_frame(&session, 1).payload).unwrap();
+        let timestamp = subscribe["timestamp"]
+            .as_str()
+            .unwrap()
+            .parse::<i64>()
+            .unwrap();
+        assert!((timestamp - server_epoch).abs() <= 2);
+        let expected = crate::requests::signature(
+            &base64::encode("my-secret"),
+            timestamp,
+            "GET",
+            "/users/self/verify",
+            "",
+        )
+        .unwrap();
+        assert_eq!(subscribe["signature"], expected);
+    }
 
-        handshake_response.append(&mut frame.clone());
-        let mock_stream = MockStream::new(&handshake_response);
+    #[tokio::test]
+    async fn websocket_user_match_message() {
+        let message = r#"{"type":"match","trade_id":10,"sequence":50,"maker_order_id":"ac928c66-ca53-498f-9c13-a110027a60e8","taker_order_id":"132fb6ae-456b-4654-b4e0-d681ac05cea1","time":"2014-11-07T08:19:27.028459Z","product_id":"BTC-USD","size":"5.23512","price":"400.23","side":"sell","taker_user_id":"5844eceecf7e803e259d0365","user_id":"5844eceecf7e803e259d0365","taker_profile_id":"765d1549-9660-4be2-97d4-fa2d65fa3352","profile_id":"765d1549-9660-4be2-97d4-fa2d65fa3352"}"#;
+
+        match serde_json::from_str::<WebsocketMessage>(message).unwrap() {
+            WebsocketMessage::Match(matched) => {
+                assert_eq!(matched.user_id.as_deref(), Some("5844eceecf7e803e259d0365"));
+                assert_eq!(
+                    matched.taker_profile_id.as_deref(),
+                    Some("765d1549-9660-4be2-97d4-fa2d65fa3352")
+                );
+                assert_eq!(matched.maker_user_id, None);
+            }
+            other => panic!("expected match, got {:?}", other),
+        }
+    }
 
-        //let stream = default_tls_stream("https://ws-feed.exchange.coinbase.com/").await;
+    #[tokio::test]
+    async fn websocket_subscribe_to_batch_and_match_channels() {
+        let subscription = SubscriptionBuilder::new()
+            .subscribe_to_matches("ETH-USD".to_string())
+            .subscribe_to_level2_batch("ETH-USD".to_string())
+            .subscribe_to_ticker_batch("BTC-USD".to_string())
+            .subscribe_to_auctionfeed("LTC-USD".to_string())
+            .subscribe_to_all_rfq_matches()
+            .build();
 
-        let mut websocket = WebsocketStreamConnector::new_no_sec()
-            .connect(
-                mock_stream,
-                &Url::parse("https://ws-feed.exchange.coinbase.com/").unwrap(),
+        assert_eq!(
+            serde_json::to_string(&WebsocketMessage::Subscribe(subscription)).unwrap(),
+            concat!(
+                r#"{"type":"subscribe","channels":["#,
+                r#"{"name":"matches","product_ids":["ETH-USD"]},"#,
+                r#"{"name":"level2_batch","product_ids":["ETH-USD"]},"#,
+                r#"{"name":"ticker_batch","product_ids":["BTC-USD"]},"#,
+                r#"{"name":"auctionfeed","product_ids":["LTC-USD"]},"#,
+                r#"{"name":"rfq_matches"}]}"#
             )
-            .await
-            .unwrap();
+        );
 
-        let mut output = Vec::new();
-        websocket.read_to_end(&mut output).await.unwrap();
+        let confirmed = r#"{"type":"subscriptions","channels":[{"name":"level2_batch","product_ids":["ETH-USD"]},{"name":"rfq_matches"}]}"#;
+        match serde_json::from_str::<WebsocketMessage>(confirmed).unwrap() {
+            WebsocketMessage::Subscriptions(subscriptions) => {
+                assert_eq!(subscriptions.channels[0].name(), "level2_batch");
+                assert!(subscriptions.channels[1].product_ids().is_empty());
+            }
+            other => panic!("expected subscriptions, got {:?}", other),
+        }
+    }
 
-        //let writes: Vec<u8> = stream.writes.lock().unwrap().clone().into_iter().collect();
-        assert_eq!(1, output.len());
+    #[tokio::test]
+    async fn websocket_match_auction_and_rfq_messages() {
+        let last_match = r#"{"type":"last_match","trade_id":10,"sequence":50,"maker_order_id":"ac928c66-ca53-498f-9c13-a110027a60e8","taker_order_id":"132fb6ae-456b-4654-b4e0-d681ac05cea1","time":"2014-11-07T08:19:27.028459Z","product_id":"BTC-USD","size":"5.23512","price":"400.23","side":"sell"}"#;
+        match serde_json::from_str::<WebsocketMessage>(last_match).unwrap() {
+            WebsocketMessage::LastMatch(matched) => assert_eq!(matched.trade_id, 10),
+            other => panic!("expected last match, got {:?}", other),
+        }
+
+        let auction = r#"{"type":"auction","product_id":"LTC-USD","sequence":3262786978,"auction_state":"collection","best_bid_price":"333.98","best_bid_size":"4.39088265","best_ask_price":"333.99","best_ask_size":"25.23542881","open_price":"333.99","open_size":"0.193","can_open":"yes","timestamp":"2015-11-14T20:46:03.511254Z"}"#;
+        match serde_json::from_str::<WebsocketMessage>(auction).unwrap() {
+            WebsocketMessage::Auction(auction) => {
+                assert_eq!(auction.auction_state, "collection");
+                assert_eq!(auction.open_size, 0.193);
+            }
+            other => panic!("expected auction, got {:?}", other),
+        }
+
+        let rfq_match = r#"{"type":"rfq_match","maker_order_id":"ac928c66-ca53-498f-9c13-a110027a60e8","taker_order_id":"132fb6ae-456b-4654-b4e0-d681ac05cea1","time":"2014-11-07T08:19:27.028459Z","trade_id":30,"product_id":"BTC-USD","size":"5.23512","price":"400.23","side":"sell"}"#;
+        match serde_json::from_str::<WebsocketMessage>(rfq_match).unwrap() {
+            WebsocketMessage::RfqMatch(matched) => assert_eq!(matched.price, 400.23),
+            other => panic!("expected rfq match, got {:?}", other),
+        }
     }
 
     #[tokio::test]
@@ -1559,7 +4346,7 @@ mod websocket_stream_tests {
         let stream_builder = MockIOBuilder::new(&stream);
         let client = MockClient::new_mock(MockRequestBuilder::new_mock(vec![]));
 
-        let mut api = CBProAPI::from_client_and_io_builder(client, stream_builder.clone());
+        let api = CBProAPI::from_client_and_io_builder(client, stream_builder.clone());
 
         api.subscribe_to_websocket(
             SubscriptionBuilder::new()
@@ -1596,7 +4383,7 @@ mod websocket_stream_tests {
         let stream_builder = MockIOBuilder::new(&stream);
         let client = MockClient::new_mock(MockRequestBuilder::new_mock(vec![]));
 
-        let mut api = CBProAPI::from_client_and_io_builder(client, stream_builder.clone());
+        let api = CBProAPI::from_client_and_io_builder(client, stream_builder.clone());
 
         api.subscribe_to_websocket(
             SubscriptionBuilder::new()
@@ -1633,7 +4420,7 @@ mod websocket_stream_tests {
         let stream_builder = MockIOBuilder::new(&stream);
         let client = MockClient::new_mock(MockRequestBuilder::new_mock(vec![]));
 
-        let mut api = CBProAPI::from_client_and_io_builder(client, stream_builder.clone());
+        let api = CBProAPI::from_client_and_io_builder(client, stream_builder.clone());
 
         api.subscribe_to_websocket(
             SubscriptionBuilder::new()
@@ -1676,7 +4463,7 @@ mod websocket_stream_tests {
         let stream_builder = MockIOBuilder::new(&stream);
         let client = MockClient::new_mock(MockRequestBuilder::new_mock(vec![]));
 
-        let mut api = CBProAPI::from_client_and_io_builder(client, stream_builder.clone());
+        let api = CBProAPI::from_client_and_io_builder(client, stream_builder.clone());
 
         api.subscribe_to_websocket(
             SubscriptionBuilder::new()
@@ -1725,7 +4512,7 @@ mod websocket_stream_tests {
         let stream_builder = MockIOBuilder::new(&stream);
         let client = MockClient::new_mock(MockRequestBuilder::new_mock(vec![]));
 
-        let mut api = CBProAPI::from_client_and_io_builder(client, stream_builder.clone());
+        let api = CBProAPI::from_client_and_io_builder(client, stream_builder.clone());
 
         api.subscribe_to_websocket(
             SubscriptionBuilder::new()
@@ -1785,21 +4572,27 @@ mod websocket_stream_tests {
         let api = CBProAPI::default();
     }
 
+    fn test_order_book() -> OrderBook {
+        let bids: Vec<OrderBookEntry> = vec![
+            (0f64, 1f64).try_into().unwrap(),
+            (1f64, 1f64).try_into().unwrap(),
+            (2f64, 1f64).try_into().unwrap(),
+        ];
+        let asks: Vec<OrderBookEntry> = vec![
+            (6f64, 1f64).try_into().unwrap(),
+            (5f64, 1f64).try_into().unwrap(),
+            (4f64, 1f64).try_into().unwrap(),
+        ];
+
+        OrderBook::new(
+            BookSide::from_entries(Side::BUY, bids),
+            BookSide::from_entries(Side::SELL, asks),
+        )
+    }
+
     #[tokio::test]
     async fn order_book_test_2() {
-        let mut order_book = OrderBook {
-            bids: Arc::new(Mutex::new(vec![
-                (0f64, 1f64).try_into().unwrap(),
-                (1f64, 1f64).try_into().unwrap(),
-                (2f64, 1f64).try_into().unwrap(),
-            ])),
-            asks: Arc::new(Mutex::new(vec![
-                (6f64, 1f64).try_into().unwrap(),
-                (5f64, 1f64).try_into().unwrap(),
-                (4f64, 1f64).try_into().unwrap(),
-            ])),
-            updated: Arc::new(Mutex::new(Instant::now())),
-        };
+        let mut order_book = test_order_book();
 
         let ask_lock = order_book.asks.lock().await;
         let idx = OrderBook::find_ask_index(&ask_lock, &(5f64, 1f64).try_into().unwrap());
@@ -1820,19 +4613,7 @@ mod websocket_stream_tests {
 
     #[tokio::test]
     async fn order_book_test_3() {
-        let mut order_book = OrderBook {
-            bids: Arc::new(Mutex::new(vec![
-                (0f64, 1f64).try_into().unwrap(),
-                (1f64, 1f64).try_into().unwrap(),
-                (2f64, 1f64).try_into().unwrap(),
-            ])),
-            asks: Arc::new(Mutex::new(vec![
-                (6f64, 1f64).try_into().unwrap(),
-                (5f64, 1f64).try_into().unwrap(),
-                (4f64, 1f64).try_into().unwrap(),
-            ])),
-            updated: Arc::new(Mutex::new(Instant::now())),
-        };
+        let mut order_book = test_order_book();
 
         order_book
             .apply_change(Side::BUY, (0f64, 0f64).try_into().unwrap())
@@ -1864,10 +4645,299 @@ mod websocket_stream_tests {
             .await;
         assert_eq!(order_book.bids.lock().await.len(), 0);
 
+        // Removing a level that is not in the book leaves the side empty.
         order_book
             .apply_change(Side::BUY, (2f64, 0f64).try_into().unwrap())
             .await;
-        assert_eq!(order_book.bids.lock().await.len(), 1);
+        assert_eq!(order_book.bids.lock().await.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn order_book_ignores_removal_of_missing_level() {
+        let mut order_book = depth_order_book();
+        assert_eq!(order_book.mid_price().await, 100f64);
+
+        order_book
+            .apply_change(Side::BUY, (100.5f64, 0f64).try_into().unwrap())
+            .await;
+        order_book
+            .apply_change(Side::SELL, (100.5f64, 0f64).try_into().unwrap())
+            .await;
+
+        assert_eq!(order_book.mid_price().await, 100f64);
+        assert_eq!(book_prices(&order_book.bids).await, vec![98f64, 99f64]);
+        assert_eq!(
+            book_prices(&order_book.asks).await,
+            vec![103f64, 102f64, 101f64]
+        );
+    }
+
+    fn depth_order_book() -> OrderBook {
+        let bids: Vec<OrderBookEntry> = vec![
+            (99f64, 1f64).try_into().unwrap(),
+            (98f64, 3f64).try_into().unwrap(),
+        ];
+        let asks: Vec<OrderBookEntry> = vec![
+            (101f64, 1f64).try_into().unwrap(),
+            (102f64, 2f64).try_into().unwrap(),
+            (103f64, 5f64).try_into().unwrap(),
+        ];
+
+        OrderBook::new(
+            BookSide::from_entries(Side::BUY, bids),
+            BookSide::from_entries(Side::SELL, asks),
+        )
+    }
+
+    #[tokio::test]
+    async fn order_book_depth_queries() {
+        let order_book = depth_order_book();
+
+        order_book
+            .depth(|depth| {
+                assert_eq!(depth.best_bid().unwrap().price(), 99f64);
+                assert_eq!(depth.best_ask().unwrap().price(), 101f64);
+                assert_eq!(depth.mid_price(), Some(100f64));
+                assert_eq!(depth.spread(), Some(2f64));
+                assert_eq!(depth.spread_bps(), Some(200f64));
+
+                assert_eq!(
+                    depth.depth_within_bps(150f64),
+                    Some(Depth {
+                        bids: 1f64,
+                        asks: 1f64
+                    })
+                );
+                assert_eq!(
+                    depth.depth_within_bps(250f64),
+                    Some(Depth {
+                        bids: 4f64,
+                        asks: 3f64
+                    })
+                );
+                assert_eq!(depth.imbalance_within_bps(150f64), Some(0f64));
+                assert_eq!(depth.imbalance_within_bps(250f64), Some(1f64 / 7f64));
+            })
+            .await;
+
+        let empty_bids = BookSide::new(Side::BUY);
+        let asks = order_book.asks.lock().await;
+        let one_sided = BookDepth::new(&empty_bids, &asks);
+        assert_eq!(one_sided.mid_price(), None);
+        assert_eq!(one_sided.spread(), None);
+        assert_eq!(one_sided.depth_within_bps(100f64), None);
+        assert_eq!(asks.depth_to(102f64), 3f64);
+    }
+
+    #[tokio::test]
+    async fn order_book_depth_fills() {
+        let order_book = depth_order_book();
+
+        order_book
+            .depth(|depth| {
+                assert_eq!(
+                    depth.fill(Side::BUY, FillAmount::Size(2f64)),
+                    Some(Fill {
+                        size: 2f64,
+                        funds: 203f64,
+                        average_price: 101.5f64,
+                        last_price: 102f64,
+                        slippage_bps: 0.5f64 / 101f64 * 10_000f64,
+                        complete: true,
+                    })
+                );
+
+                let fill = depth.fill(Side::BUY, FillAmount::Funds(305f64)).unwrap();
+                assert_eq!(fill.size, 3f64);
+                assert_eq!(fill.funds, 305f64);
+                assert_eq!(fill.last_price, 102f64);
+                assert!(fill.complete);
+
+                let fill = depth.fill(Side::SELL, FillAmount::Size(10f64)).unwrap();
+                assert_eq!(fill.size, 4f64);
+                assert_eq!(fill.funds, 393f64);
+                assert_eq!(fill.average_price, 98.25f64);
+                assert_eq!(fill.last_price, 98f64);
+                assert!(!fill.complete);
+
+                assert_eq!(
+                    depth.exhaustion_price(Side::BUY, FillAmount::Size(1.5f64)),
+                    Some(102f64)
+                );
+                assert_eq!(
+                    depth.exhaustion_price(Side::SELL, FillAmount::Size(1f64)),
+                    Some(99f64)
+                );
+                assert_eq!(depth.fill(Side::BUY, FillAmount::Size(0f64)), None);
+            })
+            .await;
+
+        assert_eq!(
+            BookSide::new(Side::SELL).fill(FillAmount::Funds(100f64)),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn order_book_depth_fill_across_removed_level() {
+        let mut order_book = depth_order_book();
+        order_book
+            .apply_change(Side::SELL, (102f64, 0f64).try_into().unwrap())
+            .await;
+        order_book
+            .apply_change(Side::SELL, (100.5f64, 0f64).try_into().unwrap())
+            .await;
+
+        order_book
+            .depth(|depth| {
+                assert_eq!(depth.best_ask().unwrap().price(), 101f64);
+                assert_eq!(depth.spread(), Some(2f64));
+
+                let fill = depth.fill(Side::BUY, FillAmount::Size(2f64)).unwrap();
+                assert_eq!(fill.funds, 204f64);
+                assert_eq!(fill.average_price, 102f64);
+                assert_eq!(fill.last_price, 103f64);
+                assert!(fill.complete);
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn order_book_depth_fill_stops_at_covering_level() {
+        // 3 * 0.3 comes out just under 0.9, the first level must still cover the funds.
+        let asks: Vec<OrderBookEntry> = vec![
+            (0.3f64, 3f64).try_into().unwrap(),
+            (0.6f64, 1f64).try_into().unwrap(),
+        ];
+        let order_book = OrderBook::new(
+            BookSide::new(Side::BUY),
+            BookSide::from_entries(Side::SELL, asks),
+        );
+
+        order_book
+            .depth(|depth| {
+                let fill = depth.fill(Side::BUY, FillAmount::Funds(0.9f64)).unwrap();
+                assert_eq!(fill.funds, 0.9f64);
+                assert_eq!(fill.last_price, 0.3f64);
+                assert!(fill.complete);
+                assert_eq!(
+                    depth.exhaustion_price(Side::BUY, FillAmount::Funds(0.9f64)),
+                    Some(0.3f64)
+                );
+            })
+            .await;
+
+        let asks = BookSide::from_entries(Side::SELL, vec![(0.3f64, 3f64).try_into().unwrap()]);
+        assert!(asks.fill(FillAmount::Funds(0.9f64)).unwrap().complete);
+    }
+
+    fn level2_change(side: &str, price: f64, size: f64) -> Level2Change {
+        Level2Change {
+            side: side.to_string(),
+            price,
+            size,
+        }
+    }
+
+    #[tokio::test]
+    async fn order_book_snapshot_published_per_batch() {
+        let mut order_book = test_order_book();
+        let initial = order_book.snapshot();
+        assert_eq!(initial.sequence(), 0);
+        assert_eq!(initial.mid_price(), Some(3f64));
+
+        order_book
+            .apply_change_l2_changes(vec![
+                level2_change("buy", 3f64, 2f64),
+                level2_change("sell", 4f64, 0f64),
+            ])
+            .await;
+
+        let snapshot = order_book.snapshot();
+        assert_eq!(snapshot.sequence(), 1);
+        assert_eq!(
+            book_side_prices(snapshot.bids()),
+            vec![3f64, 2f64, 1f64, 0f64]
+        );
+        assert_eq!(book_side_prices(snapshot.asks()), vec![5f64, 6f64]);
+        assert_eq!(snapshot.depth().spread(), Some(2f64));
+        // Earlier snapshots are not changed by later batches.
+        assert_eq!(book_side_prices(initial.asks()), vec![4f64, 5f64, 6f64]);
+
+        // Readers do not wait on the locks of the book.
+        let locks = order_book.bid_ask_locks().await;
+        assert_eq!(order_book.snapshot().sequence(), 1);
+        drop(locks);
+
+        order_book.set_snapshot_levels(1);
+        order_book
+            .apply_change(Side::SELL, (7f64, 1f64).try_into().unwrap())
+            .await;
+        let snapshot = order_book.snapshot();
+        assert_eq!(snapshot.sequence(), 2);
+        assert_eq!(book_side_prices(snapshot.bids()), vec![3f64]);
+        assert_eq!(book_side_prices(snapshot.asks()), vec![5f64]);
+        assert_eq!(order_book.asks.lock().await.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn order_book_snapshot_ignores_removal_of_missing_level() {
+        let mut order_book = test_order_book();
+
+        order_book
+            .apply_change_l2_changes(vec![
+                level2_change("buy", 3f64, 0f64),
+                level2_change("sell", 3.5f64, 0f64),
+            ])
+            .await;
+
+        let snapshot = order_book.snapshot();
+        assert_eq!(snapshot.sequence(), 1);
+        assert_eq!(book_side_prices(snapshot.bids()), vec![2f64, 1f64, 0f64]);
+        assert_eq!(book_side_prices(snapshot.asks()), vec![4f64, 5f64, 6f64]);
+        assert!(snapshot
+            .bids()
+            .levels()
+            .chain(snapshot.asks().levels())
+            .all(|level| level.size() > 0f64));
+        assert_eq!(snapshot.mid_price(), Some(3f64));
+    }
+
+    #[tokio::test]
+    async fn order_book_snapshot_subscription() {
+        let mut order_book = test_order_book();
+        let mut snapshots = order_book.subscribe_snapshots();
+
+        let writer = order_book.clone();
+        let mut lock = writer.bids.lock().await;
+        order_book
+            .apply_change_with_lock(Side::BUY, (3f64, 1f64).try_into().unwrap(), &mut lock)
+            .await;
+        drop(lock);
+        assert!(!snapshots.has_changed().unwrap());
+
+        order_book.publish_snapshot().await;
+        snapshots.changed().await.unwrap();
+        let snapshot = snapshots.borrow_and_update().clone();
+        assert_eq!(snapshot.sequence(), 1);
+        assert_eq!(snapshot.bids().best().unwrap().price(), 3f64);
+
+        order_book
+            .apply_snapshot(Level2Snapshot {
+                product_id: "BTC-USD".to_string(),
+                bids: vec![Quote {
+                    price: 10f64,
+                    size: 1f64,
+                }],
+                asks: vec![Quote {
+                    price: 12f64,
+                    size: 1f64,
+                }],
+            })
+            .await;
+        snapshots.changed().await.unwrap();
+        assert_eq!(snapshots.borrow().sequence(), 2);
+        assert_eq!(snapshots.borrow().mid_price(), Some(11f64));
     }
 }
 
@@ -1894,7 +4964,7 @@ mod live_tests {
     #[tokio::test]
     pub async fn heartbeat() {
         let res = SimpleLogger::new().with_level(LevelFilter::Debug).init();
-        let mut api = CBProAPI::default();
+        let api = CBProAPI::default();
         let request = SubscriptionBuilder::new()
             .subscribe_to_heartbeat("ETH-USD".to_string())
             .build();
@@ -1907,7 +4977,7 @@ mod live_tests {
     #[tokio::test]
     pub async fn status() {
         let res = SimpleLogger::new().with_level(LevelFilter::Debug).init();
-        let mut api = CBProAPI::default();
+        let api = CBProAPI::default();
         let request = SubscriptionBuilder::new().subscribe_to_status().build();
 
         api.subscribe_to_websocket(request).await.unwrap();
@@ -1918,7 +4988,7 @@ mod live_tests {
     #[tokio::test]
     pub async fn ticker() {
         let res = SimpleLogger::new().with_level(LevelFilter::Debug).init();
-        let mut api = CBProAPI::default();
+        let api = CBProAPI::default();
         let request = SubscriptionBuilder::new()
             .subscribe_to_ticker("ETH-USD".to_string())
             .build();
@@ -1937,7 +5007,7 @@ mod live_tests {
     #[tokio::test]
     pub async fn level2() {
         let res = SimpleLogger::new().with_level(LevelFilter::Debug).init();
-        let mut api = CBProAPI::default();
+        let api = CBProAPI::default();
         let request = SubscriptionBuilder::new()
             .subscribe_to_snapshot("ETH-USD".to_string())
             .build();
@@ -1956,7 +5026,7 @@ mod live_tests {
     #[tokio::test]
     pub async fn level3() {
         let res = SimpleLogger::new().with_level(LevelFilter::Debug).init();
-        let mut api = CBProAPI::default();
+        let api = CBProAPI::default();
         let request = SubscriptionBuilder::new()
             .subscribe_to_full("ETH-USD".to_string())
             .build();
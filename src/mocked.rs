@@ -2,7 +2,6 @@
 
 use std::any::type_name;
 
-use std::cell::RefCell;
 use std::cmp::min;
 use std::collections::vec_deque::VecDeque;
 use std::fmt::{
@@ -17,7 +16,6 @@ use std::io::{
 
 use std::io;
 use std::net::SocketAddr;
-use std::ops::Deref;
 use std::pin::Pin;
 use std::sync::{
     Arc,
@@ -42,6 +40,7 @@ use reqwest::{
     Error,
     IntoUrl,
     Method,
+    StatusCode,
 };
 use tokio::io::{
     AsyncRead,
@@ -54,35 +53,44 @@ use tokio::sync::Mutex;
 use crate::api::AsyncIOBuilder;
 use crate::errors::WebsocketError;
 
-use crate::websocket_lite::AsyncIO;
+use crate::websocket_lite::{
+    AsyncIO,
+    WebsocketStreamConnector,
+};
 
 pub struct MockClient {
-    payload: Arc<RefCell<Option<MockRequestBuilder>>>,
-    pub requested_url: Arc<RefCell<String>>,
+    payload: Arc<StdMutex<VecDeque<MockRequestBuilder>>>,
+    pub requested_url: Arc<StdMutex<String>>,
+    pub requested_method: Arc<StdMutex<Option<Method>>>,
 }
 
 impl MockClient {
     pub fn new() -> Self {
         Self {
-            payload: Arc::new(RefCell::new(None)),
-            requested_url: Arc::new(RefCell::new("".to_string())),
+            payload: Arc::new(StdMutex::new(VecDeque::new())),
+            requested_url: Arc::new(StdMutex::new("".to_string())),
+            requested_method: Arc::new(StdMutex::new(None)),
         }
     }
 
     pub fn new_mock(payload: MockRequestBuilder) -> Self {
+        Self::new_mock_sequence(vec![payload])
+    }
+
+    /// Creates a client that hands out one request builder per request in the given order.
+    pub fn new_mock_sequence(payloads: Vec<MockRequestBuilder>) -> Self {
         Self {
-            payload: Arc::new(RefCell::new(Some(payload))),
-            requested_url: Arc::new(RefCell::from("".to_string())),
+            payload: Arc::new(StdMutex::new(VecDeque::from(payloads))),
+            requested_url: Arc::new(StdMutex::new("".to_string())),
+            requested_method: Arc::new(StdMutex::new(None)),
         }
     }
 
-    pub fn request(&self, _method: Method, url: impl IntoUrl) -> MockRequestBuilder {
-        self.requested_url
-            .deref()
-            .replace(format!("{:?}", url.into_url().map(|x| x.to_string())));
-        let payload = RefCell::new(None);
-        self.payload.swap(&payload);
-        payload.into_inner().unwrap()
+    pub fn request(&self, method: Method, url: impl IntoUrl) -> MockRequestBuilder {
+        *self.requested_method.lock().unwrap() = Some(method);
+        *self.requested_url.lock().unwrap() =
+            format!("{:?}", url.into_url().map(|x| x.to_string()));
+        self.payload.lock().unwrap().pop_front().unwrap()
     }
 }
 
@@ -91,6 +99,7 @@ impl Clone for MockClient {
         Self {
             payload: self.payload.clone(),
             requested_url: self.requested_url.clone(),
+            requested_method: self.requested_method.clone(),
         }
     }
 }
@@ -155,15 +164,15 @@ impl CallInfo {
 
 #[derive(Clone, Debug)]
 pub struct MockRequestBuilder {
-    pub call_info: Arc<RefCell<Vec<CallInfo>>>,
-    pub payloads: Arc<RefCell<Vec<MockResponse>>>,
+    pub call_info: Arc<StdMutex<Vec<CallInfo>>>,
+    pub payloads: Arc<StdMutex<Vec<MockResponse>>>,
 }
 
 impl MockRequestBuilder {
     pub fn new_mock(payloads: Vec<MockResponse>) -> Self {
         Self {
-            call_info: Arc::new(RefCell::new(vec![])),
-            payloads: Arc::new(RefCell::new(payloads)),
+            call_info: Arc::new(StdMutex::new(vec![])),
+            payloads: Arc::new(StdMutex::new(payloads)),
         }
     }
 
@@ -178,21 +187,21 @@ impl MockRequestBuilder {
         let arg_1 = ArgumentInfo::new("key", key);
         let arg_2 = ArgumentInfo::new("value", value);
         let call_info = CallInfo::new("header", vec![arg_1, arg_2]);
-        self.call_info.deref().borrow_mut().push(call_info);
+        self.call_info.lock().unwrap().push(call_info);
         self
     }
 
     pub fn body(self, body: Body) -> Self {
         let arg_1 = ArgumentInfo::new("body", body);
         let call_info = CallInfo::new("body", vec![arg_1]);
-        self.call_info.deref().borrow_mut().push(call_info);
+        self.call_info.lock().unwrap().push(call_info);
         self
     }
 
     pub fn query<T: std::fmt::Debug + 'static>(self, query_params: &T) -> Self {
         let arg_1 = ArgumentInfo::new("query_params", query_params);
         let call_info = CallInfo::new("query", vec![arg_1]);
-        self.call_info.deref().borrow_mut().push(call_info);
+        self.call_info.lock().unwrap().push(call_info);
         self
     }
 
@@ -202,12 +211,12 @@ impl MockRequestBuilder {
 
     pub fn build(&self) -> Result<MockResponse, Error> {
         let clone = self.clone();
-        let mut test = clone.payloads.deref().borrow_mut();
+        let mut test = clone.payloads.lock().unwrap();
         Ok(test.pop().unwrap())
     }
 
     pub async fn send(&self) -> Result<MockResponse, Error> {
-        let mut test = self.payloads.deref().borrow_mut();
+        let mut test = self.payloads.lock().unwrap();
         Ok(test.pop().unwrap())
     }
 }
@@ -221,6 +230,7 @@ mock! {
     pub Response {
         pub async fn text(self) -> Result<String, Error>;
         pub fn headers(&self) -> &MockHeaderMap;
+        pub fn status(&self) -> StatusCode;
     }
 }
 
@@ -439,6 +449,8 @@ impl Write for MockTlsStream<MockTcpStream> {
 pub struct MockStream {
     pub(crate) stream_contents: Arc<StdMutex<VecDeque<Vec<u8>>>>,
     pub(crate) writes: Arc<StdMutex<VecDeque<Vec<u8>>>>,
+    /// Reads stay pending once the contents are consumed instead of reaching EOF
+    pub(crate) hold_open: bool,
 }
 
 impl MockStream {
@@ -450,9 +462,16 @@ impl MockStream {
         Self {
             stream_contents: Arc::new(StdMutex::new(queue)),
             writes: Arc::new(StdMutex::new(Default::default())),
+            hold_open: false,
         }
     }
 
+    /// Keeps the connection open after the contents are consumed, like an idle feed.
+    pub fn hold_open(mut self) -> Self {
+        self.hold_open = true;
+        self
+    }
+
     pub async fn append_response(&mut self, buf: &[u8]) {
         let mut resp = buf.to_vec();
         resp.reverse();
@@ -472,6 +491,9 @@ impl AsyncRead for MockStream {
         };
 
         if next_message.is_empty() {
+            if self.hold_open {
+                return Poll::Pending;
+            }
             return Poll::Ready(Ok(()));
         }
 
@@ -527,3 +549,42 @@ impl AsyncIOBuilder for MockIOBuilder {
         Ok(Box::new(self.stream.clone()))
     }
 }
+
+/// Builder that speaks the websocket protocol over `MockStream`s, each stream must start with an
+/// upgrade response. Every connection takes the next stream, connecting fails once they run out.
+#[derive(Clone)]
+pub(crate) struct MockWebsocketIOBuilder {
+    streams: Arc<StdMutex<VecDeque<MockStream>>>,
+}
+
+impl MockWebsocketIOBuilder {
+    pub fn new(stream: &MockStream) -> Self {
+        Self::new_sequence(vec![stream.clone()])
+    }
+
+    pub fn new_sequence(streams: Vec<MockStream>) -> Self {
+        Self {
+            streams: Arc::new(StdMutex::new(VecDeque::from(streams))),
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncIOBuilder for MockWebsocketIOBuilder {
+    async fn new_stream(&self, url: &str) -> Result<Box<dyn AsyncIO>, WebsocketError> {
+        let stream = self.streams.lock().unwrap().pop_front().ok_or_else(|| {
+            WebsocketError::WebsocketConnectionError {
+                source: "No mock streams left".into(),
+                url: url.to_string(),
+            }
+        })?;
+        let url = reqwest::Url::parse(url).map_err(|err| WebsocketError::URLParseError {
+            source: Box::new(err),
+            url: url.to_string(),
+        })?;
+        let websocket = WebsocketStreamConnector::new_no_sec()
+            .connect(stream, &url)
+            .await?;
+        Ok(Box::new(websocket))
+    }
+}
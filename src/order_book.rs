@@ -4,17 +4,23 @@ use crate::datastructs::websocket::{
     Level2Snapshot,
     Quote,
 };
-use log::debug;
+use crate::depth::BookDepth;
+use log::error;
 use std::cmp::Ordering;
-use std::mem;
+use std::collections::BTreeMap;
 use std::num::FpCategory;
 use std::ops::{
-    DerefMut,
+    Deref,
     Index,
-    IndexMut,
+};
+use std::sync::atomic::{
+    AtomicUsize,
+    Ordering as AtomicOrdering,
 };
 use std::sync::Arc;
+use tokio::sync::oneshot::Sender;
 use tokio::sync::{
+    watch,
     Mutex,
     MutexGuard,
 };
@@ -162,106 +168,312 @@ impl TryFrom<(f64, f64)> for OrderBookEntry {
     }
 }
 
+/// One side of an [OrderBook], the size resting at each price level.
+///
+/// Levels are kept in a map keyed by price. Positions, [BookSide::iter] and [BookSide::last]
+/// follow the order of the `Vec` the book used to keep, bids least to greatest and asks greatest
+/// to least, so the best level is always the last one.
+#[derive(Debug, Clone)]
+pub struct BookSide {
+    side: Side,
+    levels: BTreeMap<RealFloat, OrderBookEntry>,
+}
+
+impl BookSide {
+    pub fn new(side: Side) -> Self {
+        BookSide {
+            side,
+            levels: BTreeMap::new(),
+        }
+    }
+
+    /// Builds a side from entries in any order, a later entry for a price replaces an earlier one.
+    pub fn from_entries(side: Side, entries: impl IntoIterator<Item = OrderBookEntry>) -> Self {
+        let mut book_side = Self::new(side);
+        for entry in entries {
+            book_side.apply(entry);
+        }
+        book_side
+    }
+
+    pub fn side(&self) -> &Side {
+        &self.side
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+
+    /// The highest bid or the lowest ask.
+    pub fn best(&self) -> Option<OrderBookEntry> {
+        self.last().cloned()
+    }
+
+    /// The level furthest from the best price.
+    pub fn first(&self) -> Option<&OrderBookEntry> {
+        self.iter().next()
+    }
+
+    /// The best level.
+    pub fn last(&self) -> Option<&OrderBookEntry> {
+        self.iter().next_back()
+    }
+
+    /// Size resting at `price`, None when there is no level at that price.
+    pub fn size_at(&self, price: f64) -> Option<f64> {
+        let price = RealFloat::try_from(price).ok()?;
+        self.levels.get(&price).map(|entry| entry.size())
+    }
+
+    /// Levels in the order of the old `Vec`, bids least to greatest and asks greatest to least.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &OrderBookEntry> + '_ {
+        let levels: Box<dyn DoubleEndedIterator<Item = &OrderBookEntry>> = match self.side {
+            Side::BUY => Box::new(self.levels.values()),
+            Side::SELL => Box::new(self.levels.values().rev()),
+        };
+
+        levels
+    }
+
+    /// Levels from the best price outwards.
+    pub fn levels(&self) -> impl Iterator<Item = &OrderBookEntry> + '_ {
+        self.iter().rev()
+    }
+
+    /// Sets the size of the entry's price level, a size of zero removes the level.
+    pub fn apply(&mut self, entry: OrderBookEntry) {
+        if entry.size == 0f64 {
+            self.levels.remove(&entry.price);
+        } else {
+            self.levels.insert(entry.price.clone(), entry);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.levels.clear();
+    }
+
+    /// A copy of the best `levels` price levels.
+    pub fn top(&self, levels: usize) -> BookSide {
+        BookSide::from_entries(self.side.clone(), self.levels().take(levels).cloned())
+    }
+}
+
+impl Index<usize> for BookSide {
+    type Output = OrderBookEntry;
+
+    /// The level at `index` in the order of [BookSide::iter].
+    ///
+    /// The levels are walked up to `index`, so this takes time linear in the index where indexing
+    /// the old `Vec` did not. Prefer [BookSide::iter], [BookSide::levels] or [BookSide::size_at]
+    /// in loops.
+    fn index(&self, index: usize) -> &Self::Output {
+        match self.iter().nth(index) {
+            Some(entry) => entry,
+            None => panic!(
+                "index out of bounds: the len is {} but the index is {}",
+                self.len(),
+                index
+            ),
+        }
+    }
+}
+
+/// Levels per side kept in an [OrderBookSnapshot] unless changed with
+/// [OrderBook::set_snapshot_levels].
+pub const SNAPSHOT_LEVELS: usize = 50;
+
+/// # Order Book Snapshot
+/// The best levels of an [OrderBook] as they were after a batch of changes. Both sides are taken
+/// under the same lock, the bids and asks of a snapshot always belong together.
+///
+/// Snapshots are immutable and shared behind an [Arc], holding one does not hold up the book.
+#[derive(Debug, Clone)]
+pub struct OrderBookSnapshot {
+    bids: BookSide,
+    asks: BookSide,
+    sequence: u64,
+    updated: Instant,
+}
+
+impl OrderBookSnapshot {
+    pub fn bids(&self) -> &BookSide {
+        &self.bids
+    }
+
+    pub fn asks(&self) -> &BookSide {
+        &self.asks
+    }
+
+    /// Number of the snapshot, counting up from 0 for the first one published by the book.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// When the changes in the snapshot were applied.
+    pub fn updated(&self) -> Instant {
+        self.updated
+    }
+
+    /// Depth queries on the levels of the snapshot.
+    pub fn depth(&self) -> BookDepth<'_> {
+        BookDepth::new(&self.bids, &self.asks)
+    }
+
+    /// Halfway between the best bid and ask, None when either side is empty.
+    pub fn mid_price(&self) -> Option<f64> {
+        self.depth().mid_price()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct OrderBook {
-    // Bids ordered least to greatest
-    pub(crate) bids: Arc<Mutex<Vec<OrderBookEntry>>>,
-    // Asks ordered greatest to least
-    pub(crate) asks: Arc<Mutex<Vec<OrderBookEntry>>>,
+    pub(crate) bids: Arc<Mutex<BookSide>>,
+    pub(crate) asks: Arc<Mutex<BookSide>>,
 
-    pub(crate) updated: Arc<Mutex<Instant>>,
+    /// The latest snapshot, replaced while both sides are locked
+    snapshots: Arc<watch::Sender<Arc<OrderBookSnapshot>>>,
+    snapshot_levels: Arc<AtomicUsize>,
 }
 
 impl From<Level2Snapshot> for OrderBook {
     fn from(snap: Level2Snapshot) -> Self {
-        let mut bids = snap
-            .bids
-            .into_iter()
-            .map(|q| q.try_into())
-            .flatten()
-            .collect::<Vec<OrderBookEntry>>();
-        let mut asks = snap
-            .asks
-            .into_iter()
-            .map(|q| q.try_into())
-            .flatten()
-            .collect::<Vec<OrderBookEntry>>();
-
-        bids.sort();
-        asks.sort();
-
-        asks.reverse();
+        let (bids, asks) = Self::snapshot_sides(snap);
+        Self::new(bids, asks)
+    }
+}
 
-        Self {
+impl OrderBook {
+    pub(crate) fn new(bids: BookSide, asks: BookSide) -> Self {
+        let snapshot = OrderBookSnapshot {
+            bids: bids.top(SNAPSHOT_LEVELS),
+            asks: asks.top(SNAPSHOT_LEVELS),
+            sequence: 0,
+            updated: Instant::now(),
+        };
+
+        OrderBook {
             bids: Arc::new(Mutex::new(bids)),
             asks: Arc::new(Mutex::new(asks)),
-            updated: Arc::new(Mutex::new(Instant::now())),
+            snapshots: Arc::new(watch::Sender::new(Arc::new(snapshot))),
+            snapshot_levels: Arc::new(AtomicUsize::new(SNAPSHOT_LEVELS)),
         }
     }
-}
 
-impl OrderBook {
-    pub async fn bid_ask_locks(
-        &self,
-    ) -> (
-        MutexGuard<'_, Vec<OrderBookEntry>>,
-        MutexGuard<'_, Vec<OrderBookEntry>>,
-    ) {
-        let ask_lock = self.asks.lock().await;
+    fn snapshot_sides(snap: Level2Snapshot) -> (BookSide, BookSide) {
+        let bids = snap.bids.into_iter().filter_map(|q| q.try_into().ok());
+        let asks = snap.asks.into_iter().filter_map(|q| q.try_into().ok());
+
+        (
+            BookSide::from_entries(Side::BUY, bids),
+            BookSide::from_entries(Side::SELL, asks),
+        )
+    }
+
+    /// Locks both sides, always bids first so writers cannot deadlock each other.
+    pub async fn bid_ask_locks(&self) -> (MutexGuard<'_, BookSide>, MutexGuard<'_, BookSide>) {
         let bid_lock = self.bids.lock().await;
+        let ask_lock = self.asks.lock().await;
 
         (bid_lock, ask_lock)
     }
 
+    /// The latest published snapshot, read without waiting on the locks of the book.
+    pub fn snapshot(&self) -> Arc<OrderBookSnapshot> {
+        self.snapshots.borrow().clone()
+    }
+
+    /// A receiver notified whenever a new snapshot is published.
+    pub fn subscribe_snapshots(&self) -> watch::Receiver<Arc<OrderBookSnapshot>> {
+        self.snapshots.subscribe()
+    }
+
+    /// Levels per side kept in the snapshots published from now on, shared by every clone of
+    /// the book.
+    pub fn set_snapshot_levels(&self, levels: usize) {
+        self.snapshot_levels.store(levels, AtomicOrdering::Relaxed);
+    }
+
+    /// Publishes a snapshot of the book, for changes applied with
+    /// [OrderBook::apply_change_with_lock].
+    pub async fn publish_snapshot(&self) {
+        let (bid_lock, ask_lock) = self.bid_ask_locks().await;
+        self.publish_with_locks(&bid_lock, &ask_lock);
+    }
+
+    fn publish_with_locks(&self, bid_lock: &BookSide, ask_lock: &BookSide) {
+        let levels = self.snapshot_levels.load(AtomicOrdering::Relaxed);
+
+        self.snapshots.send_modify(|snapshot| {
+            *snapshot = Arc::new(OrderBookSnapshot {
+                bids: bid_lock.top(levels),
+                asks: ask_lock.top(levels),
+                sequence: snapshot.sequence + 1,
+                updated: Instant::now(),
+            })
+        });
+    }
+
     pub async fn mid_price(&self) -> f64 {
         let (bid_lock, ask_lock) = self.bid_ask_locks().await;
-        self.mid_price_with_locks(&ask_lock, &bid_lock).await
+        self.mid_price_with_locks(&bid_lock, &ask_lock).await
     }
 
     pub async fn mid_price_with_locks(
         &self,
-        bid_lock: &MutexGuard<'_, Vec<OrderBookEntry>>,
-        ask_lock: &MutexGuard<'_, Vec<OrderBookEntry>>,
+        bid_lock: &MutexGuard<'_, BookSide>,
+        ask_lock: &MutexGuard<'_, BookSide>,
     ) -> f64 {
-        let best_ask = ask_lock
-            .last()
-            .unwrap_or(&(0f64, 0f64).try_into().unwrap())
-            .price
-            .0;
-
-        let best_bid = bid_lock
-            .last()
-            .unwrap_or(&(0f64, 0f64).clone().try_into().unwrap())
-            .price
-            .0;
+        let best_ask = ask_lock.last().map(|ask| ask.price()).unwrap_or(0f64);
+        let best_bid = bid_lock.last().map(|bid| bid.price()).unwrap_or(0f64);
 
         (best_ask + best_bid) / 2.0
     }
 
-    pub async fn apply_change_l2_changes(&mut self, changes: Vec<Level2Change>) {
-        let ask_lock = self.asks.clone();
-        let bid_lock = self.bids.clone();
-
-        let b = ask_lock.lock().await;
-        let a = bid_lock.lock().await;
+    /// Runs `query` on a view of both sides taken under a single lock, the book is not updated
+    /// until it returns.
+    pub async fn depth<R>(&self, query: impl FnOnce(BookDepth<'_>) -> R) -> R {
+        let (bid_lock, ask_lock) = self.bid_ask_locks().await;
+        query(BookDepth::new(&bid_lock, &ask_lock))
+    }
 
-        let mut locks = (a, b);
+    /// Applies a batch of changes and publishes a snapshot of the result.
+    pub async fn apply_change_l2_changes(&mut self, changes: Vec<Level2Change>) {
+        let (mut bid_lock, mut ask_lock) = self.bid_ask_locks().await;
 
         for change in changes {
-            match change.clone().side.as_str() {
-                "buy" => {
-                    self.apply_change_with_lock(Side::BUY, change.into(), &mut locks.0)
-                        .await;
-                }
-                "sell" => {
-                    self.apply_change_with_lock(Side::SELL, change.into(), &mut locks.1)
-                        .await;
+            let entry = match OrderBookEntry::try_from((change.price, change.size)) {
+                Ok(entry) => entry,
+                Err(_) => {
+                    error!("Skipping level2 change with invalid price or size: {:?}", change);
+                    continue;
                 }
+            };
+
+            match change.side.as_str() {
+                "buy" => bid_lock.apply(entry),
+                "sell" => ask_lock.apply(entry),
                 _ => {
-                    return;
+                    break;
                 }
             }
         }
+
+        self.publish_with_locks(&bid_lock, &ask_lock);
+    }
+
+    /// Replaces both sides of the book with the levels of a snapshot.
+    pub async fn apply_snapshot(&mut self, snapshot: Level2Snapshot) {
+        let (bids, asks) = Self::snapshot_sides(snapshot);
+        let (mut bid_lock, mut ask_lock) = self.bid_ask_locks().await;
+
+        *bid_lock = bids;
+        *ask_lock = asks;
+        self.publish_with_locks(&bid_lock, &ask_lock);
     }
 
     pub async fn apply_change_l2_change(&mut self, change: Level2Change) {
@@ -273,93 +485,95 @@ impl OrderBook {
             }
         };
 
-        self.apply_change(side, change.into()).await
+        match OrderBookEntry::try_from((change.price, change.size)) {
+            Ok(entry) => self.apply_change(side, entry).await,
+            Err(_) => error!("Skipping level2 change with invalid price or size: {:?}", change),
+        }
     }
 
+    /// Applies the entry to the side held by `lock`, which must be the lock of `side`.
+    ///
+    /// No snapshot is published, call [OrderBook::publish_snapshot] once the lock is released.
     pub async fn apply_change_with_lock(
         &mut self,
-        side: Side,
+        _side: Side,
         entry: OrderBookEntry,
-        lock: &mut MutexGuard<'_, Vec<OrderBookEntry>>,
+        lock: &mut MutexGuard<'_, BookSide>,
     ) {
-        let idx = match side {
-            Side::BUY => Self::find_bid_index(&lock, &entry),
-            Side::SELL => Self::find_ask_index(&lock, &entry),
-        };
-
-
-
-        if idx == lock.len() {
-            lock.insert(idx, entry);
-            mem::swap(self.updated.lock().await.deref_mut(), &mut Instant::now());
-            return;
-        }
-
-        if entry.size == 0.0{
-            lock.remove(idx);
-            return;
-        }
+        lock.apply(entry);
+    }
 
-        if lock.index(idx).price == entry.price {
-            if entry.size == 0f64 {
-                lock.remove(idx);
-            } else {
-                lock.index_mut(idx).size = entry.size;
-            }
+    /// Time since the latest snapshot was published.
+    pub async fn last_updated(&mut self) -> Duration {
+        self.snapshots.borrow().updated.elapsed()
+    }
 
-            mem::swap(self.updated.lock().await.deref_mut(), &mut Instant::now());
-            return;
-        }
+    pub async fn apply_change(&mut self, side: Side, entry: OrderBookEntry) {
+        let (mut bid_lock, mut ask_lock) = self.bid_ask_locks().await;
 
-        if lock.index(idx).price != entry.price && entry.size != 0f64 {
-            lock.insert(idx, entry);
-            mem::swap(self.updated.lock().await.deref_mut(), &mut Instant::now());
-            return;
+        match side {
+            Side::BUY => bid_lock.apply(entry),
+            Side::SELL => ask_lock.apply(entry),
         }
+        self.publish_with_locks(&bid_lock, &ask_lock);
     }
 
-    pub async fn last_updated(&mut self) -> Duration {
-        self.updated.lock().await.elapsed()
+    /// Position of the entry's price in the bids, or where it would be inserted. Only needed to
+    /// check the order of the levels in tests.
+    #[cfg(all(test, feature = "mock"))]
+    pub(crate) fn find_bid_index(bids: &MutexGuard<BookSide>, entry: &OrderBookEntry) -> usize {
+        bids.levels.range(..&entry.price).count()
     }
 
-    pub async fn apply_change(&mut self, side: Side, entry: OrderBookEntry) {
-        let vec = match side {
-            Side::BUY => self.bids.clone(),
-            Side::SELL => self.asks.clone(),
+    /// Position of the entry's price in the asks, or where it would be inserted. Only needed to
+    /// check the order of the levels in tests.
+    #[cfg(all(test, feature = "mock"))]
+    pub(crate) fn find_ask_index(asks: &MutexGuard<BookSide>, entry: &OrderBookEntry) -> usize {
+        use std::ops::Bound::{
+            Excluded,
+            Unbounded,
         };
 
-        let mut lock = vec.lock().await;
-
-        self.apply_change_with_lock(side, entry, &mut lock).await;
+        asks.levels
+            .range((Excluded(&entry.price), Unbounded))
+            .count()
     }
+}
 
-    pub(crate) fn find_bid_index(
-        vec: &MutexGuard<Vec<OrderBookEntry>>,
-        entry: &OrderBookEntry,
-    ) -> usize {
-        for (index, bid) in vec.iter().enumerate().rev() {
-            if bid.price == entry.price {
-                return index;
-            } else if bid.price < entry.price {
-                return index + 1;
-            }
+/// # Live Order Book
+/// An [OrderBook] kept up to date by a background task, returned by `CBProAPI::new_order_book_l2`.
+///
+/// Dropping the handle stops the task, clones of the book taken from it stop updating. Read the
+/// book through [OrderBook::snapshot] to never wait on the task applying changes.
+#[derive(Debug)]
+pub struct LiveOrderBook {
+    order_book: OrderBook,
+    /// Dropped with the handle, which tells the task to stop
+    shutdown: Sender<()>,
+}
+
+impl LiveOrderBook {
+    pub(crate) fn new(order_book: OrderBook, shutdown: Sender<()>) -> Self {
+        LiveOrderBook {
+            order_book,
+            shutdown,
         }
+    }
 
-        return 0;
+    pub fn order_book(&self) -> &OrderBook {
+        &self.order_book
     }
 
-    pub(crate) fn find_ask_index(
-        vec: &MutexGuard<Vec<OrderBookEntry>>,
-        entry: &OrderBookEntry,
-    ) -> usize {
-        for (index, ask) in vec.iter().enumerate().rev() {
-            if ask.price == entry.price {
-                return index;
-            } else if ask.price > entry.price {
-                return index + 1;
-            }
-        }
+    /// False once the task stopped updating the book, after the websocket feed ended.
+    pub fn is_live(&self) -> bool {
+        !self.shutdown.is_closed()
+    }
+}
+
+impl Deref for LiveOrderBook {
+    type Target = OrderBook;
 
-        return 0;
+    fn deref(&self) -> &Self::Target {
+        &self.order_book
     }
 }
@@ -1,5 +1,8 @@
 use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
 
+use futures::Stream;
 use hmac::{
     Hmac,
     Mac,
@@ -8,13 +11,20 @@ use reqwest::Method;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use sha2::Sha256;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
 
 #[cfg(not(feature = "mock"))]
 use mocked::*;
 #[cfg(feature = "mock")]
 use mocks::*;
 
-use crate::api::APIKeyData;
+use crate::api::{
+    APIKeyData,
+    RateLimitedPool,
+    RetryPolicy,
+};
+use crate::datastructs::time::ServerTime;
 use crate::errors::Error::{
     CBProServerErrorVariant,
     RequestBuilderCloningError,
@@ -24,6 +34,7 @@ use crate::errors::Error::{
 use crate::errors::{
     CBProServerError,
     Error,
+    HttpErrorData,
 };
 
 #[cfg(feature = "mock")]
@@ -31,6 +42,7 @@ mod mocks {
     pub use crate::mocked::{
         MockClient as Client,
         MockRequestBuilder as RequestBuilder,
+        MockResponse as Response,
     };
 }
 
@@ -38,15 +50,105 @@ mod mocked {
     pub use reqwest::{
         Client,
         RequestBuilder,
+        Response,
     };
 }
 
 type HmacSha256 = Hmac<Sha256>;
 
+/// Response headers carried into [HttpErrorData] when a request fails.
+const ERROR_HEADERS: [&str; 3] = ["retry-after", "content-type", "cb-request-id"];
+
+/// Largest page size accepted by coinbase's pagenated endpoints.
+const MAX_PAGE_LEN: u64 = 1000;
+
+/// Caller controlled cursors for pagenated requests.
+///
+/// `before` and `after` are the `cb-before` / `cb-after` header values returned by a previous
+/// page and `limit` is the number of items requested per page.
+#[derive(Clone, Debug, Default)]
+pub struct Pagination {
+    pub limit: Option<u64>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+struct Page<O> {
+    items: Vec<O>,
+    after: Option<String>,
+    before: Option<String>,
+}
+
+struct PageStreamState {
+    builder: CBRequestBuilder,
+    request: RequestBuilder,
+    after: Option<String>,
+    before: Option<String>,
+    pages: usize,
+    done: bool,
+}
+
+struct ClockSync {
+    offset_millis: i64,
+    measured_at: Instant,
+}
+
+/// Offset between coinbase's clock and the local clock.
+///
+/// Coinbase rejects signed requests whose timestamp is more than 30 seconds from its own clock.
+/// The offset is measured against `/time` the first time it is needed and measured again once it
+/// is older than `refresh_interval`.
+#[derive(Clone)]
+pub(crate) struct ServerClock {
+    refresh_interval: Duration,
+    sync: Arc<Mutex<Option<ClockSync>>>,
+}
+
+impl ServerClock {
+    pub fn new(refresh_interval: Duration) -> Self {
+        ServerClock {
+            refresh_interval,
+            sync: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Milliseconds to add to the local clock to get the server's clock.
+    async fn offset_millis(&self, request: &CBRequestBuilder) -> Result<i64, Error> {
+        // Holding the lock while measuring stops concurrent requests from all hitting /time.
+        let mut sync = self.sync.lock().await;
+
+        if let Some(sync) = sync.as_ref() {
+            if sync.measured_at.elapsed() < self.refresh_interval {
+                return Ok(sync.offset_millis);
+            }
+        }
+
+        let sent = chrono::Utc::now().timestamp_millis();
+        let server_time = request.fetch_server_time().await?;
+        let received = chrono::Utc::now().timestamp_millis();
+
+        // Assume the server read its clock halfway through the round trip.
+        let offset_millis = (server_time.epoch * 1000.0) as i64 - (sent + received) / 2;
+        *sync = Some(ClockSync {
+            offset_millis,
+            measured_at: Instant::now(),
+        });
+
+        Ok(offset_millis)
+    }
+
+    /// Unix timestamp of the server's clock.
+    pub(crate) async fn timestamp(&self, request: &CBRequestBuilder) -> Result<i64, Error> {
+        let offset_millis = self.offset_millis(request).await?;
+        Ok((chrono::Utc::now().timestamp_millis() + offset_millis).div_euclid(1000))
+    }
+}
+
 #[derive(Clone)]
 pub enum RequestMethod {
     GET,
     POST,
+    DELETE,
 }
 
 impl Into<String> for RequestMethod {
@@ -54,6 +156,7 @@ impl Into<String> for RequestMethod {
         match self {
             RequestMethod::GET => "GET".to_string(),
             RequestMethod::POST => "POST".to_string(),
+            RequestMethod::DELETE => "DELETE".to_string(),
         }
     }
 }
@@ -63,6 +166,7 @@ impl Into<reqwest::Method> for RequestMethod {
         match self {
             RequestMethod::GET => reqwest::Method::GET,
             RequestMethod::POST => reqwest::Method::POST,
+            RequestMethod::DELETE => reqwest::Method::DELETE,
         }
     }
 }
@@ -74,6 +178,7 @@ impl Into<reqwest::Method> for RequestMethod {
 /// custom url in case you would like to query an alternate endpoint or coinbase changes urls.
 ///
 ///
+#[derive(Clone)]
 pub struct CBRequestBuilder {
     client: Client,
     user_agent: String,
@@ -83,6 +188,14 @@ pub struct CBRequestBuilder {
     body: String,
     url: String,
     method: RequestMethod,
+    after: Option<String>,
+    before: Option<String>,
+    limit: Option<u64>,
+    max_pages: Option<usize>,
+    pool: Option<RateLimitedPool>,
+    retry_policy: RetryPolicy,
+    idempotent: Option<bool>,
+    clock: Option<ServerClock>,
 }
 
 impl CBRequestBuilder {
@@ -96,6 +209,14 @@ impl CBRequestBuilder {
             body: "".to_string(),
             url: "https://api.exchange.coinbase.com".to_string(),
             method: RequestMethod::GET,
+            after: None,
+            before: None,
+            limit: None,
+            max_pages: None,
+            pool: None,
+            retry_policy: RetryPolicy::none(),
+            idempotent: None,
+            clock: None,
         }
     }
 
@@ -137,92 +258,287 @@ impl CBRequestBuilder {
         Ok(self)
     }
 
-    pub async fn exec_pagenated<O>(self) -> Result<Vec<O>, Error>
-    where
-        O: DeserializeOwned + Debug,
-    {
+    /// Start a pagenated request from the given `after` cursor instead of the most recent page.
+    pub fn set_after(mut self, after: Option<String>) -> Self {
+        self.after = after;
+        self
+    }
+
+    /// Start a pagenated request from the given `before` cursor.
+    /// When only `before` is set [Self::exec_paginated_stream] walks towards newer pages.
+    pub fn set_before(mut self, before: Option<String>) -> Self {
+        self.before = before;
+        self
+    }
+
+    /// Number of items requested per page.
+    pub fn set_limit(mut self, limit: Option<u64>) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Apply a set of caller supplied [Pagination] options.
+    pub fn set_pagination(self, pagination: Pagination) -> Self {
+        self.set_limit(pagination.limit)
+            .set_before(pagination.before)
+            .set_after(pagination.after)
+    }
+
+    /// Stop a pagenated request after this many pages even if more are available.
+    pub fn set_max_pages(mut self, max_pages: Option<usize>) -> Self {
+        self.max_pages = max_pages;
+        self
+    }
+
+    /// Schedule every page of a pagenated stream through the given rate limiter, retrying failed
+    /// pages according to `retry_policy`.
+    pub(crate) fn set_rate_limiter(
+        mut self,
+        pool: RateLimitedPool,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        self.pool = Some(pool);
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Correct the signing timestamp using the given server clock.
+    pub(crate) fn set_server_clock(mut self, clock: Option<ServerClock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Override whether this request is safe to resend after a failure.
+    /// When [None] every method except POST is treated as idempotent.
+    pub fn set_idempotent(mut self, idempotent: Option<bool>) -> Self {
+        self.idempotent = idempotent;
+        self
+    }
+
+    pub(crate) fn is_signed(&self) -> bool {
+        self.credentials.is_some()
+    }
+
+    /// Whether this request places or cancels orders.
+    pub(crate) fn is_order_request(&self) -> bool {
+        !matches!(self.method, RequestMethod::GET) && self.end_point.starts_with("/orders")
+    }
+
+    pub fn is_idempotent(&self) -> bool {
+        self.idempotent
+            .unwrap_or(!matches!(self.method, RequestMethod::POST))
+    }
+
+    fn base_request(&self) -> RequestBuilder {
         let url_string = format!("{}{}", self.url, self.end_point);
 
-        let request = self
-            .client
+        self.client
             .request(self.method.clone().into(), url_string)
             .header("User-Agent", self.user_agent.clone())
-            .header("Content-Type", "application/json");
+            .header("Content-Type", "application/json")
+    }
+
+    fn page_len(&self) -> usize {
+        self.limit.unwrap_or(MAX_PAGE_LEN) as usize
+    }
+
+    /// Request a single page using the given cursors and return it along with the cursors for the
+    /// neighbouring pages.
+    async fn exec_page<O>(
+        &self,
+        request: &RequestBuilder,
+        after: Option<String>,
+        before: Option<String>,
+    ) -> Result<Page<O>, Error>
+    where
+        O: DeserializeOwned + Debug,
+    {
+        let mut request_clone = request.try_clone().ok_or(RequestBuilderCloningError)?;
 
-        let page_len = 1000;
+        let mut params = self.query_params.clone();
 
-        let mut after: Option<String> = None;
+        if let Some(limit) = self.limit {
+            params.push(("limit".to_string(), limit.to_string()));
+        }
+
+        if let Some(aft) = after {
+            params.push(("after".to_string(), aft));
+        }
+
+        if let Some(bef) = before {
+            params.push(("before".to_string(), bef));
+        }
+
+        request_clone = request_clone.query(&params);
+
+        if let Some(creds) = self.credentials.clone() {
+            request_clone = request_clone.sign_request(
+                creds,
+                self.timestamp().await?,
+                self.end_point.clone(),
+                Some(params),
+                self.method.clone().into(),
+                self.body.clone(),
+            );
+        }
+
+        let resp = request_clone
+            .try_clone()
+            .ok_or(RequestBuilderCloningError)?
+            .send()
+            .await?;
+
+        let after = resp
+            .headers()
+            .get("cb-after")
+            .and_then(|val| val.to_str().ok())
+            .map(|val| val.to_string());
+
+        let before = resp
+            .headers()
+            .get("cb-before")
+            .and_then(|val| val.to_str().ok())
+            .map(|val| val.to_string());
+
+        let body = Self::response_body(&self.end_point, resp).await?;
+
+        Ok(Page {
+            items: Self::parse_response::<Vec<O>>(body)?,
+            after,
+            before,
+        })
+    }
+
+    /// Request a single page through the rate limiter when one is set, retrying that page with
+    /// its own cursors according to the retry policy.
+    async fn exec_page_with_retry<O>(
+        &self,
+        request: &RequestBuilder,
+        after: Option<String>,
+        before: Option<String>,
+    ) -> Result<Page<O>, Error>
+    where
+        O: DeserializeOwned + Debug,
+    {
+        match &self.pool {
+            Some(pool) => {
+                pool.schedule_retryable_task(&self.retry_policy, self.is_idempotent(), || {
+                    self.exec_page::<O>(request, after.clone(), before.clone())
+                })
+                .await
+            }
+            None => self.exec_page::<O>(request, after, before).await,
+        }
+    }
+
+    /// Request every page and return the items of all of them.
+    ///
+    /// With a rate limiter set, a failed page is retried from its own cursor rather than from the
+    /// first page.
+    pub async fn exec_pagenated<O>(self) -> Result<Vec<O>, Error>
+    where
+        O: DeserializeOwned + Debug,
+    {
+        let request = self.base_request();
+        let page_len = self.page_len();
+
+        let mut after: Option<String> = self.after.clone();
         let mut ret_vec = Vec::new();
+        let mut pages = 0;
 
         // Loop until we don't get the max number of items
         loop {
-            let mut request_clone = request.try_clone().ok_or(RequestBuilderCloningError)?;
+            let mut page = self
+                .exec_page_with_retry::<O>(&request, after, None)
+                .await?;
+            let len = page.items.len();
 
-            let mut params = self.query_params.clone();
+            ret_vec.append(&mut page.items);
+            pages += 1;
+            after = page.after;
 
-            if let Some(aft) = after {
-                let mut aft_param = vec![("after".to_string(), aft)];
-                params.append(&mut aft_param);
+            if len != page_len || after.is_none() || self.max_pages.is_some_and(|max| pages >= max)
+            {
+                break;
             }
+        }
 
-            request_clone = request_clone.query(&params);
+        Ok(ret_vec)
+    }
+
+    /// Lazily request pages and yield each one as soon as it arrives.
+    ///
+    /// Unlike [Self::exec_pagenated] nothing is buffered: the next page is only requested once the
+    /// previous one has been consumed, so dropping the stream stops the walk.
+    /// Pages are walked towards older items using the `after` cursor unless only a `before`
+    /// cursor was supplied, in which case newer pages are walked using `before`.
+    /// The stream ends after the first error.
+    pub fn exec_paginated_stream<O>(mut self) -> impl Stream<Item = Result<Vec<O>, Error>>
+    where
+        O: DeserializeOwned + Debug + 'static,
+    {
+        // Always send a limit so a short page reliably marks the end of the data.
+        self.limit = Some(self.limit.unwrap_or(MAX_PAGE_LEN));
+
+        let walk_forward = self.before.is_some() && self.after.is_none();
+        let state = PageStreamState {
+            request: self.base_request(),
+            after: self.after.clone(),
+            before: self.before.clone(),
+            builder: self,
+            pages: 0,
+            done: false,
+        };
 
-            if let Some(creds) = self.credentials.clone() {
-                request_clone = request_clone.sign_request(
-                    creds,
-                    self.end_point.clone(),
-                    Some(params),
-                    self.method.clone().into(),
-                    self.body.clone(),
-                );
+        futures::stream::unfold(state, move |mut state| async move {
+            if state.done {
+                return None;
             }
 
-            let resp = request_clone
-                .try_clone()
-                .ok_or(RequestBuilderCloningError)?
-                .send()
-                .await?;
+            let (after, before) = if walk_forward {
+                (None, state.before.clone())
+            } else {
+                (state.after.clone(), None)
+            };
+
+            let page = state
+                .builder
+                .exec_page_with_retry::<O>(&state.request, after, before)
+                .await;
 
-            after = resp
-                .headers()
-                .get("cb-after")
-                .map(|val| val.clone().to_str().unwrap().to_string());
-
-            let body = resp.text().await?;
-            let serde_result: Result<Vec<O>, serde_json::Error> =
-                serde_json::from_str(body.as_str());
-            let mut parsed_response: Vec<O> = match serde_result {
-                Ok(target) => target,
-                Err(error) => {
-                    let serde_cberror_result: Result<CBProServerError, serde_json::Error> =
-                        serde_json::from_str(body.as_str());
-
-                    return if let Ok(return_struct) = serde_cberror_result {
-                        Err(CBProServerErrorVariant(return_struct))
-                    } else {
-                        Err(SerdeError {
-                            source: error,
-                            string: body,
-                        })
-                    };
+            let page = match page {
+                Ok(page) => page,
+                Err(err) => {
+                    state.done = true;
+                    return Some((Err(err), state));
                 }
             };
-            let len = parsed_response.len();
 
-            ret_vec.append(&mut parsed_response);
+            state.pages += 1;
+            let cursor = if walk_forward {
+                state.before = page.before;
+                &state.before
+            } else {
+                state.after = page.after;
+                &state.after
+            };
 
-            if len != page_len {
-                break;
-            }
-        }
+            state.done = page.items.len() != state.builder.page_len()
+                || cursor.is_none()
+                || state
+                    .builder
+                    .max_pages
+                    .is_some_and(|max| state.pages >= max);
 
-        Ok(ret_vec)
+            Some((Ok(page.items), state))
+        })
     }
 
     pub async fn exec<O>(self) -> Result<O, Error>
     where
         O: DeserializeOwned + Debug,
     {
+        let timestamp = self.timestamp().await?;
         let url_string = format!("{}{}", self.url, self.end_point);
 
         let mut request = self
@@ -236,6 +552,7 @@ impl CBRequestBuilder {
         if let Some(creds) = self.credentials.clone() {
             request = request.sign_request(
                 creds,
+                timestamp,
                 self.end_point.clone(),
                 Some(self.query_params.clone()),
                 self.method.clone().into(),
@@ -245,8 +562,73 @@ impl CBRequestBuilder {
 
         request.try_clone().unwrap().build().unwrap();
 
-        let response_body = request.send().await?.text().await?;
+        let response = request.send().await?;
+        let response_body = Self::response_body(&self.end_point, response).await?;
+
+        Self::parse_response::<O>(response_body)
+    }
+
+    /// Read the body of a response, turning a non-success status into the matching [Error].
+    async fn response_body(path: &str, response: Response) -> Result<String, Error> {
+        let status = response.status();
+
+        if status.is_success() {
+            return Ok(response.text().await?);
+        }
+
+        let headers = ERROR_HEADERS
+            .iter()
+            .filter_map(|name| {
+                response
+                    .headers()
+                    .get(*name)
+                    .and_then(|val| val.to_str().ok())
+                    .map(|val| (name.to_string(), val.to_string()))
+            })
+            .collect();
+
+        let body = response.text().await?;
+        let message = serde_json::from_str::<CBProServerError>(body.as_str())
+            .ok()
+            .map(|err| err.message);
+
+        Err(Error::from_http_response(HttpErrorData {
+            status: status.as_u16(),
+            path: path.to_string(),
+            headers,
+            message,
+            body,
+        }))
+    }
+
+    /// Unix timestamp used to sign this request, corrected by the server clock offset when one is
+    /// set.
+    async fn timestamp(&self) -> Result<i64, Error> {
+        match (&self.clock, &self.credentials) {
+            (Some(clock), Some(_)) => clock.timestamp(self).await,
+            _ => Ok(chrono::Utc::now().timestamp()),
+        }
+    }
+
+    /// Request the server time directly.
+    /// This bypasses [Self::exec] so that measuring the clock offset never needs a signature.
+    async fn fetch_server_time(&self) -> Result<ServerTime, Error> {
+        let end_point = "/time";
 
+        let response = self
+            .client
+            .request(Method::GET, format!("{}{}", self.url, end_point))
+            .header("User-Agent", self.user_agent.clone())
+            .send()
+            .await?;
+
+        Self::parse_response(Self::response_body(end_point, response).await?)
+    }
+
+    fn parse_response<O>(response_body: String) -> Result<O, Error>
+    where
+        O: DeserializeOwned,
+    {
         let serde_result: Result<O, serde_json::Error> =
             serde_json::from_str(response_body.as_str());
 
@@ -255,7 +637,7 @@ impl CBRequestBuilder {
         // target type.
         // This approach was chosen over the past untagged enum because it provides better
         // error messages
-        return match serde_result {
+        match serde_result {
             Ok(target) => Ok(target),
             Err(error) => {
                 let serde_cberror_result: Result<CBProServerError, serde_json::Error> =
@@ -270,14 +652,43 @@ impl CBRequestBuilder {
                     })
                 }
             }
-        };
+        }
     }
 }
 
+/// Encode query parameters into a query string.
+///
+/// This is the serializer reqwest's `.query()` uses so the result is byte for byte the query
+/// string that is sent.
+pub(crate) fn encode_query(query_params: &[(String, String)]) -> String {
+    serde_urlencoded::to_string(query_params).unwrap_or_default()
+}
+
+/// Base64 encoded `CB-ACCESS-SIGN` value for a request.
+///
+/// `request_path` is the endpoint including its encoded query string and `secret` is the base64
+/// encoded API secret. Returns [None] when the secret is not valid base64.
+pub(crate) fn signature(
+    secret: &str,
+    timestamp: i64,
+    method: &str,
+    request_path: &str,
+    body: &str,
+) -> Option<String> {
+    // Format message to be signed
+    let message = format!("{}{}{}{}", timestamp, method, request_path, body);
+
+    let mut mac = HmacSha256::new_from_slice(base64::decode(secret).ok()?.as_slice()).ok()?;
+    mac.update(message.as_bytes());
+
+    Some(base64::encode(mac.finalize().into_bytes()))
+}
+
 pub(crate) trait SignRequest {
     fn sign_request(
         self,
         credentials: APIKeyData,
+        timestamp: i64,
         path: String,
         query_params: Option<Vec<(String, String)>>,
         method: String,
@@ -289,37 +700,26 @@ impl SignRequest for RequestBuilder {
     fn sign_request(
         mut self,
         credentials: APIKeyData,
+        timestamp: i64,
         mut path: String,
         query_params: Option<Vec<(String, String)>>,
         method: String,
         body: String,
     ) -> Self {
-        let timestamp = chrono::Utc::now().timestamp();
-
-        // Append query params to the end of the path
-        if query_params.is_some() {
-            path = format!("{}?", path);
-            for query in query_params.unwrap().iter() {
-                path = format!("{}{}={}&", path, query.0, query.1);
+        // Append query params to the end of the path using the same encoding reqwest uses to send
+        // them, otherwise the signature covers different bytes than the server receives.
+        if let Some(query) = query_params.map(|params| encode_query(&params)) {
+            if !query.is_empty() {
+                path = format!("{}?{}", path, query);
             }
-            // Remove last character so that queries do not end with an &
-            path.pop();
         }
 
-        // Format message to be signed
-        let message = format!("{}{}{}{}", timestamp, method, path, body);
-
         // Sign message
-        if let Ok(mut mac) =
-            HmacSha256::new_from_slice(base64::decode(credentials.secret).unwrap().as_slice())
-        {
-            mac.update(message.as_bytes());
-            let signature = mac.finalize().into_bytes();
-
+        if let Some(signature) = signature(&credentials.secret, timestamp, &method, &path, &body) {
             // Build request
             self = self
                 .header("CB-ACCESS-KEY", credentials.key)
-                .header("CB-ACCESS-SIGN", base64::encode(signature))
+                .header("CB-ACCESS-SIGN", signature)
                 .header("CB-ACCESS-TIMESTAMP", timestamp)
                 .header("CB-ACCESS-PASSPHRASE", credentials.passphrase);
         }
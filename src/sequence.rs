@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
+use crate::datastructs::websocket::WebsocketMessage;
+
+/// Outstanding gaps remembered per product, older ones are forgotten first.
+const MAX_OUTSTANDING_GAPS: usize = 64;
+
+/// Sequence numbers are shared by every message of a product, each feed is tracked separately.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SequenceFeed {
+    /// `received`, `open`, `done`, `match` and `change` messages, which number every message of a
+    /// product without skipping.
+    Full,
+    /// `ticker` messages. Tickers skip the sequence numbers of the full channel messages sent
+    /// between trades, so only duplicates and out of order tickers are reported.
+    Ticker,
+}
+
+/// Sequence numbers that were skipped for a product.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SequenceGap {
+    pub product_id: String,
+    pub feed: SequenceFeed,
+    pub first_missing: u64,
+    pub last_missing: u64,
+}
+
+impl SequenceGap {
+    pub fn missing(&self) -> RangeInclusive<u64> {
+        self.first_missing..=self.last_missing
+    }
+
+    /// Number of messages missing.
+    pub fn missing_count(&self) -> u64 {
+        self.last_missing - self.first_missing + 1
+    }
+}
+
+/// Outcome of checking a message's sequence number.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SequenceEvent {
+    /// The message follows the previous one, or is the first one seen for its product.
+    InOrder,
+    /// Messages were skipped, anything built from the feed should resync.
+    Gap(SequenceGap),
+    /// The sequence number was already seen.
+    Duplicate { product_id: String, sequence: u64 },
+    /// A message older than the latest one, for the full feed this fills part of an earlier gap.
+    OutOfOrder {
+        product_id: String,
+        sequence: u64,
+        latest: u64,
+    },
+}
+
+#[derive(Default)]
+struct ProductSequence {
+    latest: u64,
+    /// Gaps of the full feed not yet filled by a late message, oldest first
+    outstanding: Vec<RangeInclusive<u64>>,
+}
+
+impl ProductSequence {
+    /// Removes `sequence` from the outstanding gaps, false when it was not missing.
+    fn fill(&mut self, sequence: u64) -> bool {
+        let index = match self
+            .outstanding
+            .iter()
+            .position(|gap| gap.contains(&sequence))
+        {
+            None => return false,
+            Some(index) => index,
+        };
+
+        let gap = self.outstanding.remove(index);
+        let (start, end) = (*gap.start(), *gap.end());
+        if sequence < end {
+            self.outstanding.insert(index, sequence + 1..=end);
+        }
+        if start < sequence {
+            self.outstanding.insert(index, start..=sequence - 1);
+        }
+        true
+    }
+}
+
+/// # Sequence Tracker
+/// Checks the per product `sequence` of full channel and ticker messages and reports gaps,
+/// duplicates and out of order messages.
+///
+/// Gaps are reported when the message after them arrives. A gap is the signal to resync anything
+/// built from the feed, for example from `get_product_book`, and to call
+/// [SequenceTracker::resync] with the sequence of the fetched book.
+///
+/// `match` messages are checked as part of the full feed, a subscription to the matches channel
+/// alone therefore reports the messages between two matches as gaps.
+#[derive(Default)]
+pub struct SequenceTracker {
+    products: HashMap<(SequenceFeed, String), ProductSequence>,
+}
+
+impl SequenceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks the sequence of a websocket message.
+    /// None for messages that are neither full channel nor ticker messages.
+    pub fn check_message(&mut self, message: &WebsocketMessage) -> Option<SequenceEvent> {
+        let feed = match message {
+            WebsocketMessage::Received(_)
+            | WebsocketMessage::Open(_)
+            | WebsocketMessage::Done(_)
+            | WebsocketMessage::Match(_)
+            | WebsocketMessage::Change(_) => SequenceFeed::Full,
+            WebsocketMessage::Ticker(_) => SequenceFeed::Ticker,
+            _ => return None,
+        };
+
+        let product_id = message.product_id()?;
+        let sequence = message.sequence()?;
+        Some(self.check(feed, product_id, sequence))
+    }
+
+    /// Records `sequence` as received for the product and reports how it relates to the ones
+    /// before it.
+    pub fn check(&mut self, feed: SequenceFeed, product_id: &str, sequence: u64) -> SequenceEvent {
+        let product = match self.products.get_mut(&(feed, product_id.to_string())) {
+            Some(product) => product,
+            None => {
+                self.resync(feed, product_id, sequence);
+                return SequenceEvent::InOrder;
+            }
+        };
+
+        if sequence == product.latest {
+            return SequenceEvent::Duplicate {
+                product_id: product_id.to_string(),
+                sequence,
+            };
+        }
+
+        if sequence < product.latest {
+            if feed == SequenceFeed::Full && !product.fill(sequence) {
+                return SequenceEvent::Duplicate {
+                    product_id: product_id.to_string(),
+                    sequence,
+                };
+            }
+
+            return SequenceEvent::OutOfOrder {
+                product_id: product_id.to_string(),
+                sequence,
+                latest: product.latest,
+            };
+        }
+
+        let previous = product.latest;
+        product.latest = sequence;
+
+        if feed == SequenceFeed::Ticker || sequence == previous + 1 {
+            return SequenceEvent::InOrder;
+        }
+
+        if product.outstanding.len() == MAX_OUTSTANDING_GAPS {
+            product.outstanding.remove(0);
+        }
+        product.outstanding.push(previous + 1..=sequence - 1);
+
+        SequenceEvent::Gap(SequenceGap {
+            product_id: product_id.to_string(),
+            feed,
+            first_missing: previous + 1,
+            last_missing: sequence - 1,
+        })
+    }
+
+    /// Treats `sequence` as the latest one received for the product, forgetting earlier gaps.
+    pub fn resync(&mut self, feed: SequenceFeed, product_id: &str, sequence: u64) {
+        self.products.insert(
+            (feed, product_id.to_string()),
+            ProductSequence {
+                latest: sequence,
+                outstanding: Vec::new(),
+            },
+        );
+    }
+
+    /// Latest sequence received for the product.
+    pub fn latest(&self, feed: SequenceFeed, product_id: &str) -> Option<u64> {
+        self.products
+            .get(&(feed, product_id.to_string()))
+            .map(|product| product.latest)
+    }
+
+    /// Gaps of the full feed that no late message has filled yet, oldest first.
+    pub fn outstanding_gaps(&self, product_id: &str) -> Vec<RangeInclusive<u64>> {
+        self.products
+            .get(&(SequenceFeed::Full, product_id.to_string()))
+            .map(|product| product.outstanding.clone())
+            .unwrap_or_default()
+    }
+
+    /// Forgets every product, the next message of each is treated as the first.
+    pub fn reset(&mut self) {
+        self.products.clear();
+    }
+}
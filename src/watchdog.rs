@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::sync::{
+    Arc,
+    Mutex as StdMutex,
+};
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+use log::{
+    debug,
+    error,
+};
+use tokio::sync::mpsc::{
+    unbounded_channel,
+    UnboundedReceiver,
+    UnboundedSender,
+};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::api::{
+    CBProAPI,
+    SubscriptionBuilder,
+};
+use crate::datastructs::websocket::HeartbeatMessage;
+use crate::dispatcher::{
+    DispatchReceiver,
+    MessageDispatcher,
+    Reconnected,
+};
+use crate::errors::WebsocketError;
+
+/// How long a product may go without a heartbeat and what to do when it does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchdogConfig {
+    /// Coinbase sends a heartbeat every second, a product silent for longer than this is stale.
+    pub timeout: Duration,
+    /// Reconnect the websocket when a product goes stale, replaying every subscription.
+    pub reconnect: bool,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig {
+            timeout: Duration::from_secs(5),
+            reconnect: false,
+        }
+    }
+}
+
+/// The last heartbeat received for a product.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeartbeatStatus {
+    pub sequence: u64,
+    pub last_trade_id: u64,
+    /// Server time of the heartbeat
+    pub time: NaiveDateTime,
+    /// When the heartbeat was received
+    pub received: Instant,
+}
+
+/// Changes in the health of the watched feed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FeedEvent {
+    /// No heartbeat arrived for the product within the timeout.
+    Stale {
+        product_id: String,
+        last_heartbeat: Option<HeartbeatStatus>,
+        silent_for: Duration,
+    },
+    /// A heartbeat arrived for a product that was stale.
+    Recovered { product_id: String },
+    /// The websocket was reopened after a product went stale.
+    Reconnected,
+    /// Reopening the websocket failed, the message of the last connection error is included.
+    ReconnectFailed { error: String },
+}
+
+struct WatchedProduct {
+    last_heartbeat: Option<HeartbeatStatus>,
+    /// Last heartbeat, or when watching started or the websocket was last reopened
+    last_seen: Instant,
+    /// No heartbeat since the product was reported stale
+    stale: bool,
+    /// The timeout is running, cleared once it is reported until a heartbeat or reconnect
+    armed: bool,
+}
+
+impl WatchedProduct {
+    fn new() -> Self {
+        WatchedProduct {
+            last_heartbeat: None,
+            last_seen: Instant::now(),
+            stale: false,
+            armed: true,
+        }
+    }
+}
+
+/// # Heartbeat Watchdog
+/// Watches the heartbeats of a set of products and reports a [FeedEvent::Stale] when one of them
+/// goes quiet for longer than the configured timeout, optionally reconnecting the websocket.
+///
+/// Heartbeats are received through a [MessageDispatcher], the watchdog subscribes to the
+/// heartbeat channel for its products when it starts. Dropping the watchdog stops it.
+pub struct HeartbeatWatchdog {
+    products: Arc<StdMutex<HashMap<String, WatchedProduct>>>,
+    events: UnboundedReceiver<FeedEvent>,
+    task: JoinHandle<()>,
+}
+
+impl HeartbeatWatchdog {
+    /// Subscribes to the heartbeat channel for `product_ids` and starts watching them.
+    pub async fn start(
+        api: &CBProAPI,
+        dispatcher: &MessageDispatcher,
+        product_ids: Vec<String>,
+        config: WatchdogConfig,
+    ) -> Result<Self, WebsocketError> {
+        let heartbeats = dispatcher.products_receiver::<HeartbeatMessage>(product_ids.clone());
+        let reconnects = dispatcher.receiver::<Reconnected>();
+
+        api.send_subscribe(
+            SubscriptionBuilder::new()
+                .subscribe_to_heartbeat_vec(&mut product_ids.clone())
+                .build(),
+        )
+        .await?;
+
+        let products = Arc::new(StdMutex::new(
+            product_ids
+                .into_iter()
+                .map(|product_id| (product_id, WatchedProduct::new()))
+                .collect::<HashMap<_, _>>(),
+        ));
+        let (sender, events) = unbounded_channel();
+
+        let task = tokio::spawn(Self::watch(
+            api.clone(),
+            config,
+            products.clone(),
+            heartbeats,
+            reconnects,
+            sender,
+        ));
+
+        Ok(HeartbeatWatchdog {
+            products,
+            events,
+            task,
+        })
+    }
+
+    /// The next change in the health of the feed.
+    pub async fn recv(&mut self) -> Option<FeedEvent> {
+        self.events.recv().await
+    }
+
+    /// The last heartbeat received for the product, None when there was none yet or the product
+    /// is not watched.
+    pub fn last_heartbeat(&self, product_id: &str) -> Option<HeartbeatStatus> {
+        self.products
+            .lock()
+            .unwrap()
+            .get(product_id)
+            .and_then(|product| product.last_heartbeat.clone())
+    }
+
+    /// Products currently without a heartbeat within the timeout.
+    pub fn stale_products(&self) -> Vec<String> {
+        self.products
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, product)| product.stale)
+            .map(|(product_id, _)| product_id.clone())
+            .collect()
+    }
+
+    async fn watch(
+        api: CBProAPI,
+        config: WatchdogConfig,
+        products: Arc<StdMutex<HashMap<String, WatchedProduct>>>,
+        mut heartbeats: DispatchReceiver<HeartbeatMessage>,
+        mut reconnects: DispatchReceiver<Reconnected>,
+        events: UnboundedSender<FeedEvent>,
+    ) {
+        let mut dispatching = true;
+
+        loop {
+            let deadline = products
+                .lock()
+                .unwrap()
+                .values()
+                .filter(|product| product.armed)
+                .map(|product| product.last_seen + config.timeout)
+                .min();
+
+            tokio::select! {
+                heartbeat = heartbeats.recv(), if dispatching => match heartbeat {
+                    Some(heartbeat) => Self::record(&products, heartbeat, &events),
+                    None => dispatching = false,
+                },
+                reconnected = reconnects.recv(), if dispatching => match reconnected {
+                    Some(Reconnected) => Self::restart_timers(&products),
+                    None => dispatching = false,
+                },
+                _ = Self::sleep_until(deadline) => {
+                    if Self::mark_stale(&products, config.timeout, &events) && config.reconnect {
+                        Self::reconnect(&api, &products, &events).await;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sleeps until the deadline, forever when there is none.
+    async fn sleep_until(deadline: Option<Instant>) {
+        match deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline).await,
+            None => futures::future::pending().await,
+        }
+    }
+
+    fn record(
+        products: &StdMutex<HashMap<String, WatchedProduct>>,
+        heartbeat: HeartbeatMessage,
+        events: &UnboundedSender<FeedEvent>,
+    ) {
+        let mut products = products.lock().unwrap();
+        let product = match products.get_mut(&heartbeat.product_id) {
+            None => return,
+            Some(product) => product,
+        };
+
+        let received = Instant::now();
+        product.last_seen = received;
+        product.armed = true;
+        product.last_heartbeat = Some(HeartbeatStatus {
+            sequence: heartbeat.sequence,
+            last_trade_id: heartbeat.last_trade_id,
+            time: heartbeat.time,
+            received,
+        });
+
+        if product.stale {
+            product.stale = false;
+            let _ = events.send(FeedEvent::Recovered {
+                product_id: heartbeat.product_id,
+            });
+        }
+    }
+
+    /// Reports every product past the timeout, true when any went stale.
+    fn mark_stale(
+        products: &StdMutex<HashMap<String, WatchedProduct>>,
+        timeout: Duration,
+        events: &UnboundedSender<FeedEvent>,
+    ) -> bool {
+        let mut went_stale = false;
+
+        for (product_id, product) in products.lock().unwrap().iter_mut() {
+            let silent_for = product.last_seen.elapsed();
+            if !product.armed || silent_for < timeout {
+                continue;
+            }
+
+            debug!("No heartbeat for {} in {:?}", product_id, silent_for);
+            product.armed = false;
+            product.stale = true;
+            went_stale = true;
+            let _ = events.send(FeedEvent::Stale {
+                product_id: product_id.clone(),
+                last_heartbeat: product.last_heartbeat.clone(),
+                silent_for,
+            });
+        }
+
+        went_stale
+    }
+
+    async fn reconnect(
+        api: &CBProAPI,
+        products: &StdMutex<HashMap<String, WatchedProduct>>,
+        events: &UnboundedSender<FeedEvent>,
+    ) {
+        let event = match api.reconnect_websocket().await {
+            Ok(()) => FeedEvent::Reconnected,
+            Err(err) => {
+                error!("Watchdog failed to reconnect the websocket: {}", err);
+                FeedEvent::ReconnectFailed {
+                    error: err.to_string(),
+                }
+            }
+        };
+
+        Self::restart_timers(products);
+        let _ = events.send(event);
+    }
+
+    /// Gives every product a full timeout to send a heartbeat on a new connection.
+    fn restart_timers(products: &StdMutex<HashMap<String, WatchedProduct>>) {
+        let now = Instant::now();
+        for product in products.lock().unwrap().values_mut() {
+            product.last_seen = now;
+            product.armed = true;
+        }
+    }
+}
+
+impl Drop for HeartbeatWatchdog {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
@@ -51,12 +51,73 @@ use crate::errors::{
     WebsocketError,
 };
 
+pub(crate) const OP_CONTINUATION: u8 = 0x0;
+pub(crate) const OP_TEXT: u8 = 0x1;
+pub(crate) const OP_CLOSE: u8 = 0x8;
+pub(crate) const OP_PING: u8 = 0x9;
+pub(crate) const OP_PONG: u8 = 0xA;
+
+/// Status code sent when we initiate a close, RFC 6455 section 7.4.1.
+const CLOSE_NORMAL: u16 = 1000;
+
 pub struct ParsedFrame {
-    _final_flag: bool,
+    pub(crate) final_flag: bool,
+    pub(crate) op_code: u8,
     _mask: Option<[u8; 4]>,
     pub(crate) payload: Vec<u8>,
 }
 
+/// Control frames surfaced to the reader.
+///
+/// `WebsocketStream` answers pings and close frames itself, then hands them to the caller as the
+/// inner value of an `io::Error` so `read_to_end` stops at the frame boundary.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlFrame {
+    Ping(Vec<u8>),
+    Close { code: Option<u16>, reason: String },
+}
+
+impl ControlFrame {
+    fn close_from_payload(payload: &[u8]) -> Self {
+        if payload.len() < 2 {
+            return ControlFrame::Close {
+                code: None,
+                reason: String::new(),
+            };
+        }
+
+        ControlFrame::Close {
+            code: Some(u16::from_be_bytes([payload[0], payload[1]])),
+            reason: String::from_utf8_lossy(&payload[2..]).to_string(),
+        }
+    }
+}
+
+impl Display for ControlFrame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ControlFrame::Ping(payload) => write!(f, "Ping with {} byte payload", payload.len()),
+            ControlFrame::Close { code, reason } => {
+                write!(f, "Close with status {:?}: {}", code, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ControlFrame {}
+
+enum Incoming {
+    Data(VecDeque<u8>),
+    Control(ControlFrame),
+}
+
+#[derive(PartialEq)]
+enum CloseState {
+    Open,
+    CloseSent,
+    Closed,
+}
+
 pub enum ParserState {
     FinalAndOpcode,
     MaskAndLength,
@@ -101,7 +162,7 @@ impl From<u64> for FrameLength {
         if len < 126 {
             Self::Short([len as u8])
         } else if len <= u16::MAX as u64 {
-            let bytes = len.to_be_bytes();
+            let bytes = (len as u16).to_be_bytes();
             Self::Medium([126, bytes[0], bytes[1]])
         } else {
             let bytes = len.to_be_bytes();
@@ -125,14 +186,14 @@ impl Frame {
     }
 }
 
-impl From<&[u8]> for Frame {
-    fn from(payload: &[u8]) -> Self {
+impl Frame {
+    /// Builds a masked, final frame with the given opcode.
+    pub fn new(op_code: u8, payload: &[u8]) -> Self {
         let mut len = FrameLength::from(payload.len() as u64).to_vec();
         len[0] += 0b10000000;
         let mut data: Vec<u8> = Vec::with_capacity(5 + len.len() + payload.len());
 
-        // Final frame and opcode 1
-        data.push(0b10000001);
+        data.push(0b10000000 | (op_code & 0b00001111));
         data.append(&mut len);
 
         let mut mask = [0u8; 4];
@@ -145,6 +206,12 @@ impl From<&[u8]> for Frame {
     }
 }
 
+impl From<&[u8]> for Frame {
+    fn from(payload: &[u8]) -> Self {
+        Frame::new(OP_TEXT, payload)
+    }
+}
+
 pub struct FrameParser {
     current_state: ParserState,
     final_frame: bool,
@@ -171,17 +238,20 @@ impl FrameParser {
     pub fn take_frame_then_reset(&mut self) -> Result<ParsedFrame, WebsocketError> {
         let mut payload = Vec::new();
         mem::swap(&mut payload, &mut self.payload);
+        let frame = ParsedFrame {
+            final_flag: self.final_frame,
+            op_code: self.op_code,
+            _mask: self.mask,
+            payload,
+        };
+
         self.current_state = ParserState::FinalAndOpcode;
         self.final_frame = false;
         self.op_code = 0;
         self.mask = None;
         self.length = 0;
 
-        Ok(ParsedFrame {
-            _final_flag: self.final_frame,
-            _mask: self.mask,
-            payload,
-        })
+        Ok(frame)
     }
 
     pub fn process_byte(&mut self, byte: &u8) -> Result<Option<ParsedFrame>, WebsocketError> {
@@ -410,6 +480,8 @@ impl Default for WebsocketStreamConnector {
 }
 
 impl WebsocketStreamConnector {
+    /// Skips checking the server's `Sec-WebSocket-Accept`, for the mock streams.
+    #[cfg(feature = "mock")]
     pub(crate) fn new_no_sec() -> Self {
         Self {
             check_sec_accept: false,
@@ -424,10 +496,12 @@ impl WebsocketStreamConnector {
     where
         T: AsyncIO,
     {
-        let domain = url.domain().ok_or(NoDomainError {
+        let domain = url.host_str().ok_or(NoDomainError {
+            url: url.to_string(),
+        })?;
+        let port = url.port_or_known_default().ok_or(NoDomainError {
             url: url.to_string(),
         })?;
-        let port = url.socket_addrs(|| None).unwrap()[0].port();
 
         // let port_str = match port {
         //     None => {"".to_string()}
@@ -486,6 +560,9 @@ impl WebsocketStreamConnector {
             stream,
             read_parser: Arc::new(Mutex::new(FrameParser::default())),
             frame_buffer: Arc::new(Mutex::new(VecDeque::new())),
+            pending_writes: Vec::new(),
+            close_state: CloseState::Open,
+            fragments: None,
         })
     }
 
@@ -530,15 +607,18 @@ where
 {
     stream: T,
     read_parser: Arc<Mutex<FrameParser>>,
-    frame_buffer: Arc<Mutex<VecDeque<VecDeque<u8>>>>,
+    frame_buffer: Arc<Mutex<VecDeque<Incoming>>>,
+    pending_writes: Vec<u8>,
+    close_state: CloseState,
+    /// Payload of a fragmented message still waiting on its final continuation frame.
+    fragments: Option<Vec<u8>>,
 }
 
 impl<T> WebsocketStream<T>
 where
     T: AsyncIO,
 {
-    #[allow(unused)]
-    fn take_buffer_lock(&mut self) -> Result<MutexGuard<VecDeque<VecDeque<u8>>>, std::io::Error> {
+    fn take_buffer_lock(&mut self) -> Result<MutexGuard<'_, VecDeque<Incoming>>, std::io::Error> {
         match self.frame_buffer.lock() {
             Ok(lock) => Ok(lock),
             Err(_) => Err(io::Error::new(
@@ -547,6 +627,128 @@ where
             )),
         }
     }
+
+    /// Queues a whole frame so it is never interleaved with another on the wire.
+    fn queue_frame(&mut self, frame: Frame) {
+        self.pending_writes.extend(frame.data);
+    }
+
+    /// Writes queued frames to the underlying stream, ready once nothing is left to send.
+    fn poll_write_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.pending_writes.is_empty() {
+            match Pin::new(&mut self.stream).poll_write(cx, &self.pending_writes) {
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(io::Error::from(ErrorKind::WriteZero)))
+                }
+                Poll::Ready(Ok(len)) => {
+                    self.pending_writes.drain(..len);
+                }
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    /// Reads once from the underlying stream and buffers every frame parsed.
+    /// Resolves to false once the underlying stream reaches EOF.
+    fn poll_fill_buffer(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<bool>> {
+        let mut arr = [0u8; 4096];
+        let mut internal_buffer = ReadBuf::new(arr.as_mut_slice());
+
+        match Pin::new(&mut self.stream).poll_read(cx, &mut internal_buffer) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Pending => return Poll::Pending,
+        }
+
+        if internal_buffer.filled().is_empty() {
+            return Poll::Ready(Ok(false));
+        }
+
+        let mut frames = Vec::new();
+        {
+            let mut lock = ok_or_return_poll_poison!(self.read_parser.lock());
+
+            for byte in internal_buffer.filled() {
+                match lock.process_byte(byte) {
+                    Ok(Some(frame)) => frames.push(frame),
+                    Ok(None) => {}
+                    Err(_) => {
+                        return Poll::Ready(Err(io::Error::from(ErrorKind::Other)));
+                    }
+                }
+            }
+        }
+
+        for frame in frames {
+            self.handle_frame(frame)?;
+        }
+
+        Poll::Ready(Ok(true))
+    }
+
+    /// Answers control frames and buffers everything the reader should see.
+    fn handle_frame(&mut self, frame: ParsedFrame) -> io::Result<()> {
+        if self.close_state == CloseState::Closed {
+            return Ok(());
+        }
+
+        let incoming = match frame.op_code {
+            OP_PING => {
+                self.queue_frame(Frame::new(OP_PONG, &frame.payload));
+                Incoming::Control(ControlFrame::Ping(frame.payload))
+            }
+            OP_PONG => return Ok(()),
+            OP_CLOSE => {
+                if self.close_state == CloseState::Open {
+                    // Echo the status code back to complete the close handshake.
+                    let code = if frame.payload.len() >= 2 {
+                        &frame.payload[..2]
+                    } else {
+                        &[]
+                    };
+                    self.queue_frame(Frame::new(OP_CLOSE, code));
+                }
+                self.close_state = CloseState::Closed;
+                Incoming::Control(ControlFrame::close_from_payload(&frame.payload))
+            }
+            _ => match self.reassemble(frame)? {
+                None => return Ok(()),
+                Some(payload) => Incoming::Data(VecDeque::from(payload)),
+            },
+        };
+
+        self.take_buffer_lock()?.push_back(incoming);
+        Ok(())
+    }
+
+    /// Joins continuation frames onto the frame that started the message.
+    /// Returns the payload once the final frame of the message arrives.
+    fn reassemble(&mut self, frame: ParsedFrame) -> io::Result<Option<Vec<u8>>> {
+        let payload = match (frame.op_code, self.fragments.take()) {
+            (OP_CONTINUATION, Some(mut payload)) => {
+                payload.extend(frame.payload);
+                payload
+            }
+            (_, Some(_)) => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "New message started before the previous fragmented message finished",
+                ));
+            }
+            // A continuation with nothing to continue is handed up as a message of its own.
+            (_, None) => frame.payload,
+        };
+
+        if frame.final_flag {
+            return Ok(Some(payload));
+        }
+
+        self.fragments = Some(payload);
+        Ok(None)
+    }
 }
 
 impl<T> AsyncRead for WebsocketStream<T>
@@ -558,51 +760,45 @@ where
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<std::io::Result<()>> {
+        let this = &mut *self;
+
         // Poll read until a frame is parsed.
         loop {
+            // Pongs and close replies go out as soon as the frame that caused them is read.
+            if let Poll::Ready(Err(err)) = this.poll_write_pending(cx) {
+                return Poll::Ready(Err(err));
+            }
+
             {
-                let frame_buffer = ok_or_return_poll_poison!(self.frame_buffer.lock());
+                let frame_buffer = ok_or_return_poll_poison!(this.frame_buffer.lock());
 
                 // If we have a frame to give, break and give it to the reader.
-                if frame_buffer.len() > 0 {
+                if !frame_buffer.is_empty() {
                     break;
                 }
             }
 
-            let mut arr = [0u8; 4096];
-            let mut internal_buffer = ReadBuf::new(arr.as_mut_slice());
-
-            let stream_read_result = Pin::new(&mut self.stream).poll_read(cx, &mut internal_buffer);
-
-            if stream_read_result.is_pending() {
-                return stream_read_result;
+            if this.close_state == CloseState::Closed {
+                return Poll::Ready(Ok(()));
             }
 
-            let mut lock = ok_or_return_poll_poison!(self.read_parser.lock());
-
-            for byte in internal_buffer.filled() {
-                let maybe_frame = match lock.process_byte(byte) {
-                    Ok(maybe_frame) => maybe_frame,
-                    Err(_) => {
-                        return Poll::Ready(Err(io::Error::from(ErrorKind::Other)));
-                    }
-                };
-
-                if let Some(frame) = maybe_frame {
-                    let mut frame_buffer = ok_or_return_poll_poison!(self.frame_buffer.lock());
-                    let queue = VecDeque::from(frame.payload);
-                    frame_buffer.push_back(queue);
-                }
+            match this.poll_fill_buffer(cx) {
+                Poll::Ready(Ok(true)) => {}
+                Poll::Ready(Ok(false)) => return Poll::Ready(Ok(())),
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
             }
         }
 
-        let mut frame_buffer = ok_or_return_poll_poison!(self.frame_buffer.lock());
+        let mut frame_buffer = ok_or_return_poll_poison!(this.frame_buffer.lock());
 
-        if frame_buffer.len() == 0 {
-            return Poll::Ready(Ok(()));
-        }
-
-        let mut payload = frame_buffer.pop_front().unwrap();
+        let mut payload = match frame_buffer.pop_front() {
+            None => return Poll::Ready(Ok(())),
+            Some(Incoming::Control(control)) => {
+                return Poll::Ready(Err(io::Error::new(ErrorKind::Other, control)));
+            }
+            Some(Incoming::Data(payload)) => payload,
+        };
 
         let range = min(payload.len(), buf.remaining());
         let mut new_slice = Vec::new();
@@ -627,7 +823,7 @@ where
         buf.put_slice(new_slice.as_mut_slice());
 
         if bytes_written != 0 {
-            frame_buffer.push_front(payload);
+            frame_buffer.push_front(Incoming::Data(payload));
         }
 
         Poll::Ready(Ok(()))
@@ -643,44 +839,64 @@ where
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<Result<usize, Error>> {
-        let frame = Frame::from(buf).data;
-        let header_size = frame.len() - buf.len();
+        let this = &mut *self;
 
-        let mut parser = FrameParser::default();
-        let mut parsed_frame: ParsedFrame = ParsedFrame {
-            _final_flag: false,
-            _mask: None,
-            payload: vec![],
-        };
-        for byte in &frame {
-            if let Ok(Some(frame)) = parser.process_byte(&byte) {
-                parsed_frame = frame;
-            }
+        match this.poll_write_pending(cx) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Pending => return Poll::Pending,
         }
 
-        //println!("{}", String::from_utf8_lossy(&parsed_frame.payload));
+        if this.close_state != CloseState::Open {
+            return Poll::Ready(Err(io::Error::new(
+                ErrorKind::NotConnected,
+                "Websocket is closing",
+            )));
+        }
 
-        match Pin::new(&mut (self.stream)).poll_write(cx, &frame) {
-            Poll::Ready(res) => match res {
-                Ok(len) => {
-                    if len <= header_size {
-                        Poll::Ready(Ok(0))
-                    } else {
-                        Poll::Ready(Ok(len - header_size))
-                    }
-                }
-                Err(err) => Poll::Ready(Err(err)),
-            },
-            Poll::Pending => Poll::Pending,
+        this.queue_frame(Frame::from(buf));
+
+        // The frame is queued either way, anything left is sent on the next read, write or flush.
+        match this.poll_write_pending(cx) {
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            _ => Poll::Ready(Ok(buf.len())),
         }
     }
 
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        match self.poll_write_pending(cx) {
+            Poll::Ready(Ok(())) => {}
+            other => return other,
+        }
+
         Pin::new(&mut (self.stream)).poll_flush(cx)
     }
 
+    /// Sends a normal closure frame and waits for the server to answer before shutting down the
+    /// underlying stream.
     fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
-        Pin::new(&mut (self.stream)).poll_shutdown(cx)
+        let this = &mut *self;
+
+        if this.close_state == CloseState::Open {
+            this.queue_frame(Frame::new(OP_CLOSE, &CLOSE_NORMAL.to_be_bytes()));
+            this.close_state = CloseState::CloseSent;
+        }
+
+        match this.poll_write_pending(cx) {
+            Poll::Ready(Ok(())) => {}
+            other => return other,
+        }
+
+        while this.close_state == CloseState::CloseSent {
+            match this.poll_fill_buffer(cx) {
+                Poll::Ready(Ok(true)) => {}
+                Poll::Ready(Ok(false)) => this.close_state = CloseState::Closed,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        Pin::new(&mut this.stream).poll_shutdown(cx)
     }
 }
 